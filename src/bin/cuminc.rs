@@ -1,4 +1,6 @@
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::{remove_file, write};
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 extern crate anyhow;
@@ -10,19 +12,79 @@ use structopt::StructOpt;
 extern crate serde_json;
 extern crate serde_yaml;
 
-use cumin::eval::eval;
-use cumin::parser::cumin::cumin;
+use cumin::datetime::parse_datetime;
+use cumin::eval::{eval_cumin, DecimalRounding, Environ, FloatOverflow, MAX_DECIMAL_SCALE};
+use cumin::golden::{self, Case, Expect};
+use cumin::json::{DecimalOutput, Output, JSON};
+use cumin::parser::cumin::{cumin, parse_error, Cumin};
+use cumin::parser::statement::Statement;
+use cumin::units::{UnitOutput, BYTES_UNITS, DURATION_UNITS};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
     #[structopt(short = "T", long = "type", default_value = "json")]
     output_type: String,
 
+    /// Float overflow policy: error or saturate
+    #[structopt(long = "float-overflow", default_value = "error")]
+    float_overflow: FloatOverflow,
+
+    /// Rounding of Decimal quotients: half-even, half-up, half-down, up, down, ceiling or floor
+    #[structopt(long = "decimal-rounding", default_value = "half-even")]
+    decimal_rounding: DecimalRounding,
+
+    /// Decimal places kept by Decimal quotients (at most 28)
+    #[structopt(long = "decimal-scale", default_value = "28")]
+    decimal_scale: u32,
+
+    /// Emit Decimal values as JSON numbers or strings: number or string
+    #[structopt(long = "decimal-output", default_value = "number")]
+    decimal_output: DecimalOutput,
+
+    /// Emit Duration values as human strings (1h30m) or numbers in a unit: human, h, m, s, ms, us, ns
+    #[structopt(long = "duration-output", default_value = "human")]
+    duration_output: String,
+
+    /// Emit Bytes values as human strings (512KiB) or numbers in a unit: human, B, KB, KiB, MB, MiB, ...
+    #[structopt(long = "bytes-output", default_value = "human")]
+    bytes_output: String,
+
+    /// Reference time returned by now(), in RFC 3339 (e.g. 2024-03-01T09:00:00Z)
+    #[structopt(long = "now")]
+    now: Option<String>,
+
+    /// Warn when a key of `{{ ..a, ..b }}` is overwritten by a later one
+    #[structopt(long = "warn-overwrite")]
+    warn_overwrite: bool,
+
     #[structopt(name = "INPUT", default_value = "-")]
     input_cumin: String,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn cat(file_name: &String) -> String {
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Run the `test` blocks of cumin files and of the modules they use
+    Test {
+        /// Files, or directories searched for files declaring tests
+        #[structopt(name = "PATHS", default_value = ".")]
+        paths: Vec<String>,
+    },
+    /// Compile each `x.cumin` of a directory and compare it with `x.json`, or expect it to fail if `x.fail` exists;
+    /// `x.env` has its environ variables, a `KEY=VALUE` a line
+    Golden {
+        #[structopt(name = "DIR")]
+        dir: String,
+
+        /// Rewrite the expectations of the cases which differ to what they do now
+        #[structopt(long = "bless")]
+        bless: bool,
+    },
+}
+
+fn cat(file_name: &str) -> Result<String> {
     use std::fs::File;
     use std::io::BufReader;
     use std::io::{self, Read};
@@ -31,19 +93,279 @@ fn cat(file_name: &String) -> String {
     if file_name == "-" {
         let stdin = io::stdin();
         let mut handle = stdin.lock();
-        handle.read_to_string(&mut content).unwrap();
+        handle.read_to_string(&mut content)?;
     } else {
-        let file = File::open(&file_name).unwrap();
+        let file = File::open(file_name)
+            .map_err(|err| anyhow!("Cannot open `{}` ({})", file_name, err))?;
         let mut buf_reader = BufReader::new(file);
-        buf_reader.read_to_string(&mut content).unwrap();
+        buf_reader.read_to_string(&mut content)?;
     }
-    content
+    Ok(content)
+}
+
+fn environ(opt: &Opt, cd: Option<String>) -> Result<Environ> {
+    if opt.decimal_scale > MAX_DECIMAL_SCALE {
+        bail!("--decimal-scale must be at most {}", MAX_DECIMAL_SCALE);
+    }
+    let mut env = Environ::new(cd)
+        .with_float_overflow(opt.float_overflow)
+        .with_decimal_rounding(opt.decimal_rounding, opt.decimal_scale)
+        .with_warn_overwrite(opt.warn_overwrite);
+    if let Some(now) = &opt.now {
+        match parse_datetime(now) {
+            Some(now) => env = env.with_now(now),
+            None => bail!("--now must be an RFC 3339 datetime, not `{}`", now),
+        }
+    }
+    Ok(env)
+}
+
+fn output(opt: &Opt) -> Result<Output> {
+    Ok(Output {
+        decimal: opt.decimal_output,
+        duration: UnitOutput::parse(&opt.duration_output, DURATION_UNITS)?,
+        bytes: UnitOutput::parse(&opt.bytes_output, BYTES_UNITS)?,
+    })
+}
+
+fn parse(content: &str) -> Result<Cumin> {
+    match cumin(content) {
+        Ok(("", cumin)) => Ok(cumin),
+        Ok((rest, _)) => bail!("Parsing Stop with `{}`", rest),
+        Err(err) => match parse_error(&err) {
+            Some(msg) => bail!("Parse Error: {}", msg),
+            None => bail!("Parse Error"),
+        },
+    }
+}
+
+/// The `.cumin` files under `dir` which declare a `test` block, in path order.
+fn find_tests(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_tests(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "cumin") {
+            let declares_tests = path
+                .to_str()
+                .and_then(|name| cat(name).ok())
+                .and_then(|content| parse(&content).ok())
+                .is_some_and(|cumin| cumin.0.iter().any(|s| matches!(s, Statement::Test(..))));
+            if declares_tests {
+                files.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the tests of each file and of the modules it uses, each test once;
+/// a file which cannot be evaluated fails as a whole.
+fn test(opt: &Opt, paths: &[String]) -> Result<bool> {
+    let mut files = vec![];
+    for path in paths {
+        if Path::new(path).is_dir() {
+            find_tests(Path::new(path), &mut files)?;
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+    let mut seen = HashSet::new();
+    let mut passed = 0;
+    let mut failures = vec![];
+    for file in files.iter() {
+        let name = file.display().to_string();
+        let cd = file
+            .parent()
+            .and_then(|path| path.to_str().map(String::from));
+        let mut env = environ(opt, cd)?.with_tests(&name);
+        let result = cat(&name)
+            .and_then(|content| parse(&content))
+            .and_then(|cumin| eval_cumin(&mut env, &cumin));
+        for outcome in env.take_tests() {
+            let module = match outcome.module {
+                Some(module) => Path::new(&module).components().collect::<PathBuf>(),
+                None => file.clone(),
+            };
+            let key = std::fs::canonicalize(&module).unwrap_or_else(|_| module.clone());
+            if !seen.insert((key, outcome.name.clone())) {
+                continue;
+            }
+            let id = format!("{}::{}", module.display(), outcome.name);
+            match outcome.failure {
+                None => {
+                    println!("test {} ... ok", id);
+                    passed += 1;
+                }
+                Some(failure) => {
+                    println!("test {} ... FAILED", id);
+                    failures.push((id, failure));
+                }
+            }
+        }
+        if let Err(err) = result {
+            println!("{} ... FAILED", name);
+            failures.push((name, err.to_string()));
+        }
+    }
+    report("test", passed, &failures, "");
+    Ok(failures.is_empty())
+}
+
+fn compile(opt: &Opt, case: &Case, env_vars: HashMap<String, String>) -> Result<String> {
+    let name = case.cumin.display().to_string();
+    let cumin = parse(&cat(&name)?)?;
+    let cd = case
+        .cumin
+        .parent()
+        .and_then(|path| path.to_str().map(String::from));
+    let mut env = environ(opt, cd)?.with_env_vars(env_vars);
+    let json = JSON::from_cumin(eval_cumin(&mut env, &cumin)?)?.with_output(&output(opt)?);
+    Ok(json.stringify())
+}
+
+/// Why a compiled case does not meet its expectation, if it does not.
+fn check_case(case: &Case, compiled: &Result<String>) -> Result<Option<String>> {
+    Ok(match (&case.expect, compiled) {
+        (Expect::Json(path), Ok(actual)) => {
+            let expected: serde_json::Value =
+                serde_json::from_str(&cat(&path.display().to_string())?)
+                    .map_err(|err| anyhow!("`{}` is not JSON ({})", path.display(), err))?;
+            let diffs = golden::diff(&expected, &serde_json::from_str(actual)?);
+            if diffs.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "differs from `{}`:\n  {}",
+                    path.display(),
+                    diffs.join("\n  ")
+                ))
+            }
+        }
+        (Expect::Json(_), Err(err)) => Some(format!("expected to compile, but failed ({})", err)),
+        (Expect::Fail(_), Ok(actual)) => {
+            Some(format!("expected to fail, but compiled to {}", actual))
+        }
+        (Expect::Fail(_), Err(_)) => None,
+    })
+}
+
+/// Makes what a case does now its expectation: rewrites `x.json`, or swaps it with `x.fail`.
+fn bless_case(case: &Case, compiled: &Result<String>) -> Result<()> {
+    match (&case.expect, compiled) {
+        (Expect::Json(path), Ok(actual)) => write(path, format!("{}\n", actual))?,
+        (Expect::Json(path), Err(_)) => {
+            write(path.with_extension("fail"), "")?;
+            remove_file(path)?;
+        }
+        (Expect::Fail(path), Ok(actual)) => {
+            write(path.with_extension("json"), format!("{}\n", actual))?;
+            remove_file(path)?;
+        }
+        (Expect::Fail(_), Err(_)) => (),
+    }
+    Ok(())
+}
+
+enum Verdict {
+    Pass,
+    Blessed,
+    Fail(String),
+}
+
+fn run_case(opt: &Opt, case: &Case, bless: bool) -> Result<Verdict> {
+    if !case.cumin.is_file() {
+        bail!("Not Found `{}`", case.cumin.display());
+    }
+    let env_vars = match &case.env {
+        Some(path) => golden::read_env_vars(path)?,
+        None => HashMap::new(),
+    };
+    let compiled = compile(opt, case, env_vars);
+    Ok(match check_case(case, &compiled)? {
+        None => Verdict::Pass,
+        Some(_) if bless => {
+            bless_case(case, &compiled)?;
+            Verdict::Blessed
+        }
+        Some(failure) => Verdict::Fail(failure),
+    })
+}
+
+/// Runs the golden cases of `dir` in-process, each with only the environ variables of its `x.env`.
+fn golden(opt: &Opt, dir: &str, bless: bool) -> Result<bool> {
+    let mut passed = 0;
+    let mut blessed = 0;
+    let mut failures = vec![];
+    for case in golden::cases(Path::new(dir))? {
+        let name = case.cumin.display().to_string();
+        match run_case(opt, &case, bless) {
+            Ok(Verdict::Pass) => {
+                println!("golden {} ... ok", name);
+                passed += 1;
+            }
+            Ok(Verdict::Blessed) => {
+                println!("golden {} ... blessed", name);
+                blessed += 1;
+            }
+            Ok(Verdict::Fail(failure)) => {
+                println!("golden {} ... FAILED", name);
+                failures.push((name, failure));
+            }
+            Err(err) => {
+                println!("golden {} ... FAILED", name);
+                failures.push((name, err.to_string()));
+            }
+        }
+    }
+    let note = if bless {
+        format!("; {} blessed", blessed)
+    } else {
+        String::new()
+    };
+    report("golden", passed, &failures, &note);
+    Ok(failures.is_empty())
+}
+
+/// Prints the failures and a `<kind> result` summary.
+fn report(kind: &str, passed: usize, failures: &[(String, String)], note: &str) {
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (id, failure) in failures.iter() {
+            println!("\n---- {} ----\n{}", id, failure);
+        }
+    }
+    println!(
+        "\n{} result: {}. {} passed; {} failed{}",
+        kind,
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len(),
+        note
+    );
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let content = cat(&opt.input_cumin);
-    if let Ok((rest, cumin)) = cumin(content.as_str()) {
+    let passed = match &opt.command {
+        Some(Command::Test { paths }) => Some(test(&opt, paths)?),
+        Some(Command::Golden { dir, bless }) => Some(golden(&opt, dir, *bless)?),
+        None => None,
+    };
+    match passed {
+        Some(false) => std::process::exit(1),
+        Some(true) => return Ok(()),
+        None => (),
+    }
+    let content = cat(&opt.input_cumin)?;
+    let parsed = cumin(content.as_str());
+    if let Some(msg) = parsed.as_ref().err().and_then(parse_error) {
+        bail!("Parse Error: {}", msg);
+    }
+    if let Ok((rest, cumin)) = parsed {
         if !rest.is_empty() {
             eprintln!("Parsing Stop with `{}`", rest);
             eprintln!("read conf: {:?}", &cumin);
@@ -51,8 +373,10 @@ fn main() -> Result<()> {
         }
         let cd = Path::new(&opt.input_cumin)
             .parent()
-            .map(|path| String::from(path.to_str().unwrap()));
-        let json = eval(cumin, cd)?;
+            .and_then(|path| path.to_str().map(String::from));
+        let output = output(&opt)?;
+        let mut env = environ(&opt, cd)?;
+        let json = JSON::from_cumin(eval_cumin(&mut env, &cumin)?)?.with_output(&output);
         match opt.output_type.as_str() {
             "json" | "JSON" | "Json" => {
                 println!("{}", json.stringify());
@@ -1,14 +1,27 @@
+use crate::parser::expr::FormatSpec;
 use crate::parser::typing::*;
-use crate::parser::value::Value;
+use crate::parser::value::{Enums, Value};
+use crate::semver;
+use crate::units::{unit_factor, DURATION_UNITS};
+use crate::{bail_arithmetic_error, bail_type_error};
 use anyhow::Result;
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 
-pub fn concat(args: &Vec<Value>) -> Result<Value> {
+/// Upper bound of strings built by `repeat`, to keep a config from exhausting memory.
+const MAX_STRING_LEN: usize = 1 << 24;
+
+/// Upper bound of arrays built by `range`.
+const MAX_ARRAY_LEN: usize = 1 << 24;
+
+pub fn concat(args: &[Value]) -> Result<Value> {
     let mut r = vec![];
     let mut t = Typing::Any;
     for arr in args {
         match arr {
             Value::Array(typ, xs) => {
-                if let Some(unified) = Typing::unify(&t, &typ) {
+                if let Some(unified) = Typing::unify(&t, typ) {
                     t = unified;
                     r.extend(xs.iter().cloned())
                 } else {
@@ -30,3 +43,649 @@ pub fn reverse(x: &Value) -> Result<Value> {
         _ => bail!("Cannot reverse {:?}, because this is not array", x),
     }
 }
+
+fn string_arg<'a>(fname: &str, x: &'a Value) -> Result<&'a str> {
+    match x {
+        Value::Str(s) => Ok(s),
+        _ => bail!("Cannot {} {:?}, because this is not string", fname, x),
+    }
+}
+
+fn nat_arg(fname: &str, x: &Value) -> Result<usize> {
+    match x {
+        Value::Nat(n) if *n <= usize::MAX as u128 => Ok(*n as usize),
+        Value::Int(n) if *n >= 0 && *n <= usize::MAX as i128 => Ok(*n as usize),
+        _ => bail!("Cannot {} with {:?}, because this is not Nat", fname, x),
+    }
+}
+
+pub fn len(x: &Value) -> Result<Value> {
+    let n = match x {
+        Value::Array(_, elems) | Value::Tuple(elems) => elems.len(),
+        Value::Dict(_, entries) => entries.iter().count(),
+        _ => string_arg("len", x)?.chars().count(),
+    };
+    Ok(Value::Nat(n as u128))
+}
+
+pub fn upper(x: &Value) -> Result<Value> {
+    let s = string_arg("upper", x)?;
+    Ok(Value::Str(s.to_uppercase()))
+}
+
+pub fn lower(x: &Value) -> Result<Value> {
+    let s = string_arg("lower", x)?;
+    Ok(Value::Str(s.to_lowercase()))
+}
+
+pub fn trim(x: &Value) -> Result<Value> {
+    let s = string_arg("trim", x)?;
+    Ok(Value::Str(s.trim().to_string()))
+}
+
+pub fn split(x: &Value, sep: &Value) -> Result<Value> {
+    let s = string_arg("split", x)?;
+    let sep = string_arg("split", sep)?;
+    let parts = if sep.is_empty() {
+        s.chars().map(|c| Value::Str(c.to_string())).collect()
+    } else {
+        s.split(sep).map(|t| Value::Str(t.to_string())).collect()
+    };
+    Ok(Value::Array(Typing::String, parts))
+}
+
+pub fn join(xs: &Value, sep: &Value) -> Result<Value> {
+    let sep = string_arg("join", sep)?;
+    match xs {
+        Value::Array(_, elems) => {
+            let parts = elems
+                .iter()
+                .map(|x| string_arg("join", x))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Value::Str(parts.join(sep)))
+        }
+        _ => bail!("Cannot join {:?}, because this is not array", xs),
+    }
+}
+
+pub fn replace(x: &Value, from: &Value, to: &Value) -> Result<Value> {
+    let s = string_arg("replace", x)?;
+    let from = string_arg("replace", from)?;
+    let to = string_arg("replace", to)?;
+    if from.is_empty() {
+        bail!("Cannot replace an empty string");
+    }
+    Ok(Value::Str(s.replace(from, to)))
+}
+
+pub fn starts_with(x: &Value, prefix: &Value) -> Result<Value> {
+    let s = string_arg("starts_with", x)?;
+    let prefix = string_arg("starts_with", prefix)?;
+    Ok(Value::Bool(s.starts_with(prefix)))
+}
+
+pub fn ends_with(x: &Value, suffix: &Value) -> Result<Value> {
+    let s = string_arg("ends_with", x)?;
+    let suffix = string_arg("ends_with", suffix)?;
+    Ok(Value::Bool(s.ends_with(suffix)))
+}
+
+pub fn contains(x: &Value, y: &Value) -> Result<Value> {
+    match (x, y) {
+        (Value::Array(..), _) => return Ok(Value::Bool(position("contains", x, y)?.is_some())),
+        (Value::Cidr(cidr), Value::Ip(ip)) => return Ok(Value::Bool(cidr.contains(ip))),
+        (Value::Cidr(_), _) => bail!(
+            "Cannot check whether {:?} contains {:?}, because it is not an IpAddr",
+            x,
+            y
+        ),
+        _ => {}
+    }
+    let s = string_arg("contains", x)?;
+    let t = string_arg("contains", y)?;
+    Ok(Value::Bool(s.contains(t)))
+}
+
+pub fn pad_left(x: &Value, width: &Value, fill: Option<&Value>) -> Result<Value> {
+    let s = string_arg("pad_left", x)?;
+    let width = nat_arg("pad_left", width)?;
+    let fill = match fill {
+        Some(fill) => {
+            let fill = string_arg("pad_left", fill)?;
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => bail!(
+                    "Cannot pad_left with {:?}, because this is not a single char",
+                    fill
+                ),
+            }
+        }
+        None => ' ',
+    };
+    if width > MAX_STRING_LEN {
+        bail!(
+            "Cannot pad_left to {}, because the result is too long",
+            width
+        );
+    }
+    let mut r = fill
+        .to_string()
+        .repeat(width.saturating_sub(s.chars().count()));
+    r.push_str(s);
+    Ok(Value::Str(r))
+}
+
+pub fn repeat(x: &Value, n: &Value) -> Result<Value> {
+    let s = string_arg("repeat", x)?;
+    let n = nat_arg("repeat", n)?;
+    if s.len().saturating_mul(n) > MAX_STRING_LEN {
+        bail!(
+            "Cannot repeat {:?} {} times, because the result is too long",
+            s,
+            n
+        );
+    }
+    Ok(Value::Str(s.repeat(n)))
+}
+
+pub fn substr(x: &Value, start: &Value, length: &Value) -> Result<Value> {
+    let s = string_arg("substr", x)?;
+    let start = nat_arg("substr", start)?;
+    let length = nat_arg("substr", length)?;
+    let chars: Vec<char> = s.chars().collect();
+    match start.checked_add(length) {
+        Some(end) if end <= chars.len() => Ok(Value::Str(chars[start..end].iter().collect())),
+        _ => bail!(
+            "IndexError: substr({}, {}) is out of range for length {}",
+            start,
+            length,
+            chars.len()
+        ),
+    }
+}
+
+fn array_arg<'a>(fname: &str, x: &'a Value) -> Result<(&'a Typing, &'a Vec<Value>)> {
+    match x {
+        Value::Array(t, xs) => Ok((t, xs)),
+        _ => bail!("Cannot {} {:?}, because this is not array", fname, x),
+    }
+}
+
+pub fn sum(xs: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("sum", xs)?;
+    fold_numbers(
+        "sum",
+        t,
+        elems,
+        0,
+        |x, y| x.checked_add(y),
+        |x, y| x.checked_add(y),
+        |x, y| x + y,
+        |x, y| x.checked_add(y),
+    )
+}
+
+pub fn product(xs: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("product", xs)?;
+    fold_numbers(
+        "product",
+        t,
+        elems,
+        1,
+        |x, y| x.checked_mul(y),
+        |x, y| x.checked_mul(y),
+        |x, y| x * y,
+        |x, y| x.checked_mul(y),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fold_numbers(
+    fname: &str,
+    t: &Typing,
+    elems: &[Value],
+    unit: u8,
+    nat: fn(u128, u128) -> Option<u128>,
+    int: fn(i128, i128) -> Option<i128>,
+    float: fn(f64, f64) -> f64,
+    decimal: fn(Decimal, Decimal) -> Option<Decimal>,
+) -> Result<Value> {
+    let overflow = || {
+        anyhow!(
+            "ArithmeticError: Cannot {} {:?}, because it overflows",
+            fname,
+            elems
+        )
+    };
+    match t {
+        Typing::Any | Typing::Nat => {
+            let mut acc = unit as u128;
+            for x in elems {
+                match x {
+                    Value::Nat(x) => acc = nat(acc, *x).ok_or_else(overflow)?,
+                    _ => bail!("Cannot {} {:?}, because this is not number", fname, x),
+                }
+            }
+            Ok(Value::Nat(acc))
+        }
+        Typing::Int => {
+            let mut acc = unit as i128;
+            for x in elems {
+                match x {
+                    Value::Int(x) => acc = int(acc, *x).ok_or_else(overflow)?,
+                    _ => bail!("Cannot {} {:?}, because this is not number", fname, x),
+                }
+            }
+            Ok(Value::Int(acc))
+        }
+        Typing::Float => {
+            let mut acc = unit as f64;
+            for x in elems {
+                match x {
+                    Value::Float(x) => acc = float(acc, *x),
+                    _ => bail!("Cannot {} {:?}, because this is not number", fname, x),
+                }
+            }
+            Ok(Value::Float(acc))
+        }
+        Typing::Decimal => {
+            let mut acc = Decimal::from(unit);
+            for x in elems {
+                match x {
+                    Value::Decimal(x) => acc = decimal(acc, *x).ok_or_else(overflow)?,
+                    _ => bail!("Cannot {} {:?}, because this is not number", fname, x),
+                }
+            }
+            Ok(Value::Decimal(acc))
+        }
+        _ => bail!(
+            "Cannot {} Array of {:?}, because this is not number",
+            fname,
+            t
+        ),
+    }
+}
+
+fn extremum(fname: &str, xs: &Value, wanted: Ordering, enums: &Enums) -> Result<Value> {
+    let (_, elems) = array_arg(fname, xs)?;
+    let mut best: Option<&Value> = None;
+    for x in elems.iter() {
+        best = match best {
+            Some(b) if x.compare(b, enums)? != wanted => Some(b),
+            _ => Some(x),
+        };
+    }
+    match best {
+        Some(b) => Ok(b.clone()),
+        None => bail!("Cannot {} an empty array", fname),
+    }
+}
+
+/// `min(xs)` over an array, or `min(x, y, ...)` over values unified as numbers.
+pub fn min(args: &[Value], enums: &Enums) -> Result<Value> {
+    extremum("min", &variadic(args)?, Ordering::Less, enums)
+}
+
+/// `max(xs)` over an array, or `max(x, y, ...)` over values unified as numbers.
+pub fn max(args: &[Value], enums: &Enums) -> Result<Value> {
+    extremum("max", &variadic(args)?, Ordering::Greater, enums)
+}
+
+fn variadic(args: &[Value]) -> Result<Value> {
+    if let [xs] = args {
+        return Ok(xs.clone());
+    }
+    unify_values(args)
+}
+
+fn unify_values(args: &[Value]) -> Result<Value> {
+    let mut t = Typing::Any;
+    for x in args {
+        match Typing::unify(&t, &x.type_of()) {
+            Some(unified) => t = unified,
+            None => bail!("Cannot unify {:?} and {:?}", &t, x.type_of()),
+        }
+    }
+    let elems = args.iter().map(|x| x.cast(&t)).collect::<Result<_>>()?;
+    Ok(Value::Array(t, elems))
+}
+
+/// Stable sort of `elems` by `key`, failing on the first incomparable pair.
+fn sort_values<F>(elems: &mut Vec<Value>, key: F, enums: &Enums) -> Result<()>
+where
+    F: Fn(&Value) -> Result<Value>,
+{
+    let keys = elems.iter().map(key).collect::<Result<Vec<_>>>()?;
+    let mut indexed: Vec<(Value, Value)> = keys.into_iter().zip(elems.drain(..)).collect();
+    let mut err = None;
+    indexed.sort_by(|(a, _), (b, _)| {
+        a.compare(b, enums).unwrap_or_else(|e| {
+            err.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+    if let Some(e) = err {
+        return Err(e);
+    }
+    elems.extend(indexed.into_iter().map(|(_, x)| x));
+    Ok(())
+}
+
+pub fn sort(xs: &Value, enums: &Enums) -> Result<Value> {
+    let (t, elems) = array_arg("sort", xs)?;
+    let mut elems = elems.clone();
+    sort_values(&mut elems, |x| Ok(x.clone()), enums)?;
+    Ok(Value::Array(t.clone(), elems))
+}
+
+/// Sorts an array of structs by a field name, or an array of tuples by a position.
+pub fn sort_by_key(xs: &Value, key: &Value, enums: &Enums) -> Result<Value> {
+    let (t, elems) = array_arg("sort_by_key", xs)?;
+    let mut elems = elems.clone();
+    sort_values(
+        &mut elems,
+        |x| match (x, key) {
+            (Value::Dict(_, entries), Value::Str(name)) => entries
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, val)| val.clone())
+                .ok_or_else(|| anyhow!("Cannot find property {} in {:?}", name, x)),
+            (Value::Tuple(items), Value::Nat(i)) => items
+                .get(usize::try_from(*i).unwrap_or(usize::MAX))
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "IndexError: index {} is out of range for length {}",
+                        i,
+                        items.len()
+                    )
+                }),
+            _ => bail!("Cannot sort_by_key {:?} with key {:?}", x, key),
+        },
+        enums,
+    )?;
+    Ok(Value::Array(t.clone(), elems))
+}
+
+pub fn uniq(xs: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("uniq", xs)?;
+    let mut r: Vec<Value> = vec![];
+    for x in elems.iter() {
+        if !r.contains(x) {
+            r.push(x.clone());
+        }
+    }
+    Ok(Value::Array(t.clone(), r))
+}
+
+pub fn zip(xs: &Value, ys: &Value) -> Result<Value> {
+    let (s, xs) = array_arg("zip", xs)?;
+    let (t, ys) = array_arg("zip", ys)?;
+    let r = xs
+        .iter()
+        .zip(ys.iter())
+        .map(|(x, y)| Value::Tuple(vec![x.clone(), y.clone()]))
+        .collect();
+    Ok(Value::Array(Typing::Tuple(vec![s.clone(), t.clone()]), r))
+}
+
+pub fn enumerate(xs: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("enumerate", xs)?;
+    let r = elems
+        .iter()
+        .enumerate()
+        .map(|(i, x)| Value::Tuple(vec![Value::Nat(i as u128), x.clone()]))
+        .collect();
+    Ok(Value::Array(Typing::Tuple(vec![Typing::Nat, t.clone()]), r))
+}
+
+pub fn flatten(xs: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("flatten", xs)?;
+    match t {
+        Typing::Any | Typing::Array(_) => concat(elems),
+        _ => bail!("Cannot flatten Array of {:?}, because this is not array", t),
+    }
+}
+
+fn position(fname: &str, xs: &Value, x: &Value) -> Result<Option<usize>> {
+    let (t, elems) = array_arg(fname, xs)?;
+    let x = match Typing::unify(t, &x.type_of()) {
+        Some(typ) => x.cast(&typ)?,
+        None => bail!("Cannot find {:?} in Array of {:?}", x, t),
+    };
+    Ok(elems.iter().position(|e| e == &x))
+}
+
+pub fn index_of(xs: &Value, x: &Value) -> Result<Value> {
+    let i = position("index_of", xs, x)?.map(|i| Value::Nat(i as u128));
+    Ok(Value::Optional(Typing::Nat, Box::new(i)))
+}
+
+pub fn chunks(xs: &Value, n: &Value) -> Result<Value> {
+    let (t, elems) = array_arg("chunks", xs)?;
+    let n = nat_arg("chunks", n)?;
+    if n == 0 {
+        bail!("Cannot chunks with size 0");
+    }
+    let r = elems
+        .chunks(n)
+        .map(|c| Value::Array(t.clone(), c.to_vec()))
+        .collect();
+    Ok(Value::Array(Typing::Array(Box::new(t.clone())), r))
+}
+
+pub fn range(args: &[Value]) -> Result<Value> {
+    let (start, end) = match args {
+        [end] => (0, nat_arg("range", end)?),
+        [start, end] => (nat_arg("range", start)?, nat_arg("range", end)?),
+        _ => bail!("Cannot range with {:?}", args),
+    };
+    if end.saturating_sub(start) > MAX_ARRAY_LEN {
+        bail!(
+            "Cannot range({}, {}), because the result is too long",
+            start,
+            end
+        );
+    }
+    let r = (start..end).map(|i| Value::Nat(i as u128)).collect();
+    Ok(Value::Array(Typing::Nat, r))
+}
+
+pub fn constant(name: &str) -> Option<Value> {
+    match name {
+        "PI" => Some(Value::Float(std::f64::consts::PI)),
+        "E" => Some(Value::Float(std::f64::consts::E)),
+        _ => None,
+    }
+}
+
+fn float_arg(fname: &str, x: &Value) -> Result<f64> {
+    match x {
+        Value::Nat(x) => Ok(*x as f64),
+        Value::Int(x) => Ok(*x as f64),
+        Value::Float(x) => Ok(*x),
+        _ => bail_type_error!(compute fname x),
+    }
+}
+
+pub fn abs(x: &Value) -> Result<Value> {
+    match x {
+        Value::Nat(_) => Ok(x.clone()),
+        Value::Int(y) => match y.checked_abs() {
+            Some(y) => Ok(Value::Int(y)),
+            None => bail_arithmetic_error!(overflow "abs" x),
+        },
+        Value::Float(y) => Ok(Value::Float(y.abs())),
+        Value::Decimal(y) => Ok(Value::Decimal(y.abs())),
+        _ => bail_type_error!(compute "abs" x),
+    }
+}
+
+/// Whether a SemVer (or a string of one) is in a range such as `^1.2` or `>=1.4, <2`.
+pub fn satisfies(v: &Value, range: &Value) -> Result<Value> {
+    let range = string_arg("satisfies", range)?;
+    match v.coerce(&Typing::SemVer)? {
+        Value::SemVer(v) => match semver::satisfies(&v, range) {
+            Ok(ok) => Ok(Value::Bool(ok)),
+            Err(err) => bail!("Invalid version range {:?} ({})", range, err),
+        },
+        _ => bail_type_error!(compute "satisfies" v),
+    }
+}
+
+/// Whole units in a Duration, truncated: `90s.as_mins()` is 1.
+pub fn duration_as(fname: &str, d: &Value) -> Result<Value> {
+    let unit = match fname {
+        "as_nanos" => "ns",
+        "as_micros" => "us",
+        "as_millis" => "ms",
+        "as_secs" => "s",
+        "as_mins" => "m",
+        _ => "h",
+    };
+    match (d, unit_factor(DURATION_UNITS, unit)) {
+        (Value::Duration(nanos), Some(factor)) => Ok(Value::Nat(nanos / factor)),
+        _ => bail_type_error!(compute fname d),
+    }
+}
+
+/// Rounding functions keep integers as they are.
+pub fn rounding(
+    fname: &str,
+    x: &Value,
+    f: fn(f64) -> f64,
+    g: fn(&Decimal) -> Decimal,
+) -> Result<Value> {
+    match x {
+        Value::Nat(_) | Value::Int(_) => Ok(x.clone()),
+        Value::Float(y) => Ok(Value::Float(f(*y))),
+        Value::Decimal(y) => Ok(Value::Decimal(g(y))),
+        _ => bail_type_error!(compute fname x),
+    }
+}
+
+/// Real functions always return Float, and fail instead of producing NaN.
+pub fn real(fname: &str, x: &Value, f: fn(f64) -> f64) -> Result<Value> {
+    let y = f(float_arg(fname, x)?);
+    if y.is_nan() {
+        bail!(
+            "Cannot compute `{}` {:?}, because the result is NaN",
+            fname,
+            x
+        );
+    }
+    Ok(Value::Float(y))
+}
+
+pub fn clamp(x: &Value, lo: &Value, hi: &Value, enums: &Enums) -> Result<Value> {
+    let unified = unify_values(&[x.clone(), lo.clone(), hi.clone()])?;
+    let (_, elems) = array_arg("clamp", &unified)?;
+    let (x, lo, hi) = (&elems[0], &elems[1], &elems[2]);
+    if lo.compare(hi, enums)? == Ordering::Greater {
+        bail!(
+            "Cannot clamp, because the lower bound {:?} exceeds the upper bound {:?}",
+            lo,
+            hi
+        );
+    }
+    if x.compare(lo, enums)? == Ordering::Less {
+        Ok(lo.clone())
+    } else if x.compare(hi, enums)? == Ordering::Greater {
+        Ok(hi.clone())
+    } else {
+        Ok(x.clone())
+    }
+}
+
+fn integer_arg(fname: &str, x: &Value) -> Result<u128> {
+    match x {
+        Value::Nat(x) => Ok(*x),
+        Value::Int(x) => Ok(x.unsigned_abs()),
+        _ => bail_type_error!(compute fname x),
+    }
+}
+
+fn integer_result(fname: &str, x: &Value, y: &Value, r: u128) -> Result<Value> {
+    match (x, y) {
+        (Value::Nat(_), Value::Nat(_)) => Ok(Value::Nat(r)),
+        _ => match i128::try_from(r) {
+            Ok(r) => Ok(Value::Int(r)),
+            Err(_) => bail_arithmetic_error!(overflow x fname y),
+        },
+    }
+}
+
+fn euclid(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+pub fn gcd(x: &Value, y: &Value) -> Result<Value> {
+    let a = integer_arg("gcd", x)?;
+    let b = integer_arg("gcd", y)?;
+    integer_result("gcd", x, y, euclid(a, b))
+}
+
+pub fn lcm(x: &Value, y: &Value) -> Result<Value> {
+    let a = integer_arg("lcm", x)?;
+    let b = integer_arg("lcm", y)?;
+    if a == 0 || b == 0 {
+        return integer_result("lcm", x, y, 0);
+    }
+    match (a / euclid(a, b)).checked_mul(b) {
+        Some(r) => integer_result("lcm", x, y, r),
+        None => bail_arithmetic_error!(overflow x "lcm" y),
+    }
+}
+
+/// Renders a value in an interpolated string.
+pub fn format(val: &Value, spec: &FormatSpec) -> Result<String> {
+    let width = spec.width.unwrap_or(0);
+    if width > MAX_STRING_LEN || spec.precision.unwrap_or(0) > MAX_STRING_LEN {
+        bail!(
+            "Cannot format {:?} with {:?}, because the result is too long",
+            val,
+            spec
+        );
+    }
+    let s = match (val, spec.precision) {
+        (Value::Nat(_), Some(p)) | (Value::Int(_), Some(p)) | (Value::Float(_), Some(p)) => {
+            format!("{:.*}", p, float_arg("format", val)?)
+        }
+        (Value::Decimal(x), Some(p)) => format!("{:.*}", p, x),
+        (Value::Str(x), Some(p)) => x.chars().take(p).collect(),
+        (_, Some(_)) => bail!("Cannot format {:?} with precision", val),
+        (_, None) => match val.coerce(&Typing::String)? {
+            Value::Str(s) => s,
+            _ => bail!("Cannot format {:?} as String", val),
+        },
+    };
+    let is_number = matches!(
+        val,
+        Value::Nat(_) | Value::Int(_) | Value::Float(_) | Value::Decimal(_)
+    );
+    let n = s.chars().count();
+    if n >= width {
+        return Ok(s);
+    }
+    let pad = width - n;
+    if spec.zero && is_number && spec.align.is_none() {
+        return Ok(match s.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(pad), digits),
+            None => format!("{}{}", "0".repeat(pad), s),
+        });
+    }
+    let fill = spec.fill.unwrap_or(if spec.zero { '0' } else { ' ' });
+    let fill = |k: usize| fill.to_string().repeat(k);
+    let align = spec.align.unwrap_or(if is_number { '>' } else { '<' });
+    Ok(match align {
+        '>' => format!("{}{}", fill(pad), s),
+        '^' => format!("{}{}{}", fill(pad / 2), s, fill(pad - pad / 2)),
+        _ => format!("{}{}", s, fill(pad)),
+    })
+}
@@ -0,0 +1,129 @@
+use crate::parser::value::Value;
+use anyhow::Result;
+use chrono::{
+    DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, SecondsFormat, TimeDelta, Timelike,
+};
+use std::convert::TryFrom;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+const NANOS_PER_DAY: u128 = 86_400 * NANOS_PER_SEC;
+
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+}
+
+/// `HH:MM`, `HH:MM:SS` or `HH:MM:SS.fff`
+pub fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M:%S%.f")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .ok()
+}
+
+/// RFC 3339, with an explicit offset.
+pub fn parse_datetime(s: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(s).ok()
+}
+
+pub fn format_date(d: &NaiveDate) -> String {
+    d.format("%Y-%m-%d").to_string()
+}
+
+pub fn format_time(t: &NaiveTime) -> String {
+    t.format("%H:%M:%S%.f").to_string()
+}
+
+/// RFC 3339, writing UTC as `Z`.
+pub fn format_datetime(dt: &DateTime<FixedOffset>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// A Duration (in nanoseconds) as a span of time.
+pub fn to_delta(nanos: u128) -> Result<TimeDelta> {
+    let secs = i64::try_from(nanos / NANOS_PER_SEC).ok();
+    match secs.and_then(TimeDelta::try_seconds) {
+        Some(delta) => Ok(delta + TimeDelta::nanoseconds((nanos % NANOS_PER_SEC) as i64)),
+        None => bail!("ArithmeticError: Duration of {}ns is out of range", nanos),
+    }
+}
+
+/// A span of time as a Duration (in nanoseconds), which cannot be negative.
+pub fn from_delta(delta: TimeDelta) -> Result<u128> {
+    let secs = u128::try_from(delta.num_seconds());
+    let nanos = u128::try_from(delta.subsec_nanos());
+    match (secs, nanos) {
+        (Ok(secs), Ok(nanos)) => Ok(secs * NANOS_PER_SEC + nanos),
+        _ => bail!("ArithmeticError: {} is a negative Duration", delta),
+    }
+}
+
+/// A Duration of whole days, to move a Date by.
+pub fn to_days(nanos: u128) -> Result<TimeDelta> {
+    if !nanos.is_multiple_of(NANOS_PER_DAY) {
+        bail!(
+            "ArithmeticError: Date can only move by whole days, not {}ns",
+            nanos
+        );
+    }
+    to_delta(nanos)
+}
+
+/// `year`, `month`, `day`, `weekday` (1 for Monday), `hour`, `minute`, `second`,
+/// and `date`, `time` of a DateTime.
+pub fn component(val: &Value, name: &str) -> Option<Value> {
+    let nat = |x: u32| Some(Value::Nat(x as u128));
+    let date = |d: &NaiveDate| match name {
+        "year" => Some(Value::Int(d.year() as i128)),
+        "month" => nat(d.month()),
+        "day" => nat(d.day()),
+        "weekday" => nat(d.weekday().number_from_monday()),
+        _ => None,
+    };
+    let time = |t: &NaiveTime| match name {
+        "hour" => nat(t.hour()),
+        "minute" => nat(t.minute()),
+        "second" => nat(t.second()),
+        _ => None,
+    };
+    match val {
+        Value::Date(d) => date(d),
+        Value::Time(t) => time(t),
+        Value::DateTime(dt) => match name {
+            "date" => Some(Value::Date(dt.date_naive())),
+            "time" => Some(Value::Time(dt.time())),
+            _ => date(&dt.date_naive()).or_else(|| time(&dt.time())),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_datetime {
+    use crate::datetime::*;
+
+    #[test]
+    fn test_parse_format() {
+        let d = parse_date("2024-02-29").unwrap();
+        assert_eq!(format_date(&d), "2024-02-29");
+        assert!(parse_date("2023-02-29").is_none());
+        assert_eq!(format_time(&parse_time("09:30").unwrap()), "09:30:00");
+        assert_eq!(
+            format_time(&parse_time("09:30:15.250").unwrap()),
+            "09:30:15.250"
+        );
+        let dt = parse_datetime("2024-03-01T12:00:00+00:00").unwrap();
+        assert_eq!(format_datetime(&dt), "2024-03-01T12:00:00Z");
+        let dt = parse_datetime("2024-03-01T12:00:00+09:00").unwrap();
+        assert_eq!(format_datetime(&dt), "2024-03-01T12:00:00+09:00");
+        assert!(parse_datetime("2024-03-01T12:00:00").is_none());
+    }
+
+    #[test]
+    fn test_delta() {
+        let nanos = 90 * NANOS_PER_SEC + 5;
+        assert_eq!(from_delta(to_delta(nanos).unwrap()).unwrap(), nanos);
+        assert!(from_delta(TimeDelta::seconds(-1)).is_err());
+        assert!(to_delta(u128::MAX).is_err());
+        assert!(to_days(NANOS_PER_DAY * 2).is_ok());
+        assert!(to_days(NANOS_PER_DAY + 1).is_err());
+    }
+}
@@ -26,6 +26,20 @@ macro_rules! assert_args_leq {
     };
 }
 
+#[macro_export]
+macro_rules! assert_args_geq {
+    ($name:expr, $given:expr, $expected:expr) => {
+        if $given < $expected {
+            bail!(
+                "ArgumentError: wrong number of arguments for `{}` (given {}, expected >={})",
+                $name,
+                $given,
+                $expected
+            );
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! bail_type_error {
     (compute $x:tt $op:tt $y:tt) => {
@@ -35,3 +49,21 @@ macro_rules! bail_type_error {
         bail!("TypeError: Cant compute `{}` {:?}.", $op, $x)
     };
 }
+
+#[macro_export]
+macro_rules! bail_arithmetic_error {
+    (overflow $x:tt $op:tt $y:tt) => {
+        bail!("ArithmeticError: {:?} `{}` {:?} overflows.", $x, $op, $y)
+    };
+    (overflow $op:tt $x:tt) => {
+        bail!("ArithmeticError: `{}` {:?} overflows.", $op, $x)
+    };
+    (zero $x:tt $op:tt $y:tt) => {
+        bail!(
+            "ArithmeticError: {:?} `{}` {:?} divides by zero.",
+            $x,
+            $op,
+            $y
+        )
+    };
+}
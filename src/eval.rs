@@ -1,10 +1,18 @@
 use crate::builtins;
+use crate::datetime;
 use crate::json::*;
+use crate::net;
 use crate::parser;
 use crate::parser::{cumin::Cumin, entries::*, expr::*, statement::*, typing::*, value::*};
-use crate::{assert_args_eq, assert_args_leq, bail_type_error};
+use crate::{
+    assert_args_eq, assert_args_geq, assert_args_leq, bail_arithmetic_error, bail_type_error,
+};
 use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::RoundingStrategy;
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::env;
 use std::fs::read_to_string;
 use std::path::Path;
@@ -13,7 +21,7 @@ use Statement::*;
 pub fn eval(cumin: Cumin, cd: Option<String>) -> Result<JSON> {
     let mut env = Environ::new(cd);
     let val = eval_cumin(&mut env, &cumin)?;
-    Ok(JSON::from_cumin(val))
+    JSON::from_cumin(val)
 }
 
 fn find(path: String, env: &Environ) -> Option<String> {
@@ -28,22 +36,22 @@ fn find(path: String, env: &Environ) -> Option<String> {
         if let Some(dir) = &env.cd {
             let f = Path::new(&dir).join(f);
             if f.is_file() {
-                return Some(String::from(f.to_str().unwrap()));
+                return f.to_str().map(String::from);
             }
         }
     }
     None
 }
 
-pub fn eval_cumin(mut env: &mut Environ, cumin: &Cumin) -> Result<Value> {
+pub fn eval_cumin(env: &mut Environ, cumin: &Cumin) -> Result<Value> {
     // Hoisting types
     for stmt in cumin.0.iter() {
         match stmt {
             // Hoisting types
-            Type(name, types) => {
+            Type(name, types, refinement) => {
                 let _ = env
                     .types
-                    .insert(name.to_string(), types.iter().cloned().collect());
+                    .insert(name.to_string(), (types.to_vec(), refinement.clone()));
             }
             // Hoisting enums
             Enum(name, variants) => {
@@ -55,42 +63,45 @@ pub fn eval_cumin(mut env: &mut Environ, cumin: &Cumin) -> Result<Value> {
 
     // Hoisting struct
     for stmt in cumin.0.iter() {
-        match stmt {
-            Struct(sname, fields) => {
-                // key duplication check
-                {
-                    let mut used = HashSet::new();
-                    for (name, _, _) in fields.iter() {
-                        if used.contains(&name) {
-                            bail!("Duplicated Key `{}` in struct `{}`", name, sname);
-                        }
-                        used.insert(name);
+        if let Struct(sname, fields, checks) = stmt {
+            // key duplication check
+            {
+                let mut used = HashSet::new();
+                for (name, _, _, _) in fields.iter() {
+                    if used.contains(&name) {
+                        bail!("Duplicated Key `{}` in struct `{}`", name, sname);
                     }
+                    used.insert(name);
                 }
-                let mut simplified_fields = vec![];
-                for (name, typ, default) in fields.iter() {
-                    let simplified = match default {
-                        Some(e) => {
-                            let val = eval_expr(&mut env, &e)?.cast(&typ)?;
-                            (name.to_string(), val.type_of(), Some(Expr::Val(val)))
-                        }
-                        None => (name.to_string(), typ.clone(), None),
-                    };
-                    simplified_fields.push(simplified);
-                }
-                env.structs.insert(sname.clone(), simplified_fields);
             }
-            _ => (),
+            let mut simplified_fields = vec![];
+            for (name, typ, refinement, default) in fields.iter() {
+                let simplified = match default {
+                    Some(e) => {
+                        let path = format!("{}.{}", sname, name);
+                        let val = cast_in(env, eval_expr(env, e)?, typ, &path)?;
+                        let val = refine(env, val, refinement, &path)?;
+                        let typ = val.type_of();
+                        (
+                            name.to_string(),
+                            typ,
+                            refinement.clone(),
+                            Some(Expr::Val(val)),
+                        )
+                    }
+                    None => (name.to_string(), typ.clone(), refinement.clone(), None),
+                };
+                simplified_fields.push(simplified);
+            }
+            env.structs.insert(sname.clone(), simplified_fields);
+            env.checks.insert(sname.clone(), checks.to_vec());
         }
     }
 
     // Hoisting enums
     for stmt in cumin.0.iter() {
-        match stmt {
-            Enum(name, variants) => {
-                env.enums.insert(name.clone(), variants.clone());
-            }
-            _ => (),
+        if let Enum(name, variants) = stmt {
+            env.enums.insert(name.clone(), variants.clone());
         }
     }
 
@@ -101,7 +112,7 @@ pub fn eval_cumin(mut env: &mut Environ, cumin: &Cumin) -> Result<Value> {
                 env.funs
                     .insert(name.clone(), (env.clone(), args.to_vec(), body.clone()));
             }
-            Import(path) => match find(path.to_string(), &env) {
+            Import(path) => match find(path.to_string(), env) {
                 Some(path) => {
                     if env.loaded_modules.contains(&path) {
                         continue;
@@ -109,10 +120,13 @@ pub fn eval_cumin(mut env: &mut Environ, cumin: &Cumin) -> Result<Value> {
                     env.loaded_modules.insert(path.to_string());
 
                     let path = Path::new(&path);
-                    match read_to_string(&path) {
+                    match read_to_string(path) {
                         Ok(content) => match parser::cumin::cumin(&content) {
                             Ok((_, cumin)) => {
-                                let _ = eval_cumin(&mut env, &cumin)?;
+                                let outer = env.module.replace(path.display().to_string());
+                                let result = eval_cumin(env, &cumin);
+                                env.module = outer;
+                                let _ = result?;
                             }
                             err => {
                                 eprintln!("Error in loading {:?}", path);
@@ -129,675 +143,3294 @@ pub fn eval_cumin(mut env: &mut Environ, cumin: &Cumin) -> Result<Value> {
                 }
             },
             Let(id, typ, expr) => {
-                let val = eval_expr(&env, expr)?.cast(typ)?;
+                let val = cast_in(env, eval_expr(env, expr)?, typ, id)?;
                 env.vars.insert(id.clone(), (typ.clone(), val));
             }
+            LetPattern(pat, expr) => {
+                let val = eval_expr(env, expr)?;
+                let mut bindings = vec![];
+                bind(env, pat, val, &mut bindings)?;
+                for (name, val) in bindings {
+                    env.vars.insert(name, (Typing::Any, val));
+                }
+            }
             _ => (),
         }
     }
 
-    eval_expr(&env, &cumin.1)
+    // Checking assertions, with every let in scope
+    for stmt in cumin.0.iter() {
+        if let Assert(pred, operands, message) = stmt {
+            check_assert(env, pred, operands, message)?;
+        }
+    }
+
+    // Running tests, only when asked to
+    if env.tests.is_some() {
+        for stmt in cumin.0.iter() {
+            if let Test(name, body) = stmt {
+                let outcome = TestOutcome {
+                    module: env.module.clone(),
+                    name: name.clone(),
+                    failure: run_test(env, body),
+                };
+                if let Some(tests) = env.tests.as_mut() {
+                    tests.push(outcome);
+                }
+            }
+        }
+    }
+
+    eval_expr(env, &cumin.1)
+}
+
+/// How deeply expressions may nest when evaluated, counting the bodies of the functions
+/// they call, which the parser's own limit does not bound. Sized so that evaluating in
+/// a debug build stays well within a 2 MiB thread, the default for threads other than
+/// `main`.
+pub const MAX_DEPTH: usize = 128;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts one level of expression evaluation for as long as it is alive.
+struct Depth;
+
+impl Depth {
+    fn enter() -> Result<Self> {
+        let depth = DEPTH.with(Cell::get);
+        if depth >= MAX_DEPTH {
+            bail!(
+                "RecursionError: expression nested deeper than {} levels.",
+                MAX_DEPTH
+            );
+        }
+        DEPTH.with(|d| d.set(depth + 1));
+        Ok(Depth)
+    }
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+    }
 }
 
 fn eval_expr(env: &Environ, expr: &Expr) -> Result<Value> {
+    let _depth = Depth::enter()?;
+    // Kept to dispatching alone, so that each level of nesting costs little stack;
+    // the work for each node is done out of line.
     use Expr::*;
-    use Value::*;
     match expr {
-        Val(value) => eval_value(&env, value),
-        Var(v) => match env.vars.get(v) {
-            Some((_, val)) => Ok((*val).clone()),
+        Val(value) => eval_value(env, value),
+        Var(v) => eval_var(env, v),
+        Apply(fname, args, kwargs) => eval_apply(env, fname, args, kwargs),
+        MethodCall(receiver, method, args, kwargs) => {
+            eval_method_call(env, receiver, method, args, kwargs)
+        }
+        AnonymousStruct(items) => eval_anonymous_struct(env, items),
+        StructUpdate(sname, base, kwargs) => eval_struct_update(env, sname, base, kwargs),
+        Spread(parts) => eval_spread(env, parts),
+        Concat(x, y) => binary(env, x, y, |_, a, b| builtins::concat(&[a, b])),
+        Add(x, y) => binary(env, x, y, eval_add),
+        Sub(x, y) => binary(env, x, y, eval_sub),
+        Mul(x, y) => binary(env, x, y, eval_mul),
+        Div(x, y) => binary(env, x, y, eval_div),
+        Mod(x, y) => binary(env, x, y, eval_mod),
+        Pow(x, y) => binary(env, x, y, eval_pow),
+        BitAnd(x, y) => binary(env, x, y, |_, a, b| {
+            eval_bitwise(a, b, "&", |x, y| x & y, |x, y| x & y)
+        }),
+        BitOr(x, y) => binary(env, x, y, |_, a, b| {
+            eval_bitwise(a, b, "|", |x, y| x | y, |x, y| x | y)
+        }),
+        BitXor(x, y) => binary(env, x, y, |_, a, b| {
+            eval_bitwise(a, b, "^", |x, y| x ^ y, |x, y| x ^ y)
+        }),
+        Shl(x, y) => binary(env, x, y, |_, a, b| eval_shl(a, b)),
+        Shr(x, y) => binary(env, x, y, |_, a, b| eval_shr(a, b)),
+        Minus(x) => unary(env, x, |_, a| eval_minus(a)),
+        And(x, y) => binary(env, x, y, |_, a, b| eval_logic(a, b, "and", |x, y| x && y)),
+        Or(x, y) => binary(env, x, y, |_, a, b| eval_logic(a, b, "or", |x, y| x || y)),
+        Xor(x, y) => binary(env, x, y, |_, a, b| eval_logic(a, b, "xor", |x, y| x ^ y)),
+        Not(x) => unary(env, x, |_, a| match a {
+            Value::Bool(x) => Ok(Value::Bool(!x)),
+            x => bail_type_error!(compute "not" x),
+        }),
+        Equal(x, y) => binary(env, x, y, eval_equal),
+        Less(x, y) => binary(env, x, y, |env, a, b| {
+            let ord = a.compare(&b, &env.enums)?;
+            Ok(Value::Bool(ord == std::cmp::Ordering::Less))
+        }),
+        Arrayed(elements) => eval_arrayed(env, elements),
+        Expr::Tuple(elements) => elements
+            .iter()
+            .map(|e| eval_expr(env, e))
+            .collect::<Result<_>>()
+            .map(Value::Tuple),
+        Blocked(inner) => eval_cumin(&mut env.clone(), inner),
+        AsCast(expr, typ) => unary(env, expr, |env, val| eval_as(env, val, typ)),
+        Prop(expr, prop) => unary(env, expr, |_, val| eval_prop(val, prop)),
+        Index(expr, idx) => binary(env, expr, idx, |_, val, idx| eval_index(val, idx)),
+        Interpolated(segments) => eval_interpolated(env, segments),
+        Slice(expr, start, end) => eval_slice(env, expr, start, end),
+    }
+}
+
+fn eval_var(env: &Environ, v: &str) -> Result<Value> {
+    match env.vars.get(v) {
+        Some((_, val)) => Ok((*val).clone()),
+        None => match builtins::constant(v) {
+            Some(val) => Ok(val),
             None => bail!("Undefined variable `{}`.", v),
         },
-        Apply(fname, args, kwargs) => {
-            let values: Vec<Value> = args
-                .iter()
-                .map(|x| eval_expr(&env, &x))
-                .collect::<Result<_>>()?;
-            let kwvalues: HashMap<String, Value> = kwargs
-                .iter()
-                .map(|(name, x)| eval_expr(&env, &x).map(|val| (name.to_string(), val)))
-                .collect::<Result<_>>()?;
-            match fname.as_str() {
-                "Some" => {
-                    assert_args_eq!("Some", values.len(), 1);
-                    let val = values[0].clone();
-                    let typ = val.type_of();
-                    Ok(Optional(typ, Box::new(Some(val))))
-                }
-                "not" => {
-                    assert_args_eq!("not", values.len(), 1);
-                    let e = Not(Box::new(Val(values[0].clone())));
-                    eval_expr(&env, &e)
-                }
-                "concat" => builtins::concat(&values),
-                "reverse" => {
-                    assert_args_eq!("reverse", values.len(), 1);
-                    builtins::reverse(&values[0])
-                }
-                // Struct Apply
-                _ if env.structs.contains_key(fname) => {
-                    let fields = env.structs.get(fname).unwrap();
-                    assert_args_leq!(fname, values.len(), fields.len());
-                    let n = values.len();
-                    let mut items = vec![];
-                    for ((name, typ, _default), value) in fields[..n].iter().zip(values.iter()) {
-                        let val = value.cast(typ)?;
-                        items.push((name.to_string(), val.clone()));
-                    }
-                    for (name, typ, default) in fields[n..].iter() {
-                        if let Some(value) = kwvalues.get(name) {
-                            let val = value.cast(typ)?;
-                            items.push((name.to_string(), val.clone()));
-                        } else if let Some(e) = default {
-                            let value = eval_expr(&env, e)?;
-                            let val = value.cast(typ)?;
-                            items.push((name.to_string(), val.clone()));
-                        } else {
-                            bail!("Not supplied Field `{}` for Struct `{}`", name, fname);
-                        }
-                    }
-                    Ok(Dict(Some(fname.to_string()), Entries::new(items)))
-                }
-                // Type Apply
-                _ if env.types.contains_key(fname) => {
-                    assert_args_eq!(fname, values.len(), 1);
-                    let value = values[0].clone();
-                    let typ = values[0].type_of();
-                    // up-cast
-                    for variant_typ in env.types.get(fname).unwrap().iter() {
-                        if let Ok(val) = value.cast(variant_typ) {
-                            return Ok(Wrapped(
-                                Typing::UserTyping(fname.to_string()),
-                                Box::new(val),
-                            ));
-                        } else {
-                            continue;
-                        }
-                    }
-                    bail!("Cannot up-cast `{:?}` <: `{}`.", typ, fname.to_string());
-                }
-                // Function Apply
-                _ if env.funs.contains_key(fname) => {
-                    let (env_inner, args, body) = env.funs.get(fname).unwrap();
-                    assert_args_leq!(fname, values.len(), args.len());
-                    let mut env_inner = env_inner.clone();
-                    let n = values.len();
-                    for ((name, typ, _default), value) in args[..n].iter().zip(values.iter()) {
-                        let val = value.cast(typ)?;
-                        env_inner.vars.insert(name.to_string(), (typ.clone(), val));
-                    }
-                    for (name, typ, default) in args[n..].iter() {
-                        if let Some(val) = kwvalues.get(name) {
-                            let val = val.cast(typ)?;
-                            env_inner.vars.insert(name.to_string(), (typ.clone(), val));
-                        } else if let Some(e) = default {
-                            let val = eval_expr(&env, &e)?;
-                            let val = val.cast(typ)?;
-                            env_inner.vars.insert(name.to_string(), (typ.clone(), val));
-                        } else {
-                            bail!("Not supplied Arg `{}` for Function `{}`.", name, fname);
-                        }
-                    }
-                    eval_expr(&mut env_inner, body)
-                }
-                _ => bail!("Cannot resolve name `{}`.", fname),
+    }
+}
+
+/// Evaluates `x` and `y`, then applies `op` to their values.
+fn binary(
+    env: &Environ,
+    x: &Expr,
+    y: &Expr,
+    op: impl Fn(&Environ, Value, Value) -> Result<Value>,
+) -> Result<Value> {
+    let a = eval_expr(env, x)?;
+    let b = eval_expr(env, y)?;
+    op(env, a, b)
+}
+
+/// Evaluates `x`, then applies `op` to its value.
+fn unary(env: &Environ, x: &Expr, op: impl Fn(&Environ, Value) -> Result<Value>) -> Result<Value> {
+    let a = eval_expr(env, x)?;
+    op(env, a)
+}
+
+/// `a and b`, `a or b` or `a xor b`
+fn eval_logic(a: Value, b: Value, op: &str, f: fn(bool, bool) -> bool) -> Result<Value> {
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(f(x, y))),
+        (x, y) => bail_type_error!(compute x op y),
+    }
+}
+
+/// `a + b`
+fn eval_add(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(ret) = eval_datetime(&a, &b, "+")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_quantity(&a, &b, "+")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_decimal(&a, &b, "+", |x, y| x.checked_add(y))? {
+        return Ok(ret);
+    }
+    let ret = match (&a, &b) {
+        (Nat(x), Nat(y)) => x.checked_add(*y).map(Nat),
+        (Nat(x), Int(y)) => nat_to_int(*x)?.checked_add(*y).map(Int),
+        (Nat(x), Float(y)) => Some(Float(*x as f64 + y)),
+        (Int(x), Nat(y)) => x.checked_add(nat_to_int(*y)?).map(Int),
+        (Int(x), Int(y)) => x.checked_add(*y).map(Int),
+        (Int(x), Float(y)) => Some(Float(*x as f64 + y)),
+        (Float(x), Nat(y)) => Some(Float(x + *y as f64)),
+        (Float(x), Int(y)) => Some(Float(x + *y as f64)),
+        (Float(x), Float(y)) => Some(Float(x + y)),
+        (Str(x), Str(y)) => {
+            let mut z = x.clone();
+            z.push_str(y);
+            Some(Str(z))
+        }
+        (x, y) => bail_type_error!(compute x "+" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `+` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "+" b),
+    }
+}
+
+/// `a - b`
+fn eval_sub(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(ret) = eval_datetime(&a, &b, "-")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_quantity(&a, &b, "-")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_decimal(&a, &b, "-", |x, y| x.checked_sub(y))? {
+        return Ok(ret);
+    }
+    let ret = match (&a, &b) {
+        (Nat(x), Nat(y)) => {
+            if x >= y {
+                Some(Nat(x - y))
+            } else {
+                nat_to_int(*x)?.checked_sub(nat_to_int(*y)?).map(Int)
             }
         }
-        AnonymousStruct(items) => {
-            let mut values = vec![];
-            // key duplication check
-            {
-                let mut used = HashSet::new();
-                for (name, _, _) in items.iter() {
-                    if used.contains(&name) {
-                        bail!("Duplicated Key `{}` in an AnonymousStruct", name);
-                    }
-                    used.insert(name);
-                }
+        (Nat(x), Int(y)) => nat_to_int(*x)?.checked_sub(*y).map(Int),
+        (Nat(x), Float(y)) => Some(Float(*x as f64 - y)),
+        (Int(x), Nat(y)) => x.checked_sub(nat_to_int(*y)?).map(Int),
+        (Int(x), Int(y)) => x.checked_sub(*y).map(Int),
+        (Int(x), Float(y)) => Some(Float(*x as f64 - y)),
+        (Float(x), Nat(y)) => Some(Float(x - *y as f64)),
+        (Float(x), Int(y)) => Some(Float(x - *y as f64)),
+        (Float(x), Float(y)) => Some(Float(x - y)),
+        (x, y) => bail_type_error!(compute x "-" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `-` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "-" b),
+    }
+}
+
+/// `a * b`
+fn eval_mul(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(ret) = eval_quantity(&a, &b, "*")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_decimal(&a, &b, "*", |x, y| x.checked_mul(y))? {
+        return Ok(ret);
+    }
+    let ret = match (&a, &b) {
+        (Nat(x), Nat(y)) => x.checked_mul(*y).map(Nat),
+        (Nat(x), Int(y)) => nat_to_int(*x)?.checked_mul(*y).map(Int),
+        (Nat(x), Float(y)) => Some(Float(*x as f64 * y)),
+        (Int(x), Nat(y)) => x.checked_mul(nat_to_int(*y)?).map(Int),
+        (Int(x), Int(y)) => x.checked_mul(*y).map(Int),
+        (Int(x), Float(y)) => Some(Float(*x as f64 * y)),
+        (Float(x), Nat(y)) => Some(Float(x * *y as f64)),
+        (Float(x), Int(y)) => Some(Float(x * *y as f64)),
+        (Float(x), Float(y)) => Some(Float(x * y)),
+        (x, y) => bail_type_error!(compute x "*" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `*` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "*" b),
+    }
+}
+
+/// `a / b`
+fn eval_div(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(ret) = eval_quantity(&a, &b, "/")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_decimal(&a, &b, "/", |x, y| {
+        x.checked_div(y)
+            .map(|z| z.round_dp_with_strategy(env.decimal_scale, env.decimal_rounding.strategy()))
+    })? {
+        return Ok(ret);
+    }
+    let ret = match (&a, &b) {
+        (Nat(_), Nat(0)) | (Int(_), Nat(0)) | (Nat(_), Int(0)) | (Int(_), Int(0)) => {
+            bail_arithmetic_error!(zero a "/" b)
+        }
+        (Nat(x), Nat(y)) => x.checked_div(*y).map(Nat),
+        (Nat(x), Int(y)) => nat_to_int(*x)?.checked_div(*y).map(Int),
+        (Nat(x), Float(y)) => Some(Float(*x as f64 / y)),
+        (Int(x), Nat(y)) => x.checked_div(nat_to_int(*y)?).map(Int),
+        (Int(x), Int(y)) => x.checked_div(*y).map(Int),
+        (Int(x), Float(y)) => Some(Float(*x as f64 / y)),
+        (Float(x), Nat(y)) => Some(Float(x / *y as f64)),
+        (Float(x), Int(y)) => Some(Float(x / *y as f64)),
+        (Float(x), Float(y)) => Some(Float(x / y)),
+        (x, y) => bail_type_error!(compute x "/" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `/` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "/" b),
+    }
+}
+
+/// `a % b`
+fn eval_mod(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(ret) = eval_quantity(&a, &b, "%")? {
+        return Ok(ret);
+    }
+    if let Some(ret) = eval_decimal(&a, &b, "%", |x, y| x.checked_rem(y))? {
+        return Ok(ret);
+    }
+    let ret = match (&a, &b) {
+        (Nat(_), Nat(0)) | (Int(_), Nat(0)) | (Nat(_), Int(0)) | (Int(_), Int(0)) => {
+            bail_arithmetic_error!(zero a "%" b)
+        }
+        (Nat(x), Nat(y)) => x.checked_rem(*y).map(Nat),
+        (Nat(x), Int(y)) => nat_to_int(*x)?.checked_rem(*y).map(Int),
+        (Nat(x), Float(y)) => Some(Float(*x as f64 % y)),
+        (Int(x), Nat(y)) => x.checked_rem(nat_to_int(*y)?).map(Int),
+        (Int(x), Int(y)) => x.checked_rem(*y).map(Int),
+        (Int(x), Float(y)) => Some(Float(*x as f64 % y)),
+        (Float(x), Nat(y)) => Some(Float(x % *y as f64)),
+        (Float(x), Int(y)) => Some(Float(x % *y as f64)),
+        (Float(x), Float(y)) => Some(Float(x % y)),
+        (x, y) => bail_type_error!(compute x "%" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `%` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "%" b),
+    }
+}
+
+/// `a ** b`
+fn eval_pow(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    let ret = match (&a, &b) {
+        (Nat(x), Nat(y)) => nat_pow(*x, *y).map(Nat),
+        (Nat(x), Int(y)) => {
+            if *y >= 0 {
+                nat_pow(*x, *y as u128).map(Nat)
+            } else {
+                Some(Float(powi(*x as f64, *y)))
             }
-            for (name, typ, val) in items.iter() {
-                let val = eval_expr(&env, &val)?.cast(typ)?;
-                values.push((name.to_string(), val.clone()));
-            }
-            Ok(Dict(None, Entries::new(values)))
-        }
-        Concat(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            builtins::concat(&vec![a, b])
-        }
-        Add(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Nat(x + y),
-                (Nat(x), Int(y)) => Int(x as i128 + y),
-                (Nat(x), Float(y)) => Float(x as f64 + y),
-                (Int(x), Nat(y)) => Int(x + y as i128),
-                (Int(x), Int(y)) => Int(x + y),
-                (Int(x), Float(y)) => Float(x as f64 + y),
-                (Float(x), Nat(y)) => Float(x + y as f64),
-                (Float(x), Int(y)) => Float(x + y as f64),
-                (Float(x), Float(y)) => Float(x + y),
-                (Str(x), Str(y)) => {
-                    let mut z = x;
-                    z.push_str(&y);
-                    Str(z)
-                }
-                (x, y) => bail_type_error!(compute x "+" y),
-            };
-            Ok(ret)
-        }
-        Sub(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => {
-                    if x >= y {
-                        Nat(x - y)
-                    } else {
-                        Int(x as i128 - y as i128)
-                    }
-                }
-                (Nat(x), Int(y)) => Int(x as i128 - y),
-                (Nat(x), Float(y)) => Float(x as f64 - y),
-                (Int(x), Nat(y)) => Int(x - y as i128),
-                (Int(x), Int(y)) => Int(x - y),
-                (Int(x), Float(y)) => Float(x as f64 - y),
-                (Float(x), Nat(y)) => Float(x - y as f64),
-                (Float(x), Int(y)) => Float(x - y as f64),
-                (Float(x), Float(y)) => Float(x - y),
-                (x, y) => bail_type_error!(compute x "-" y),
-            };
-            Ok(ret)
-        }
-        Mul(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Nat(x * y),
-                (Nat(x), Int(y)) => Int(x as i128 * y),
-                (Nat(x), Float(y)) => Float(x as f64 * y),
-                (Int(x), Nat(y)) => Int(x * y as i128),
-                (Int(x), Int(y)) => Int(x * y),
-                (Int(x), Float(y)) => Float(x as f64 * y),
-                (Float(x), Nat(y)) => Float(x * y as f64),
-                (Float(x), Int(y)) => Float(x * y as f64),
-                (Float(x), Float(y)) => Float(x * y),
-                (x, y) => bail_type_error!(compute x "*" y),
-            };
-            Ok(ret)
-        }
-        Div(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Nat(x / y),
-                (Nat(x), Int(y)) => Int(x as i128 / y),
-                (Nat(x), Float(y)) => Float(x as f64 / y),
-                (Int(x), Nat(y)) => Int(x / y as i128),
-                (Int(x), Int(y)) => Int(x / y),
-                (Int(x), Float(y)) => Float(x as f64 / y),
-                (Float(x), Nat(y)) => Float(x / y as f64),
-                (Float(x), Int(y)) => Float(x / y as f64),
-                (Float(x), Float(y)) => Float(x / y),
-                (x, y) => bail_type_error!(compute x "/" y),
-            };
-            Ok(ret)
-        }
-        Mod(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Nat(x % y),
-                (Nat(x), Int(y)) => Int(x as i128 % y),
-                (Nat(x), Float(y)) => Float(x as f64 % y),
-                (Int(x), Nat(y)) => Int(x % y as i128),
-                (Int(x), Int(y)) => Int(x % y),
-                (Int(x), Float(y)) => Float(x as f64 % y),
-                (Float(x), Nat(y)) => Float(x % y as f64),
-                (Float(x), Int(y)) => Float(x % y as f64),
-                (Float(x), Float(y)) => Float(x % y),
-                (x, y) => bail_type_error!(compute x "%" y),
-            };
-            Ok(ret)
-        }
-        Pow(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Nat(x.pow(y as u32)),
-                (Nat(x), Int(y)) => {
-                    if y >= 0 {
-                        Nat(x.pow(y as u32))
-                    } else {
-                        Float((x as f64).powi(y as i32))
-                    }
-                }
-                (Nat(x), Float(y)) => Float((x as f64).powf(y)),
-                (Int(x), Nat(y)) => Int(x.pow(y as u32)),
-                (Int(x), Int(y)) => {
-                    if y >= 0 {
-                        Int(x.pow(y as u32))
-                    } else {
-                        Float((x as f64).powi(y as i32))
-                    }
-                }
-                (Int(x), Float(y)) => Float((x as f64).powf(y)),
-                (Float(x), Nat(y)) => Float(x.powi(y as i32)),
-                (Float(x), Int(y)) => Float(x.powi(y as i32)),
-                (Float(x), Float(y)) => Float(x.powf(y)),
-                (x, y) => bail_type_error!(compute x "**" y),
-            };
-            Ok(ret)
-        }
-        Minus(x) => {
-            let a = eval_expr(&env, &x)?;
-            let ret = match a {
-                Nat(x) => Int(-(x as i128)),
-                Int(x) => Int(-x),
-                Float(x) => Float(-x),
-                x => bail_type_error!(compute "-" x),
-            };
-            Ok(ret)
-        }
-        And(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Bool(x), Bool(y)) => Bool(x && y),
-                (x, y) => bail_type_error!(compute x "and" y),
-            };
-            Ok(ret)
-        }
-        Or(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Bool(x), Bool(y)) => Bool(x || y),
-                (x, y) => bail_type_error!(compute x "or" y),
-            };
-            Ok(ret)
-        }
-        Xor(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Bool(x), Bool(y)) => Bool(x ^ y),
-                (x, y) => bail_type_error!(compute x "xor" y),
-            };
-            Ok(ret)
         }
-        Not(x) => {
-            let a = eval_expr(&env, &x)?;
-            let ret = match a {
-                Bool(x) => Bool(!x),
-                x => bail_type_error!(compute "not" x),
-            };
-            Ok(ret)
-        }
-        Equal(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let s = a.type_of();
-            let t = b.type_of();
-            if let Some(ty) = Typing::unify(&s, &t) {
-                let a = a.cast(&ty)?;
-                let b = b.cast(&ty)?;
-                Ok(Bool(a == b))
+        (Nat(x), Float(y)) => Some(Float((*x as f64).powf(*y))),
+        (Int(x), Nat(y)) => int_pow(*x, *y).map(Int),
+        (Int(x), Int(y)) => {
+            if *y >= 0 {
+                int_pow(*x, *y as u128).map(Int)
             } else {
-                bail!(
-                    "Cannot compare different type values: {:?} and {:?}",
-                    &a,
-                    &b
-                );
+                Some(Float(powi(*x as f64, *y)))
             }
         }
-        Less(x, y) => {
-            let a = eval_expr(&env, &x)?;
-            let b = eval_expr(&env, &y)?;
-            let ret = match (a, b) {
-                (Nat(x), Nat(y)) => Bool(x < y),
-                (Nat(x), Int(y)) => Bool((x as i128) < y),
-                (Int(x), Nat(y)) => Bool(x < y as i128),
-                (Int(x), Int(y)) => Bool(x < y),
-                (Float(x), Float(y)) => Bool(x < y),
-                (x, y) => bail_type_error!(compute x "<" y),
-            };
-            Ok(ret)
+        (Int(x), Float(y)) => Some(Float((*x as f64).powf(*y))),
+        (Float(x), Nat(y)) => Some(Float(powi(*x, nat_to_int(*y)?))),
+        (Float(x), Int(y)) => Some(Float(powi(*x, *y))),
+        (Float(x), Float(y)) => Some(Float(x.powf(*y))),
+        (x, y) => bail_type_error!(compute x "**" y),
+    };
+    match ret {
+        Some(ret) => check_float(env, ret, || format!("{:?} `**` {:?}", a, b)),
+        None => bail_arithmetic_error!(overflow a "**" b),
+    }
+}
+
+/// `a << b`
+fn eval_shl(a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    let ret = match (a, shift_amount(&b)?) {
+        (Nat(x), n) if x.leading_zeros() >= n => Nat(x << n),
+        (Int(x), n) if (x << n) >> n == x => Int(x << n),
+        (x @ Nat(_), _) | (x @ Int(_), _) => {
+            bail!("ArithmeticError: {:?} << {:?} overflows", x, b)
         }
-        Arrayed(elements) => {
-            let elements: Vec<Value> = elements
-                .iter()
-                .map(|e| eval_expr(&env, &e))
-                .collect::<Result<_>>()?;
-            // type-unification
-            let mut element_type = Typing::Any;
-            for elem in elements.iter() {
-                if let Some(unified) = Typing::unify(&element_type, &elem.type_of()) {
-                    element_type = unified;
-                } else {
-                    bail!("Cannot infer type of Array({:?}); Hint: Array cannot contain values with different types.", &elements);
-                }
-            }
-            let mut values = vec![];
-            for elem in elements.iter() {
-                let val = elem.cast(&element_type)?;
-                values.push(val);
+        (x, _) => bail_type_error!(compute x "<<" b),
+    };
+    Ok(ret)
+}
+
+/// `a >> b`
+fn eval_shr(a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    let ret = match (a, shift_amount(&b)?) {
+        (Nat(x), n) => Nat(x >> n),
+        (Int(x), n) => Int(x >> n),
+        (x, _) => bail_type_error!(compute x ">>" b),
+    };
+    Ok(ret)
+}
+
+/// `-a`
+fn eval_minus(a: Value) -> Result<Value> {
+    use Value::*;
+    let ret = match &a {
+        Nat(x) => 0i128.checked_sub_unsigned(*x).map(Int),
+        Int(x) => x.checked_neg().map(Int),
+        Float(x) => Some(Float(-x)),
+        Decimal(x) => Some(Decimal(-*x)),
+        x => bail_type_error!(compute "-" x),
+    };
+    match ret {
+        Some(ret) => Ok(ret),
+        None => bail_arithmetic_error!(overflow "-" a),
+    }
+}
+
+/// `a == b`
+fn eval_equal(env: &Environ, a: Value, b: Value) -> Result<Value> {
+    use Value::*;
+    if let Some(eq) = a.numeric_eq(&b) {
+        return Ok(Bool(eq));
+    }
+    if let (SemVer(_), SemVer(_) | Str(_)) | (Str(_), SemVer(_)) = (&a, &b) {
+        let ord = a.compare(&b, &env.enums)?;
+        return Ok(Bool(ord == std::cmp::Ordering::Equal));
+    }
+    let s = a.type_of();
+    let t = b.type_of();
+    if let Some(ty) = Typing::unify(&s, &t) {
+        let a = a.cast(&ty)?;
+        let b = b.cast(&ty)?;
+        Ok(Bool(a == b))
+    } else {
+        bail!(
+            "Cannot compare different type values: {:?} and {:?}",
+            &a,
+            &b
+        );
+    }
+}
+
+/// `{{ x = .., y: T = .. }}`
+fn eval_anonymous_struct(env: &Environ, items: &[(String, Typing, Expr)]) -> Result<Value> {
+    use Value::*;
+    let mut values = vec![];
+    // key duplication check
+    {
+        let mut used = HashSet::new();
+        for (name, _, _) in items.iter() {
+            if used.contains(&name) {
+                bail!("Duplicated Key `{}` in an AnonymousStruct", name);
             }
-            Ok(Array(element_type, values))
+            used.insert(name);
         }
-        Expr::Tuple(elements) => {
-            let elements: Vec<Value> = elements
-                .iter()
-                .map(|e| eval_expr(&env, &e))
-                .collect::<Result<_>>()?;
-            Ok(Value::Tuple(elements))
-        }
-        Blocked(inner) => {
-            let mut env_inner: Environ = (*env).clone();
-            eval_cumin(&mut env_inner, &inner)
-        }
-        AsCast(expr, typ) => {
-            let val = eval_expr(&env, &expr)?;
-            val.coerce(typ)
-        }
-        Prop(expr, prop) => {
-            let val = eval_expr(&env, &expr)?;
-            match &val {
-                Dict(_, entries) => {
-                    for (field, inval) in entries.iter() {
-                        if field == prop {
-                            return Ok(inval.clone());
-                        }
+    }
+    for (name, typ, val) in items.iter() {
+        let val = cast_in(env, eval_expr(env, val)?, typ, name)?;
+        values.push((name.to_string(), val.clone()));
+    }
+    Ok(Dict(None, Entries::new(values)))
+}
+
+/// `S { ..base, x = .. }`
+fn eval_struct_update(
+    env: &Environ,
+    sname: &str,
+    base: &Expr,
+    kwargs: &[(String, Expr)],
+) -> Result<Value> {
+    use Value::*;
+    if !env.structs.contains_key(sname) {
+        bail!("Cannot resolve struct `{}`.", sname);
+    }
+    let mut items: Vec<(String, Value)> = match eval_expr(env, base)? {
+        Dict(Some(name), entries) if name == sname => entries.iter().cloned().collect(),
+        other => bail!(
+            "TypeError: `..` in `{} {{ .. }}` needs a {}, not {:?}",
+            sname,
+            sname,
+            other.type_of()
+        ),
+    };
+    let mut used = HashSet::new();
+    for (name, _) in kwargs.iter() {
+        if !used.insert(name) {
+            bail!("Duplicated Key `{}` in an update of `{}`", name, sname);
+        }
+    }
+    let values = kwargs
+        .iter()
+        .map(|(name, e)| Ok((name.to_string(), eval_expr(env, e)?)))
+        .collect::<Result<Vec<_>>>()?;
+    set_fields(env, sname, &mut items, values)?;
+    check_struct(env, sname, &items)?;
+    Ok(Dict(Some(sname.to_string()), Entries::new(items)))
+}
+
+/// `{{ ..a, x = .., ..b }}`, later keys win
+fn eval_spread(env: &Environ, parts: &[Expr]) -> Result<Value> {
+    use Value::*;
+    let mut items: Vec<(String, Value)> = vec![];
+    for part in parts.iter() {
+        let entries = match eval_expr(env, part)? {
+            Dict(_, entries) => entries,
+            other => bail!("TypeError: Cannot spread {:?} into a dict", other.type_of()),
+        };
+        for (name, val) in entries.iter() {
+            match items.iter_mut().find(|item| &item.0 == name) {
+                Some(item) => {
+                    if env.warn_overwrite {
+                        eprintln!(
+                            "Warning: `{}` = {:?} is overwritten with {:?}",
+                            name, item.1, val
+                        );
                     }
-                    bail!("Cannot find property {} in {:?}", prop, &val);
+                    item.1 = val.clone();
                 }
-                _ => bail!("Cannot access properties in {:?}", &val),
+                None => items.push((name.to_string(), val.clone())),
             }
         }
     }
+    Ok(Dict(None, Entries::new(items)))
 }
 
-fn eval_value(env: &Environ, value: &Value) -> Result<Value> {
+/// `[x, y, ..]`, typed by unifying its elements
+fn eval_arrayed(env: &Environ, elements: &[Expr]) -> Result<Value> {
     use Value::*;
-    match value {
-        Env(v, default_value) => match (env.env_vars.get(v), default_value) {
-            (Some(val), _) => Ok(Str(val.to_string())),
-            (None, Some(def)) => Ok(Str(def.to_string())),
-            _ => bail!("Undefined env variable {}", v),
-        },
-        EnumVariant(s, t) => {
-            // check existence
-            if let Some(variants) = env.enums.get(s) {
-                if variants.iter().any(|v| v == t) {
-                    Ok(EnumVariant(s.to_string(), t.to_string()))
-                } else {
-                    bail!("Enum {} doesnt have {}", s, t)
-                }
-            } else {
-                bail!("Not found Enum {}", s)
-            }
+    let elements: Vec<Value> = elements
+        .iter()
+        .map(|e| eval_expr(env, e))
+        .collect::<Result<_>>()?;
+    // type-unification
+    let mut element_type = Typing::Any;
+    for elem in elements.iter() {
+        if let Some(unified) = Typing::unify(&element_type, &elem.type_of()) {
+            element_type = unified;
+        } else {
+            bail!("Cannot infer type of Array({:?}); Hint: Array cannot contain values with different types.", &elements);
         }
-        _ => Ok(value.clone()),
     }
+    let mut values = vec![];
+    for elem in elements.iter() {
+        let val = elem.cast(&element_type)?;
+        values.push(val);
+    }
+    Ok(Array(element_type, values))
 }
 
-#[derive(Clone)]
-pub struct Environ {
-    cd: Option<String>,
-    wasm: bool,
-    types: HashMap<String, Vec<Typing>>,
-    structs: HashMap<String, Vec<(String, Typing, Option<Expr>)>>,
-    enums: HashMap<String, Vec<String>>,
-    vars: HashMap<String, (Typing, Value)>,
-    env_vars: HashMap<String, String>,
-    loaded_modules: HashSet<String>,
-    funs: HashMap<String, (Environ, Vec<(String, Typing, Option<Expr>)>, Expr)>,
+/// `val as typ`
+fn eval_as(env: &Environ, val: Value, typ: &Typing) -> Result<Value> {
+    if let Typing::Patch(sname) = typ {
+        return cast_in(env, val, typ, sname);
+    }
+    let ret = val.coerce(typ)?;
+    check_float(env, ret, || format!("{:?} as {:?}", val, typ))
 }
 
-impl Environ {
-    pub fn new(cd: Option<String>) -> Self {
-        Self {
-            cd,
-            wasm: false,
-            types: HashMap::new(),
-            structs: HashMap::new(),
-            enums: HashMap::new(),
-            env_vars: env::vars().collect(),
-            vars: HashMap::new(),
-            loaded_modules: HashSet::new(),
-            funs: HashMap::new(),
+/// `val.prop`
+fn eval_prop(val: Value, prop: &str) -> Result<Value> {
+    use Value::*;
+    match &val {
+        Dict(_, entries) => {
+            for (field, inval) in entries.iter() {
+                if field == prop {
+                    return Ok(inval.clone());
+                }
+            }
+            bail!("Cannot find property {} in {:?}", prop, &val);
         }
-    }
-    pub fn wasm() -> Self {
-        Self {
-            cd: None,
-            wasm: true,
-            types: HashMap::new(),
-            structs: HashMap::new(),
-            enums: HashMap::new(),
-            env_vars: HashMap::new(),
-            vars: HashMap::new(),
-            loaded_modules: HashSet::new(),
-            funs: HashMap::new(),
+        // an unset field is an error rather than None, which a set Option field can be
+        Wrapped(Typing::Patch(sname), patch) => match patch.as_ref() {
+            Dict(_, entries) => match entries.iter().find(|(field, _)| field == prop) {
+                Some((_, inval)) => Ok(inval.clone()),
+                None => bail!("Patch<{}> does not set `{}`", sname, prop),
+            },
+            _ => bail!("Cannot access properties in {:?}", &val),
+        },
+        Value::Tuple(elems) if prop.chars().all(|c| c.is_ascii_digit()) => {
+            let i = seq_index(&Nat(prop.parse().unwrap_or(u128::MAX)), elems.len())?;
+            Ok(elems[i].clone())
         }
+        SemVer(v) => match prop {
+            "major" => Ok(Nat(v.major as u128)),
+            "minor" => Ok(Nat(v.minor as u128)),
+            "patch" => Ok(Nat(v.patch as u128)),
+            _ => bail!("Cannot find property {} in {:?}", prop, &val),
+        },
+        Cidr(_) | SocketAddr(_) | Url(_) => match net::component(&val, prop) {
+            Some(ret) => Ok(ret),
+            None => bail!("Cannot find property {} in {:?}", prop, &val),
+        },
+        Date(_) | Time(_) | DateTime(_) => match datetime::component(&val, prop) {
+            Some(ret) => Ok(ret),
+            None => bail!("Cannot find property {} in {:?}", prop, &val),
+        },
+        _ => bail!("Cannot access properties in {:?}", &val),
     }
 }
 
-#[cfg(test)]
-mod test_eval_from_parse {
-    use crate::eval::eval;
-    use crate::json::JSON;
-    use crate::parser::cumin::cumin;
+/// `val[idx]`
+fn eval_index(val: Value, idx: Value) -> Result<Value> {
+    use Value::*;
+    match val {
+        Array(_, elems) | Value::Tuple(elems) => {
+            let i = seq_index(&idx, elems.len())?;
+            Ok(elems[i].clone())
+        }
+        Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let i = seq_index(&idx, chars.len())?;
+            Ok(Str(chars[i].to_string()))
+        }
+        x => bail_type_error!(compute x "[]" idx),
+    }
+}
 
-    macro_rules! assert_eval {
-        ($code:expr, $json:expr) => {
-            assert_eq!(eval(cumin($code).unwrap().1, None).unwrap(), $json);
-        };
+/// `f"..{e:spec}.."`
+fn eval_interpolated(env: &Environ, segments: &[Segment]) -> Result<Value> {
+    use Value::*;
+    let mut r = String::new();
+    for seg in segments.iter() {
+        match seg {
+            Segment::Lit(s) => r.push_str(s),
+            Segment::Expr(e, spec) => {
+                let val = eval_expr(env, e)?;
+                r.push_str(&builtins::format(&val, spec)?);
+            }
+        }
     }
+    Ok(Str(r))
+}
 
-    #[test]
-    fn test_numbers() {
+/// `val[start..end]`
+fn eval_slice(
+    env: &Environ,
+    expr: &Expr,
+    start: &Option<Box<Expr>>,
+    end: &Option<Box<Expr>>,
+) -> Result<Value> {
+    let val = eval_expr(env, expr)?;
+    let start = start.as_ref().map(|e| eval_expr(env, e)).transpose()?;
+    let end = end.as_ref().map(|e| eval_expr(env, e)).transpose()?;
+    use Value::*;
+    match val {
+        Array(typ, elems) => {
+            let (i, j) = seq_range(start, end, elems.len())?;
+            Ok(Array(typ, elems[i..j].to_vec()))
+        }
+        Value::Tuple(elems) => {
+            let (i, j) = seq_range(start, end, elems.len())?;
+            Ok(Value::Tuple(elems[i..j].to_vec()))
+        }
+        Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let (i, j) = seq_range(start, end, chars.len())?;
+            Ok(Str(chars[i..j].iter().collect()))
+        }
+        x => bail_type_error!(compute x "[..]" start),
+    }
+}
+
+/// Runs the `check` invariants of struct `sname` over its resolved fields, and reports
+/// every violated one.
+fn check_struct(env: &Environ, sname: &str, items: &[(String, Value)]) -> Result<()> {
+    let checks = match env.checks.get(sname) {
+        Some(checks) if !checks.is_empty() => checks,
+        _ => return Ok(()),
+    };
+    let mut env_inner = env.clone();
+    for (name, val) in items.iter() {
+        env_inner
+            .vars
+            .insert(name.to_string(), (val.type_of(), val.clone()));
+    }
+    let mut violations = vec![];
+    for ((pred, source), message) in checks.iter() {
+        let violation = match eval_expr(&env_inner, pred) {
+            Ok(Value::Bool(true)) => continue,
+            Ok(Value::Bool(false)) => match eval_expr(&env_inner, message)? {
+                Value::Str(message) => format!("{} (`{}`)", message, source),
+                other => bail!("Check message must be String, not {:?}", other),
+            },
+            Ok(other) => format!("`{}` is {:?}, not Bool", source, other),
+            Err(err) => format!("`{}` cannot be checked ({})", source, err),
+        };
+        violations.push(violation);
+    }
+    if !violations.is_empty() {
+        bail!(
+            "CheckError: {} violates {} check(s):\n  - {}",
+            sname,
+            violations.len(),
+            violations.join("\n  - ")
+        );
+    }
+    Ok(())
+}
+
+/// How `merge` combines an array with the array overlaid on it.
+#[derive(Debug, Clone, PartialEq)]
+enum ArrayMerge {
+    Replace,
+    Append,
+    /// Deep-merges the dicts with equal values of the key, and appends the others.
+    ByKey(String),
+}
+
+impl ArrayMerge {
+    /// `[strategy [, key]]` of `merge(base, overlay, ..)`
+    fn from_args(args: &[Value]) -> Result<Self> {
+        let key = match args.get(1) {
+            Some(Value::Str(key)) => Some(key.to_string()),
+            Some(other) => bail!("TypeError: key of `merge` must be String, not {:?}", other),
+            None => None,
+        };
+        match (args.first(), key) {
+            (None, None) => Ok(ArrayMerge::Replace),
+            (Some(Value::Str(s)), None) if s == "replace" => Ok(ArrayMerge::Replace),
+            (Some(Value::Str(s)), None) if s == "append" => Ok(ArrayMerge::Append),
+            (Some(Value::Str(s)), Some(key)) if s == "merge" => Ok(ArrayMerge::ByKey(key)),
+            (Some(Value::Str(s)), None) if s == "merge" => {
+                bail!("ArgumentError: `merge` of arrays needs a key, as merge(a, b, \"merge\", \"name\")")
+            }
+            (Some(strategy), _) => bail!(
+                "ArgumentError: unknown array strategy {:?} for `merge` (expected \"replace\", \"append\" or \"merge\")",
+                strategy
+            ),
+            (None, Some(_)) => unreachable!(),
+        }
+    }
+}
+
+/// Deep-merges `overlay` into `base`: dicts key by key, arrays by `arrays`,
+/// and any other value is replaced by the overlay of the same type.
+/// A merged struct keeps its name, field types, refinements and checks.
+fn merge(
+    env: &Environ,
+    base: &Value,
+    overlay: &Value,
+    arrays: &ArrayMerge,
+    path: &str,
+) -> Result<Value> {
+    use Value::*;
+    let at = |key: &str| match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+    let mismatch = || {
+        let kind = |val: &Value| match val {
+            Dict(None, _) => "Dict".to_string(),
+            _ => format!("{:?}", val.type_of()),
+        };
+        let at = match path {
+            "" => String::new(),
+            _ => format!(" at `{}`", path),
+        };
+        anyhow!(
+            "TypeError: merge cannot change {} to {}{}",
+            kind(base),
+            kind(overlay),
+            at
+        )
+    };
+    // the merged value keeps a type both sides fit, such as Int for Nat and Int; a struct
+    // field is then checked against the type it declares
+    let unified = |val: Value| {
+        let typ = Typing::unify(&base.type_of(), &val.type_of()).ok_or_else(mismatch)?;
+        val.cast(&typ).map_err(|_| mismatch())
+    };
+    match (base, overlay) {
+        (Dict(name, xs), Dict(overlay_name, ys)) => {
+            if overlay_name.is_some() && overlay_name != name {
+                return Err(mismatch());
+            }
+            let fields = name.as_ref().and_then(|name| env.structs.get(name));
+            let mut items: Vec<(String, Value)> = xs.iter().cloned().collect();
+            for (key, y) in ys.iter() {
+                let item = items.iter_mut().find(|item| &item.0 == key);
+                match (item, fields) {
+                    (Some(item), Some(fields)) => {
+                        let (_, typ, refinement, _) =
+                            fields.iter().find(|field| &field.0 == key).unwrap();
+                        let val = merge(env, &item.1, y, arrays, &at(key))?;
+                        let val = val.cast(typ).map_err(|_| {
+                            anyhow!(
+                                "TypeError: merge cannot change {:?} to {:?} at `{}`",
+                                typ,
+                                val.type_of(),
+                                at(key)
+                            )
+                        })?;
+                        item.1 = refine(env, val, refinement, &at(key))?;
+                    }
+                    (Some(item), None) => item.1 = merge(env, &item.1, y, arrays, &at(key))?,
+                    (None, Some(_)) => bail!(
+                        "Struct `{}` has no Field `{}`",
+                        name.as_ref().unwrap(),
+                        at(key)
+                    ),
+                    (None, None) => items.push((key.to_string(), y.clone())),
+                }
+            }
+            if let Some(name) = name {
+                check_struct(env, name, &items)?;
+            }
+            Ok(Dict(name.clone(), Entries::new(items)))
+        }
+        (Dict(_, _), _) | (_, Dict(_, _)) if base != &Null => Err(mismatch()),
+        (Array(_, xs), Array(_, ys)) => {
+            let merged = match arrays {
+                ArrayMerge::Replace => overlay.clone(),
+                ArrayMerge::Append => builtins::concat(&[base.clone(), overlay.clone()])?,
+                ArrayMerge::ByKey(key) => {
+                    let key_of = |val: &Value, i: usize, side: &str| match val {
+                        Dict(_, entries) => match entries.iter().find(|item| &item.0 == key) {
+                            Some((_, k)) => Ok(k.clone()),
+                            None => bail!(
+                                "TypeError: {} `{}[{}]` has no key `{}` to merge by",
+                                side,
+                                path,
+                                i,
+                                key
+                            ),
+                        },
+                        _ => bail!(
+                            "TypeError: {} `{}[{}]` is {:?}, not a dict to merge by `{}`",
+                            side,
+                            path,
+                            i,
+                            val.type_of(),
+                            key
+                        ),
+                    };
+                    let keys = xs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, x)| key_of(x, i, "base"))
+                        .collect::<Result<Vec<_>>>()?;
+                    let mut elems = xs.clone();
+                    for (j, y) in ys.iter().enumerate() {
+                        let k = key_of(y, j, "overlay")?;
+                        match keys.iter().position(|x| x == &k) {
+                            Some(i) => {
+                                let at = format!("{}[{}]", path, i);
+                                elems[i] = merge(env, &xs[i], y, arrays, &at)?;
+                            }
+                            None => elems.push(y.clone()),
+                        }
+                    }
+                    Array(Typing::Any, elems)
+                }
+            };
+            unified(merged)
+        }
+        (Null, _) => Ok(overlay.clone()),
+        _ => unified(overlay.clone()),
+    }
+}
+
+/// Merges `overrides` into `base`, an Array of a struct, matching elements by the field `key`:
+/// a match is deep-merged, or removed when the override has `_delete = true`,
+/// and an override without a match is appended as a new struct. An empty base takes the
+/// struct of its overrides.
+fn merge_by(env: &Environ, base: &Value, overrides: &Value, key: &Value) -> Result<Value> {
+    use Value::*;
+    let key = match key {
+        Str(key) => key,
+        other => bail!(
+            "TypeError: key of `merge_by` must be String, not {:?}",
+            other
+        ),
+    };
+    let (otyp, ys) = match overrides {
+        Array(otyp, ys) => (otyp, ys),
+        other => bail!(
+            "TypeError: overrides of merge_by must be an Array, not {:?}",
+            other.type_of()
+        ),
+    };
+    let (sname, xs) = match base {
+        Array(Typing::UserTyping(sname), xs) if env.structs.contains_key(sname) => {
+            (Some(sname.to_string()), xs)
+        }
+        // an empty base takes the struct of its overrides, or else holds plain dicts
+        Array(_, xs) if xs.is_empty() => (struct_of_overrides(env, otyp, ys), xs),
+        other => bail!(
+            "TypeError: merge_by needs an Array of a struct, not {:?}",
+            other.type_of()
+        ),
+    };
+    let key_typ = match &sname {
+        Some(sname) => match env.structs[sname].iter().find(|field| &field.0 == key) {
+            Some((_, typ, _, _)) => Some(typ),
+            None => bail!("Struct `{}` has no Field `{}` to merge by", sname, key),
+        },
+        None => None,
+    };
+    let key_of = |entries: &Entries, at: &str| match entries.iter().find(|item| &item.0 == key) {
+        Some((_, k)) => match key_typ {
+            Some(key_typ) => k.cast(key_typ).map_err(|_| {
+                anyhow!(
+                    "TypeError: merge_by key `{}` of {} is {:?}, not {:?}",
+                    key,
+                    at,
+                    k.type_of(),
+                    key_typ
+                )
+            }),
+            None => Ok(k.clone()),
+        },
+        None => bail!("merge_by key `{}` is missing in {}", key, at),
+    };
+    let mut elems = xs.clone();
+    for (j, y) in ys.iter().enumerate() {
+        let at = format!("overrides[{}]", j);
+        let entries = match y {
+            Dict(None, entries) => entries.clone(),
+            Dict(Some(name), entries) if sname.as_ref() == Some(name) => entries.clone(),
+            Wrapped(Typing::Patch(name), patch) if sname.as_ref() == Some(name) => match &**patch {
+                Dict(_, entries) => entries.clone(),
+                _ => unreachable!(),
+            },
+            other => bail!(
+                "TypeError: {} of merge_by by `{}` must be {}, not {:?}",
+                at,
+                key,
+                match &sname {
+                    Some(sname) => format!("a {} or its Patch", sname),
+                    None => "a Dict".to_string(),
+                },
+                other.type_of()
+            ),
+        };
+        let k = key_of(&entries, &at)?;
+        let delete = match entries.iter().find(|item| item.0 == "_delete") {
+            Some((_, Bool(delete))) => *delete,
+            Some((_, other)) => bail!(
+                "TypeError: `_delete` of {} must be Bool, not {:?}",
+                at,
+                other
+            ),
+            None => false,
+        };
+        let entries: Vec<(String, Value)> = entries
+            .iter()
+            .filter(|item| item.0 != "_delete")
+            .cloned()
+            .collect();
+        let position = elems.iter().position(|x| match x {
+            Dict(_, xs) => xs.iter().any(|item| &item.0 == key && item.1 == k),
+            _ => false,
+        });
+        match position {
+            Some(i) if delete => {
+                elems.remove(i);
+            }
+            Some(i) => {
+                let overlay = Dict(None, Entries::new(entries));
+                elems[i] = merge(
+                    env,
+                    &elems[i],
+                    &overlay,
+                    &ArrayMerge::Replace,
+                    &format!("[{}]", i),
+                )?;
+            }
+            None if delete => bail!(
+                "merge_by cannot delete {} = {:?} of {}, which is not in base",
+                key,
+                k,
+                at
+            ),
+            None => match &sname {
+                Some(sname) => {
+                    let mut items = vec![];
+                    set_fields(env, sname, &mut items, entries)?;
+                    let kwargs = items
+                        .into_iter()
+                        .map(|(name, val)| (name, Expr::Val(val)))
+                        .collect();
+                    elems.push(eval_expr(
+                        env,
+                        &Expr::Apply(sname.to_string(), vec![], kwargs),
+                    )?);
+                }
+                None => elems.push(Dict(None, Entries::new(entries))),
+            },
+        }
+    }
+    Ok(Array(sname.map_or(Typing::Any, Typing::UserTyping), elems))
+}
+
+/// The struct which the overrides of `merge_by` are annotated with, or the first of them is.
+fn struct_of_overrides(env: &Environ, otyp: &Typing, ys: &[Value]) -> Option<String> {
+    let name = match (otyp, ys.first()) {
+        (Typing::UserTyping(name), _) | (Typing::Patch(name), _) => name,
+        (_, Some(Value::Dict(Some(name), _))) => name,
+        (_, Some(Value::Wrapped(Typing::Patch(name), _))) => name,
+        _ => return None,
+    };
+    if env.structs.contains_key(name) {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+/// A test passes when its body evaluates to true, or to nothing with every `assert` holding.
+fn run_test(env: &Environ, body: &Cumin) -> Option<String> {
+    let mut env_inner = env.clone();
+    env_inner.tests = None;
+    // a body without a final expression, such as only asserts, passes when they hold
+    let statements_only = body.1 == Expr::Val(Value::Null);
+    match eval_cumin(&mut env_inner, body) {
+        Ok(Value::Bool(true)) => None,
+        Ok(Value::Null) if statements_only => None,
+        Ok(Value::Bool(false)) => Some("test evaluated to false".to_string()),
+        Ok(other) => Some(format!("TypeError: test is {:?}, not Bool", other)),
+        Err(err) => Some(err.to_string()),
+    }
+}
+
+/// Destructures `val` by `pat`, pushing each bound name and its value to `bindings`.
+fn bind(
+    env: &Environ,
+    pat: &Pattern,
+    val: Value,
+    bindings: &mut Vec<(String, Value)>,
+) -> Result<()> {
+    let val = match val {
+        Value::Wrapped(Typing::UserTyping(_), inner) => *inner,
+        val => val,
+    };
+    match (pat, val) {
+        (Pattern::Ignore, _) => {}
+        (Pattern::Bind(name), val) => {
+            if bindings.iter().any(|(bound, _)| bound == name) {
+                bail!("Duplicated binding `{}` in a pattern", name);
+            }
+            bindings.push((name.to_string(), val));
+        }
+        (Pattern::Tuple(items), Value::Tuple(elems)) => {
+            if items.len() != elems.len() {
+                bail!(
+                    "TypeError: `{}` binds {} elements, but the Tuple has {}",
+                    pat,
+                    items.len(),
+                    elems.len()
+                );
+            }
+            for (item, elem) in items.iter().zip(elems) {
+                bind(env, item, elem, bindings)?;
+            }
+        }
+        (Pattern::Array(items, rest), Value::Array(typ, mut elems)) => {
+            match rest {
+                None if items.len() != elems.len() => bail!(
+                    "TypeError: `{}` binds {} elements, but the Array has {}",
+                    pat,
+                    items.len(),
+                    elems.len()
+                ),
+                Some(_) if items.len() > elems.len() => bail!(
+                    "TypeError: `{}` binds at least {} elements, but the Array has {}",
+                    pat,
+                    items.len(),
+                    elems.len()
+                ),
+                _ => {}
+            }
+            let tail = elems.split_off(items.len());
+            for (item, elem) in items.iter().zip(elems) {
+                bind(env, item, elem, bindings)?;
+            }
+            if let Some(rest) = rest {
+                bind(
+                    env,
+                    &Pattern::Bind(rest.to_string()),
+                    Value::Array(typ, tail),
+                    bindings,
+                )?;
+            }
+        }
+        (Pattern::Dict(keys), Value::Dict(_, entries)) => {
+            for key in keys.iter() {
+                match entries.iter().find(|(name, _)| name == key) {
+                    Some((_, val)) => {
+                        bind(env, &Pattern::Bind(key.to_string()), val.clone(), bindings)?
+                    }
+                    None => bail!(
+                        "TypeError: `{}` binds `{}`, but the Dict has no such key",
+                        pat,
+                        key
+                    ),
+                }
+            }
+        }
+        (Pattern::Struct(sname, names, rest), Value::Dict(Some(name), entries))
+            if &name == sname =>
+        {
+            let fields = match env.structs.get(sname) {
+                Some(fields) => fields,
+                None => bail!("Cannot resolve struct `{}`.", sname),
+            };
+            for key in names.iter() {
+                if !fields.iter().any(|(field, _, _, _)| field == key) {
+                    bail!("Struct `{}` has no Field `{}`", sname, key);
+                }
+            }
+            let unbound: Vec<&str> = fields
+                .iter()
+                .map(|(field, _, _, _)| field.as_str())
+                .filter(|field| !names.iter().any(|name| name == field))
+                .collect();
+            if !rest && !unbound.is_empty() {
+                bail!(
+                    "TypeError: `{}` does not bind {} of {}; end it with `..` to ignore them",
+                    pat,
+                    unbound.join(", "),
+                    sname
+                );
+            }
+            let dict = Pattern::Dict(names.to_vec());
+            bind(env, &dict, Value::Dict(Some(name), entries), bindings)?;
+        }
+        (pat, val) => {
+            let expected = match pat {
+                Pattern::Tuple(_) => "a Tuple".to_string(),
+                Pattern::Array(_, _) => "an Array".to_string(),
+                Pattern::Dict(_) => "a Dict".to_string(),
+                Pattern::Struct(sname, _, _) => format!("a {}", sname),
+                Pattern::Bind(_) | Pattern::Ignore => unreachable!(),
+            };
+            let actual = match val {
+                Value::Dict(None, _) => "Dict".to_string(),
+                Value::Dict(Some(name), _) => name,
+                val => format!("{:?}", val.type_of()),
+            };
+            bail!("TypeError: `{}` needs {}, not {}", pat, expected, actual);
+        }
+    }
+    Ok(())
+}
+
+/// Sets the fields of struct `sname` in `items` to `values`, each cast to its field type and refined.
+fn set_fields(
+    env: &Environ,
+    sname: &str,
+    items: &mut Vec<(String, Value)>,
+    values: Vec<(String, Value)>,
+) -> Result<()> {
+    let fields = match env.structs.get(sname) {
+        Some(fields) => fields,
+        None => bail!("Cannot resolve struct `{}`.", sname),
+    };
+    for (name, val) in values {
+        let (typ, refinement) = match fields.iter().find(|field| field.0 == name) {
+            Some((_, typ, refinement, _)) => (typ, refinement),
+            None => bail!("Struct `{}` has no Field `{}`", sname, name),
+        };
+        let path = format!("{}.{}", sname, name);
+        let val = cast_in(env, val, typ, &path)?;
+        let val = refine(env, val, refinement, &path)?;
+        match items.iter_mut().find(|item| item.0 == name) {
+            Some(item) => item.1 = val,
+            None => items.push((name, val)),
+        }
+    }
+    Ok(())
+}
+
+/// Casts `val` to `typ`; a dict cast to `Patch<S>` is checked against the fields of struct `S`,
+/// and a value cast to a type alias is up-cast into it; `path` names what is cast in errors.
+fn cast_in(env: &Environ, val: Value, typ: &Typing, path: &str) -> Result<Value> {
+    match (val, typ) {
+        (Value::Dict(name, entries), Typing::Patch(sname))
+            if name.is_none() || name.as_ref() == Some(sname) =>
+        {
+            let mut items = vec![];
+            set_fields(env, sname, &mut items, entries.iter().cloned().collect())?;
+            Ok(Value::Wrapped(
+                typ.clone(),
+                Box::new(Value::Dict(None, Entries::new(items))),
+            ))
+        }
+        (val, Typing::UserTyping(tname))
+            if env.types.contains_key(tname) && val.type_of() != *typ =>
+        {
+            up_cast(env, &val, tname, path)
+        }
+        (val, typ) => match val.cast(typ) {
+            Ok(val) => Ok(val),
+            Err(err) => bail!("TypeError: {}: {}", path, err),
+        },
+    }
+}
+
+/// Wraps `value` in the type alias `tname`, as the first of its variants that it casts to;
+/// `path` names what is checked by its `where`.
+fn up_cast(env: &Environ, value: &Value, tname: &str, path: &str) -> Result<Value> {
+    let (variants, refinement) = env.types.get(tname).unwrap();
+    for variant_typ in variants.iter() {
+        if let Ok(val) = value.cast(variant_typ) {
+            let val = refine(env, val, refinement, path)?;
+            return Ok(Value::Wrapped(
+                Typing::UserTyping(tname.to_string()),
+                Box::new(val),
+            ));
+        }
+    }
+    bail!("Cannot up-cast `{:?}` <: `{}`.", value.type_of(), tname);
+}
+
+/// Sets the fields of `base`, a struct, which `patch` has.
+fn apply_patch(env: &Environ, base: &Value, patch: &Value) -> Result<Value> {
+    let (sname, mut items) = match base {
+        Value::Dict(Some(sname), entries) => (sname, entries.iter().cloned().collect()),
+        _ => bail!(
+            "TypeError: apply_patch needs a struct to patch, not {:?}",
+            base.type_of()
+        ),
+    };
+    let patch = match cast_in(env, patch.clone(), &Typing::Patch(sname.to_string()), sname)? {
+        Value::Wrapped(_, patch) => *patch,
+        other => bail!("TypeError: {:?} cannot patch {}", other.type_of(), sname),
+    };
+    if let Value::Dict(_, entries) = patch {
+        set_fields(env, sname, &mut items, entries.iter().cloned().collect())?;
+    }
+    check_struct(env, sname, &items)?;
+    Ok(Value::Dict(Some(sname.to_string()), Entries::new(items)))
+}
+
+/// Fails on a false `assert` with its source, the values of the non-literal
+/// operands of a comparison, and its message.
+fn check_assert(
+    env: &Environ,
+    pred: &Predicate,
+    operands: &Option<Operands>,
+    message: &Option<Expr>,
+) -> Result<()> {
+    let (pred, source) = pred;
+    match eval_expr(env, pred)? {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => (),
+        other => bail!("TypeError: assert `{}` is {:?}, not Bool", source, other),
+    }
+    let mut failure = format!("assert failed: {}", source);
+    let comparison = match pred {
+        Expr::Not(e) => e.as_ref(),
+        e => e,
+    };
+    if let (Some(operands), Expr::Equal(x, y) | Expr::Less(x, y)) = (operands, comparison) {
+        let (left, right) = match operands {
+            Operands::InOrder => (x, y),
+            Operands::Swapped => (y, x),
+        };
+        let mut shown = vec![];
+        for (side, e) in [("left", left), ("right", right)].iter() {
+            if !matches!(e.as_ref(), Expr::Val(_)) {
+                let val = eval_expr(env, e)?;
+                let val = match JSON::from_cumin(val.clone()) {
+                    Ok(json) => json.stringify(),
+                    Err(_) => format!("{:?}", val),
+                };
+                shown.push(format!("{} = {}", side, val));
+            }
+        }
+        if !shown.is_empty() {
+            failure = format!("{} ({})", failure, shown.join(", "));
+        }
+    }
+    if let Some(message) = message {
+        match eval_expr(env, message)? {
+            Value::Str(message) => failure = format!("{}: {}", failure, message),
+            other => bail!("Assert message must be String, not {:?}", other),
+        }
+    }
+    bail!(failure)
+}
+
+/// Checks the refinement `where` predicate of a field or type, reading `val` as `self`;
+/// `path` names what is checked in the error.
+fn refine(env: &Environ, val: Value, refinement: &Option<Predicate>, path: &str) -> Result<Value> {
+    let (pred, source) = match refinement {
+        Some(refinement) => refinement,
+        None => return Ok(val),
+    };
+    let mut env_inner = env.clone();
+    env_inner
+        .vars
+        .insert("self".to_string(), (val.type_of(), val.clone()));
+    match eval_expr(&env_inner, pred) {
+        Ok(Value::Bool(true)) => Ok(val),
+        Ok(Value::Bool(false)) => bail!(
+            "RefinementError: {} = {:?} violates `where {}`",
+            path,
+            val,
+            source
+        ),
+        Ok(other) => bail!(
+            "RefinementError: `where {}` of {} is {:?}, not Bool",
+            source,
+            path,
+            other
+        ),
+        Err(err) => bail!(
+            "RefinementError: `where {}` of {} cannot be checked ({})",
+            source,
+            path,
+            err
+        ),
+    }
+}
+
+fn eval_bitwise(
+    a: Value,
+    b: Value,
+    op: &str,
+    nat: fn(u128, u128) -> u128,
+    int: fn(i128, i128) -> i128,
+) -> Result<Value> {
+    use Value::*;
+    let ret = match (a, b) {
+        (Nat(x), Nat(y)) => Nat(nat(x, y)),
+        (Nat(x), Int(y)) => Int(int(nat_to_int(x)?, y)),
+        (Int(x), Nat(y)) => Int(int(x, nat_to_int(y)?)),
+        (Int(x), Int(y)) => Int(int(x, y)),
+        (x, y) => bail_type_error!(compute x op y),
+    };
+    Ok(ret)
+}
+
+/// Calendar arithmetic: Date, Time and DateTime move by a Duration, and the difference
+/// of two of a kind is a Duration. Dates move by whole days only; Time wraps around midnight.
+fn eval_datetime(a: &Value, b: &Value, op: &str) -> Result<Option<Value>> {
+    use Value::*;
+    let ret = match (op, a, b) {
+        ("+", DateTime(x), Duration(d)) | ("+", Duration(d), DateTime(x)) => {
+            x.checked_add_signed(datetime::to_delta(*d)?).map(DateTime)
+        }
+        ("-", DateTime(x), Duration(d)) => {
+            x.checked_sub_signed(datetime::to_delta(*d)?).map(DateTime)
+        }
+        ("+", Date(x), Duration(d)) | ("+", Duration(d), Date(x)) => {
+            x.checked_add_signed(datetime::to_days(*d)?).map(Date)
+        }
+        ("-", Date(x), Duration(d)) => x.checked_sub_signed(datetime::to_days(*d)?).map(Date),
+        ("+", Time(x), Duration(d)) | ("+", Duration(d), Time(x)) => {
+            Some(Time(x.overflowing_add_signed(datetime::to_delta(*d)?).0))
+        }
+        ("-", Time(x), Duration(d)) => {
+            Some(Time(x.overflowing_sub_signed(datetime::to_delta(*d)?).0))
+        }
+        ("-", DateTime(x), DateTime(y)) => Some(Duration(datetime::from_delta(*x - *y)?)),
+        ("-", Date(x), Date(y)) => Some(Duration(datetime::from_delta(*x - *y)?)),
+        ("-", Time(x), Time(y)) => Some(Duration(datetime::from_delta(*x - *y)?)),
+        (_, Date(_) | Time(_) | DateTime(_), _) | (_, _, Date(_) | Time(_) | DateTime(_)) => {
+            bail_type_error!(compute a op b)
+        }
+        _ => return Ok(None),
+    };
+    match ret {
+        Some(ret) => Ok(Some(ret)),
+        None => bail_arithmetic_error!(overflow a op b),
+    }
+}
+
+/// Unit-aware arithmetic on Duration and Bytes, which never mix with bare numbers
+/// except as a count in `*` and `/`.
+fn eval_quantity(a: &Value, b: &Value, op: &str) -> Result<Option<Value>> {
+    use Value::*;
+    let ret = match (op, a, b) {
+        ("+", Duration(x), Duration(y)) => x.checked_add(*y).map(Duration),
+        ("+", Bytes(x), Bytes(y)) => x.checked_add(*y).map(Bytes),
+        ("-", Duration(x), Duration(y)) => x.checked_sub(*y).map(Duration),
+        ("-", Bytes(x), Bytes(y)) => x.checked_sub(*y).map(Bytes),
+        ("*", Duration(x), Nat(y)) | ("*", Nat(y), Duration(x)) => x.checked_mul(*y).map(Duration),
+        ("*", Bytes(x), Nat(y)) | ("*", Nat(y), Bytes(x)) => x.checked_mul(*y).map(Bytes),
+        ("/", Duration(_), Nat(0))
+        | ("/", Duration(_), Duration(0))
+        | ("/", Bytes(_), Nat(0))
+        | ("/", Bytes(_), Bytes(0))
+        | ("%", Duration(_), Duration(0))
+        | ("%", Bytes(_), Bytes(0)) => bail_arithmetic_error!(zero a op b),
+        ("/", Duration(x), Nat(y)) => Some(Duration(x / y)),
+        ("/", Bytes(x), Nat(y)) => Some(Bytes(x / y)),
+        ("/", Duration(x), Duration(y)) | ("/", Bytes(x), Bytes(y)) => {
+            Some(Float(*x as f64 / *y as f64))
+        }
+        ("%", Duration(x), Duration(y)) => Some(Duration(x % y)),
+        ("%", Bytes(x), Bytes(y)) => Some(Bytes(x % y)),
+        (_, Duration(_), _) | (_, Bytes(_), _) | (_, _, Duration(_)) | (_, _, Bytes(_)) => {
+            bail_type_error!(compute a op b)
+        }
+        _ => return Ok(None),
+    };
+    match ret {
+        Some(ret) => Ok(Some(ret)),
+        None => bail_arithmetic_error!(overflow a op b),
+    }
+}
+
+/// Decimal arithmetic, when one side is a Decimal and the other an integer or a Decimal.
+fn eval_decimal<F>(a: &Value, b: &Value, op: &str, f: F) -> Result<Option<Value>>
+where
+    F: Fn(rust_decimal::Decimal, rust_decimal::Decimal) -> Option<rust_decimal::Decimal>,
+{
+    use Value::*;
+    match (a, b) {
+        (Decimal(_), Nat(_) | Int(_) | Decimal(_)) | (Nat(_) | Int(_), Decimal(_)) => {}
+        _ => return Ok(None),
+    }
+    match (a.cast(&Typing::Decimal)?, b.cast(&Typing::Decimal)?) {
+        (Decimal(x), Decimal(y)) => {
+            if (op == "/" || op == "%") && y.is_zero() {
+                bail_arithmetic_error!(zero a op b);
+            }
+            match f(x, y) {
+                Some(z) => Ok(Some(Decimal(z))),
+                None => bail_arithmetic_error!(overflow a op b),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Integer power; an exponent beyond `u32` only fits for a base of 0 or 1.
+fn nat_pow(x: u128, y: u128) -> Option<u128> {
+    match u32::try_from(y) {
+        Ok(y) => x.checked_pow(y),
+        Err(_) if x <= 1 => Some(x),
+        Err(_) => None,
+    }
+}
+
+/// Integer power; an exponent beyond `u32` only fits for a base of 0, 1 or -1.
+fn int_pow(x: i128, y: u128) -> Option<i128> {
+    match u32::try_from(y) {
+        Ok(y) => x.checked_pow(y),
+        Err(_) if x == 0 || x == 1 => Some(x),
+        Err(_) if x == -1 => Some(if y.is_multiple_of(2) { 1 } else { -1 }),
+        Err(_) => None,
+    }
+}
+
+/// `powi` for exponents beyond `i32`.
+fn powi(x: f64, y: i128) -> f64 {
+    match i32::try_from(y) {
+        Ok(y) => x.powi(y),
+        Err(_) => x.powf(y as f64),
+    }
+}
+
+/// Applies the Float overflow policy to an arithmetic result.
+fn check_float<F>(env: &Environ, val: Value, describe: F) -> Result<Value>
+where
+    F: Fn() -> String,
+{
+    match val {
+        Value::Float(x) if x.is_nan() => {
+            bail!("ArithmeticError: {} is not a number.", describe())
+        }
+        Value::Float(x) if x.is_infinite() => match env.float_overflow {
+            FloatOverflow::Error => bail!("ArithmeticError: {} overflows Float.", describe()),
+            FloatOverflow::Saturate => Ok(Value::Float(f64::MAX.copysign(x))),
+        },
+        _ => Ok(val),
+    }
+}
+
+fn nat_to_int(x: u128) -> Result<i128> {
+    i128::try_from(x).map_err(|_| anyhow!("ArithmeticError: {} is too large for Int", x))
+}
+
+fn shift_amount(n: &Value) -> Result<u32> {
+    match n {
+        Value::Nat(n) if *n < 128 => Ok(*n as u32),
+        Value::Int(n) if 0 <= *n && *n < 128 => Ok(*n as u32),
+        _ => bail!("ArithmeticError: Cannot shift by {:?}", n),
+    }
+}
+
+/// `f(args, kwargs)`: a builtin, struct, type or function application.
+fn eval_apply(
+    env: &Environ,
+    fname: &str,
+    args: &[Expr],
+    kwargs: &[(String, Expr)],
+) -> Result<Value> {
+    let values: Vec<Value> = args
+        .iter()
+        .map(|x| eval_expr(env, x))
+        .collect::<Result<_>>()?;
+    let kwvalues = eval_kwargs(env, kwargs)?;
+    apply(env, fname, values, kwvalues)
+}
+
+/// `x.f(args, kwargs)`: `f(x, args, kwargs)`.
+fn eval_method_call(
+    env: &Environ,
+    receiver: &Expr,
+    method: &str,
+    args: &[Expr],
+    kwargs: &[(String, Expr)],
+) -> Result<Value> {
+    let values: Vec<Value> = std::iter::once(receiver)
+        .chain(args)
+        .map(|x| eval_expr(env, x))
+        .collect::<Result<_>>()?;
+    let kwvalues = eval_kwargs(env, kwargs)?;
+    apply(env, method, values, kwvalues)
+}
+
+fn eval_kwargs(env: &Environ, kwargs: &[(String, Expr)]) -> Result<HashMap<String, Value>> {
+    kwargs
+        .iter()
+        .map(|(name, x)| eval_expr(env, x).map(|val| (name.to_string(), val)))
+        .collect()
+}
+
+fn apply(
+    env: &Environ,
+    fname: &str,
+    values: Vec<Value>,
+    kwvalues: HashMap<String, Value>,
+) -> Result<Value> {
+    use Value::*;
+    let ret = match fname {
+        "Some" => {
+            assert_args_eq!("Some", values.len(), 1);
+            let val = values[0].clone();
+            let typ = val.type_of();
+            Ok(Optional(typ, Box::new(Some(val))))
+        }
+        "Decimal" => {
+            assert_args_eq!("Decimal", values.len(), 1);
+            values[0].coerce(&Typing::Decimal)
+        }
+        "not" => {
+            assert_args_eq!("not", values.len(), 1);
+            let e = Expr::Not(Box::new(Expr::Val(values[0].clone())));
+            eval_expr(env, &e)
+        }
+        "concat" => builtins::concat(&values),
+        "reverse" => {
+            assert_args_eq!("reverse", values.len(), 1);
+            builtins::reverse(&values[0])
+        }
+        "len" => {
+            assert_args_eq!("len", values.len(), 1);
+            builtins::len(&values[0])
+        }
+        "sum" => {
+            assert_args_eq!("sum", values.len(), 1);
+            builtins::sum(&values[0])
+        }
+        "product" => {
+            assert_args_eq!("product", values.len(), 1);
+            builtins::product(&values[0])
+        }
+        "min" => {
+            assert_args_geq!("min", values.len(), 1);
+            builtins::min(&values, &env.enums)
+        }
+        "max" => {
+            assert_args_geq!("max", values.len(), 1);
+            builtins::max(&values, &env.enums)
+        }
+        "abs" => {
+            assert_args_eq!("abs", values.len(), 1);
+            builtins::abs(&values[0])
+        }
+        "floor" => {
+            assert_args_eq!("floor", values.len(), 1);
+            builtins::rounding(
+                "floor",
+                &values[0],
+                f64::floor,
+                rust_decimal::Decimal::floor,
+            )
+        }
+        "ceil" => {
+            assert_args_eq!("ceil", values.len(), 1);
+            builtins::rounding("ceil", &values[0], f64::ceil, rust_decimal::Decimal::ceil)
+        }
+        "round" => {
+            assert_args_eq!("round", values.len(), 1);
+            builtins::rounding("round", &values[0], f64::round, |d| {
+                d.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+            })
+        }
+        "trunc" => {
+            assert_args_eq!("trunc", values.len(), 1);
+            builtins::rounding(
+                "trunc",
+                &values[0],
+                f64::trunc,
+                rust_decimal::Decimal::trunc,
+            )
+        }
+        "sqrt" => {
+            assert_args_eq!("sqrt", values.len(), 1);
+            builtins::real("sqrt", &values[0], f64::sqrt)
+        }
+        "exp" => {
+            assert_args_eq!("exp", values.len(), 1);
+            builtins::real("exp", &values[0], f64::exp)
+        }
+        "ln" => {
+            assert_args_eq!("ln", values.len(), 1);
+            builtins::real("ln", &values[0], f64::ln)
+        }
+        "log2" => {
+            assert_args_eq!("log2", values.len(), 1);
+            builtins::real("log2", &values[0], f64::log2)
+        }
+        "log10" => {
+            assert_args_eq!("log10", values.len(), 1);
+            builtins::real("log10", &values[0], f64::log10)
+        }
+        "clamp" => {
+            assert_args_eq!("clamp", values.len(), 3);
+            builtins::clamp(&values[0], &values[1], &values[2], &env.enums)
+        }
+        "gcd" => {
+            assert_args_eq!("gcd", values.len(), 2);
+            builtins::gcd(&values[0], &values[1])
+        }
+        "lcm" => {
+            assert_args_eq!("lcm", values.len(), 2);
+            builtins::lcm(&values[0], &values[1])
+        }
+        "sort" => {
+            assert_args_eq!("sort", values.len(), 1);
+            builtins::sort(&values[0], &env.enums)
+        }
+        "sort_by_key" => {
+            assert_args_eq!("sort_by_key", values.len(), 2);
+            builtins::sort_by_key(&values[0], &values[1], &env.enums)
+        }
+        "uniq" => {
+            assert_args_eq!("uniq", values.len(), 1);
+            builtins::uniq(&values[0])
+        }
+        "zip" => {
+            assert_args_eq!("zip", values.len(), 2);
+            builtins::zip(&values[0], &values[1])
+        }
+        "enumerate" => {
+            assert_args_eq!("enumerate", values.len(), 1);
+            builtins::enumerate(&values[0])
+        }
+        "flatten" => {
+            assert_args_eq!("flatten", values.len(), 1);
+            builtins::flatten(&values[0])
+        }
+        "index_of" => {
+            assert_args_eq!("index_of", values.len(), 2);
+            builtins::index_of(&values[0], &values[1])
+        }
+        "chunks" => {
+            assert_args_eq!("chunks", values.len(), 2);
+            builtins::chunks(&values[0], &values[1])
+        }
+        "range" => {
+            assert_args_geq!("range", values.len(), 1);
+            assert_args_leq!("range", values.len(), 2);
+            builtins::range(&values)
+        }
+        "upper" => {
+            assert_args_eq!("upper", values.len(), 1);
+            builtins::upper(&values[0])
+        }
+        "lower" => {
+            assert_args_eq!("lower", values.len(), 1);
+            builtins::lower(&values[0])
+        }
+        "trim" => {
+            assert_args_eq!("trim", values.len(), 1);
+            builtins::trim(&values[0])
+        }
+        "split" => {
+            assert_args_eq!("split", values.len(), 2);
+            builtins::split(&values[0], &values[1])
+        }
+        "join" => {
+            assert_args_eq!("join", values.len(), 2);
+            builtins::join(&values[0], &values[1])
+        }
+        "replace" => {
+            assert_args_eq!("replace", values.len(), 3);
+            builtins::replace(&values[0], &values[1], &values[2])
+        }
+        "starts_with" => {
+            assert_args_eq!("starts_with", values.len(), 2);
+            builtins::starts_with(&values[0], &values[1])
+        }
+        "ends_with" => {
+            assert_args_eq!("ends_with", values.len(), 2);
+            builtins::ends_with(&values[0], &values[1])
+        }
+        "contains" => {
+            assert_args_eq!("contains", values.len(), 2);
+            builtins::contains(&values[0], &values[1])
+        }
+        "satisfies" => {
+            assert_args_eq!("satisfies", values.len(), 2);
+            builtins::satisfies(&values[0], &values[1])
+        }
+        "pad_left" => {
+            assert_args_geq!("pad_left", values.len(), 2);
+            assert_args_leq!("pad_left", values.len(), 3);
+            builtins::pad_left(&values[0], &values[1], values.get(2))
+        }
+        "repeat" => {
+            assert_args_eq!("repeat", values.len(), 2);
+            builtins::repeat(&values[0], &values[1])
+        }
+        "merge" => {
+            assert_args_geq!("merge", values.len(), 2);
+            assert_args_leq!("merge", values.len(), 4);
+            let arrays = ArrayMerge::from_args(&values[2..])?;
+            merge(env, &values[0], &values[1], &arrays, "")
+        }
+        "merge_by" => {
+            assert_args_eq!("merge_by", values.len(), 3);
+            merge_by(env, &values[0], &values[1], &values[2])
+        }
+        "apply_patch" => {
+            assert_args_eq!("apply_patch", values.len(), 2);
+            apply_patch(env, &values[0], &values[1])
+        }
+        "now" => {
+            assert_args_eq!("now", values.len(), 0);
+            match env.now {
+                Some(now) => Ok(Value::DateTime(now)),
+                None => bail!("now() needs a reference time supplied by the host"),
+            }
+        }
+        "as_nanos" | "as_micros" | "as_millis" | "as_secs" | "as_mins" | "as_hours" => {
+            assert_args_eq!(fname, values.len(), 1);
+            builtins::duration_as(fname, &values[0])
+        }
+        "substr" => {
+            assert_args_eq!("substr", values.len(), 3);
+            builtins::substr(&values[0], &values[1], &values[2])
+        }
+        // Struct Apply
+        _ if env.structs.contains_key(fname) => {
+            let fields = env.structs.get(fname).unwrap();
+            assert_args_leq!(fname, values.len(), fields.len());
+            let n = values.len();
+            let mut items = vec![];
+            let path = |name: &str| format!("{}.{}", fname, name);
+            for ((name, typ, refinement, _default), value) in fields[..n].iter().zip(values.iter())
+            {
+                let val = refine(
+                    env,
+                    cast_in(env, value.clone(), typ, &path(name))?,
+                    refinement,
+                    &path(name),
+                )?;
+                items.push((name.to_string(), val));
+            }
+            for (name, typ, refinement, default) in fields[n..].iter() {
+                if let Some(value) = kwvalues.get(name) {
+                    let val = refine(
+                        env,
+                        cast_in(env, value.clone(), typ, &path(name))?,
+                        refinement,
+                        &path(name),
+                    )?;
+                    items.push((name.to_string(), val));
+                } else if let Some(e) = default {
+                    let value = eval_expr(env, e)?;
+                    let val = cast_in(env, value, typ, &path(name))?;
+                    let val = refine(env, val, refinement, &path(name))?;
+                    items.push((name.to_string(), val));
+                } else {
+                    bail!("Not supplied Field `{}` for Struct `{}`", name, fname);
+                }
+            }
+            check_struct(env, fname, &items)?;
+            Ok(Dict(Some(fname.to_string()), Entries::new(items)))
+        }
+        // Type Apply
+        _ if env.types.contains_key(fname) => {
+            assert_args_eq!(fname, values.len(), 1);
+            up_cast(env, &values[0], fname, fname)
+        }
+        // Function Apply
+        _ if env.funs.contains_key(fname) => {
+            let (env_inner, args, body) = env.funs.get(fname).unwrap();
+            assert_args_leq!(fname, values.len(), args.len());
+            let mut env_inner = env_inner.clone();
+            let n = values.len();
+            let path = |name: &str| format!("{}.{}", fname, name);
+            for ((name, typ, _default), value) in args[..n].iter().zip(values.iter()) {
+                let val = cast_in(env, value.clone(), typ, &path(name))?;
+                env_inner.vars.insert(name.to_string(), (typ.clone(), val));
+            }
+            for (name, typ, default) in args[n..].iter() {
+                if let Some(val) = kwvalues.get(name) {
+                    let val = cast_in(env, val.clone(), typ, &path(name))?;
+                    env_inner.vars.insert(name.to_string(), (typ.clone(), val));
+                } else if let Some(e) = default {
+                    let val = eval_expr(env, e)?;
+                    let val = cast_in(env, val, typ, &path(name))?;
+                    env_inner.vars.insert(name.to_string(), (typ.clone(), val));
+                } else {
+                    bail!("Not supplied Arg `{}` for Function `{}`.", name, fname);
+                }
+            }
+            eval_expr(&env_inner, body)
+        }
+        _ => bail!("Cannot resolve name `{}`.", fname),
+    }?;
+    // every builtin Float follows the overflow policy, as arithmetic does
+    check_float(env, ret, || {
+        let args: Vec<String> = values.iter().map(|val| format!("{:?}", val)).collect();
+        format!("{}({})", fname, args.join(", "))
+    })
+}
+
+/// Reads an index value; negative indices count from the end.
+fn seq_offset(idx: &Value, len: usize) -> Result<(i128, i128)> {
+    let i = match idx {
+        Value::Nat(i) => i128::try_from(*i).unwrap_or(i128::MAX),
+        Value::Int(i) => *i,
+        _ => bail!("TypeError: Index must be Nat or Int, but {:?}.", idx),
+    };
+    Ok((i, if i < 0 { i + len as i128 } else { i }))
+}
+
+fn seq_index(idx: &Value, len: usize) -> Result<usize> {
+    let (raw, i) = seq_offset(idx, len)?;
+    if i < 0 || i >= len as i128 {
+        bail!(
+            "IndexError: index {} is out of range for length {}",
+            raw,
+            len
+        );
+    }
+    Ok(i as usize)
+}
+
+fn seq_range(start: Option<Value>, end: Option<Value>, len: usize) -> Result<(usize, usize)> {
+    let (raw_i, i) = match &start {
+        Some(idx) => seq_offset(idx, len)?,
+        None => (0, 0),
+    };
+    let (raw_j, j) = match &end {
+        Some(idx) => seq_offset(idx, len)?,
+        None => (len as i128, len as i128),
+    };
+    if i < 0 || j > len as i128 || i > j {
+        bail!(
+            "IndexError: range {}..{} is out of range for length {}",
+            raw_i,
+            raw_j,
+            len
+        );
+    }
+    Ok((i as usize, j as usize))
+}
+
+fn eval_value(env: &Environ, value: &Value) -> Result<Value> {
+    use Value::*;
+    match value {
+        Env(v, default_value) => match (env.env_vars.get(v), default_value) {
+            (Some(val), _) => Ok(Str(val.to_string())),
+            (None, Some(def)) => Ok(Str(def.to_string())),
+            _ => bail!("Undefined env variable {}", v),
+        },
+        EnumVariant(s, t) => {
+            // check existence
+            if let Some(variants) = env.enums.get(s) {
+                if variants.iter().any(|v| v == t) {
+                    Ok(EnumVariant(s.to_string(), t.to_string()))
+                } else {
+                    bail!("Enum {} doesnt have {}", s, t)
+                }
+            } else {
+                bail!("Not found Enum {}", s)
+            }
+        }
+        // `1e400` reads as infinity
+        Float(_) => check_float(env, value.clone(), || "a Float literal".to_string()),
+        _ => Ok(value.clone()),
+    }
+}
+
+/// What to do when a Float result becomes infinite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatOverflow {
+    /// Fail with an `ArithmeticError`.
+    Error,
+    /// Clamp to the largest finite Float of the same sign.
+    Saturate,
+}
+
+impl std::str::FromStr for FloatOverflow {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(FloatOverflow::Error),
+            "saturate" => Ok(FloatOverflow::Saturate),
+            _ => bail!(
+                "Unknown float overflow policy: {} (expected error or saturate)",
+                s
+            ),
+        }
+    }
+}
+
+/// How `/` rounds a Decimal quotient to the configured scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecimalRounding {
+    /// Half to even, the banker's rounding.
+    HalfEven,
+    /// Half away from zero.
+    HalfUp,
+    /// Half toward zero.
+    HalfDown,
+    /// Away from zero.
+    Up,
+    /// Toward zero.
+    Down,
+    /// Toward positive infinity.
+    Ceiling,
+    /// Toward negative infinity.
+    Floor,
+}
+
+impl DecimalRounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            DecimalRounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            DecimalRounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            DecimalRounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            DecimalRounding::Up => RoundingStrategy::AwayFromZero,
+            DecimalRounding::Down => RoundingStrategy::ToZero,
+            DecimalRounding::Ceiling => RoundingStrategy::ToPositiveInfinity,
+            DecimalRounding::Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
+}
+
+impl std::str::FromStr for DecimalRounding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "half-even" => Ok(DecimalRounding::HalfEven),
+            "half-up" => Ok(DecimalRounding::HalfUp),
+            "half-down" => Ok(DecimalRounding::HalfDown),
+            "up" => Ok(DecimalRounding::Up),
+            "down" => Ok(DecimalRounding::Down),
+            "ceiling" => Ok(DecimalRounding::Ceiling),
+            "floor" => Ok(DecimalRounding::Floor),
+            _ => bail!(
+                "Unknown decimal rounding: {} (expected half-even, half-up, half-down, up, down, ceiling or floor)",
+                s
+            ),
+        }
+    }
+}
+
+/// Largest scale a Decimal can hold.
+pub const MAX_DECIMAL_SCALE: u32 = 28;
+
+type Fun = (Environ, Vec<(String, Typing, Option<Expr>)>, Expr);
+
+#[derive(Clone)]
+pub struct Environ {
+    cd: Option<String>,
+    wasm: bool,
+    types: HashMap<String, (Vec<Typing>, Option<Predicate>)>,
+    structs: HashMap<String, Vec<Field>>,
+    checks: HashMap<String, Vec<Check>>,
+    enums: Enums,
+    vars: HashMap<String, (Typing, Value)>,
+    env_vars: HashMap<String, String>,
+    loaded_modules: HashSet<String>,
+    funs: HashMap<String, Fun>,
+    float_overflow: FloatOverflow,
+    decimal_rounding: DecimalRounding,
+    decimal_scale: u32,
+    now: Option<DateTime<FixedOffset>>,
+    module: Option<String>,
+    tests: Option<Vec<TestOutcome>>,
+    warn_overwrite: bool,
+}
+
+/// The outcome of a `test "<name>" { ... }` block in `module`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestOutcome {
+    pub module: Option<String>,
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+impl Environ {
+    pub fn new(cd: Option<String>) -> Self {
+        Self {
+            cd,
+            wasm: false,
+            types: HashMap::new(),
+            structs: HashMap::new(),
+            checks: HashMap::new(),
+            enums: HashMap::new(),
+            env_vars: env::vars().collect(),
+            vars: HashMap::new(),
+            loaded_modules: HashSet::new(),
+            funs: HashMap::new(),
+            float_overflow: FloatOverflow::Error,
+            decimal_rounding: DecimalRounding::HalfEven,
+            decimal_scale: MAX_DECIMAL_SCALE,
+            now: None,
+            module: None,
+            tests: None,
+            warn_overwrite: false,
+        }
+    }
+    pub fn with_float_overflow(mut self, float_overflow: FloatOverflow) -> Self {
+        self.float_overflow = float_overflow;
+        self
+    }
+    /// Rounds Decimal quotients to `scale` places (at most 28) with `rounding`.
+    pub fn with_decimal_rounding(mut self, rounding: DecimalRounding, scale: u32) -> Self {
+        self.decimal_rounding = rounding;
+        self.decimal_scale = scale.min(MAX_DECIMAL_SCALE);
+        self
+    }
+    /// The reference time returned by `now()`; without one, `now()` is an error.
+    pub fn with_now(mut self, now: DateTime<FixedOffset>) -> Self {
+        self.now = Some(now);
+        self
+    }
+    /// Warns on stderr when a key of `{{ ..a, ..b }}` is overwritten by a later one.
+    pub fn with_warn_overwrite(mut self, warn_overwrite: bool) -> Self {
+        self.warn_overwrite = warn_overwrite;
+        self
+    }
+    /// Reads `${VAR}` from `env_vars` instead of the process environment.
+    pub fn with_env_vars(mut self, env_vars: HashMap<String, String>) -> Self {
+        self.env_vars = env_vars;
+        self
+    }
+    /// Runs the `test` blocks of `module` and of the modules it uses, which
+    /// normal evaluation skips; their outcomes are collected by `take_tests`.
+    pub fn with_tests(mut self, module: &str) -> Self {
+        self.module = Some(module.to_string());
+        self.tests = Some(vec![]);
+        self
+    }
+    pub fn take_tests(&mut self) -> Vec<TestOutcome> {
+        self.tests.take().unwrap_or_default()
+    }
+    pub fn wasm() -> Self {
+        Self {
+            cd: None,
+            wasm: true,
+            types: HashMap::new(),
+            structs: HashMap::new(),
+            checks: HashMap::new(),
+            enums: HashMap::new(),
+            env_vars: HashMap::new(),
+            vars: HashMap::new(),
+            loaded_modules: HashSet::new(),
+            funs: HashMap::new(),
+            float_overflow: FloatOverflow::Error,
+            decimal_rounding: DecimalRounding::HalfEven,
+            decimal_scale: MAX_DECIMAL_SCALE,
+            now: None,
+            module: None,
+            tests: None,
+            warn_overwrite: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_eval_from_parse {
+    use crate::eval::eval;
+    use crate::json::JSON;
+    use crate::parser::cumin::{cumin, parse_error};
+
+    macro_rules! assert_eval {
+        ($code:expr, $json:expr) => {
+            assert_eq!(eval(cumin($code).unwrap().1, None).unwrap(), $json);
+        };
+    }
+
+    #[test]
+    fn test_numbers() {
         assert_eval!("-1", JSON::Int(-1));
         assert_eval!("-1 + 3", JSON::Int(2));
         assert_eval!("-1 / 2", JSON::Int(0));
         assert_eval!("1 + 2 * 3", JSON::Nat(7));
         assert_eval!("(1 + 2) * 3", JSON::Nat(9));
+        assert_eval!("1 ** 4294967296", JSON::Nat(1));
+        assert_eval!("(-1) ** 4294967297", JSON::Int(-1));
+        assert_eval!("2 ** -1", JSON::Float(0.5));
+        assert_eval!("2.5 as Nat", JSON::Nat(2));
+        assert_eval!("-2.5 as Int", JSON::Int(-2));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eval!("0o755 & 0o022", JSON::Nat(0o000));
+        assert_eval!("0o644 | 0o111", JSON::Nat(0o755));
+        assert_eval!("0xFF ^ 0x0F", JSON::Nat(0xF0));
+        assert_eval!("1 << 10", JSON::Nat(1024));
+        assert_eval!("0b1000 >> 3", JSON::Nat(1));
+        assert_eval!("-8 >> 1", JSON::Int(-4));
+        assert_eval!("-1 & 0xFF", JSON::Int(255));
+        assert_eval!("1e-3 * 1000", JSON::Float(1.0));
+    }
+
+    #[test]
+    fn test_bools() {
+        assert_eval!(
+            "[true or true, true or false, false or true, false or false]",
+            JSON::Array(vec![
+                JSON::Bool(true),
+                JSON::Bool(true),
+                JSON::Bool(true),
+                JSON::Bool(false)
+            ])
+        );
+        assert_eval!(
+            "[true and true, true and false, false and true, false and false]",
+            JSON::Array(vec![
+                JSON::Bool(true),
+                JSON::Bool(false),
+                JSON::Bool(false),
+                JSON::Bool(false)
+            ])
+        );
+        assert_eval!(
+            "[true xor true, true xor false, false xor true, false xor false]",
+            JSON::Array(vec![
+                JSON::Bool(false),
+                JSON::Bool(true),
+                JSON::Bool(true),
+                JSON::Bool(false)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_eval!("Some(1)", JSON::Nat(1));
+        assert_eval!("Some(1 + 2)", JSON::Nat(3));
+        assert_eval!("not(true)", JSON::Bool(false));
+        assert_eval!("concat()", JSON::Array(vec![]));
+        assert_eval!("concat([1])", JSON::Array(vec![JSON::Nat(1)]));
+        assert_eval!(
+            "concat([1], [2])",
+            JSON::Array(vec![JSON::Nat(1), JSON::Nat(2)])
+        );
+        assert_eval!(
+            "concat([1], [2], [3])",
+            JSON::Array(vec![JSON::Nat(1), JSON::Nat(2), JSON::Nat(3)])
+        );
+        assert_eval!(
+            "reverse([1, 2, 3])",
+            JSON::Array(vec![JSON::Nat(3), JSON::Nat(2), JSON::Nat(1)])
+        );
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        use JSON::*;
+        assert_eval!("len(\"h\u{e9}llo\")", Nat(5));
+        assert_eval!("upper(\"Hello\")", Str("HELLO".to_string()));
+        assert_eval!("lower(\"Hello\")", Str("hello".to_string()));
+        assert_eval!("trim(\"  x y \\n\")", Str("x y".to_string()));
+        assert_eval!(
+            "split(\"a,b,,c\", \",\")",
+            Array(vec![
+                Str("a".to_string()),
+                Str("b".to_string()),
+                Str("".to_string()),
+                Str("c".to_string())
+            ])
+        );
+        assert_eval!("join([\"web\", \"01\"], \"-\")", Str("web-01".to_string()));
+        assert_eval!("join([], \"-\")", Str("".to_string()));
+        assert_eval!("replace(\"a.b.c\", \".\", \"/\")", Str("a/b/c".to_string()));
+        assert_eval!("starts_with(\"web01\", \"web\")", Bool(true));
+        assert_eval!("ends_with(\"web01\", \"web\")", Bool(false));
+        assert_eval!("contains(\"web01\", \"b0\")", Bool(true));
+        assert_eval!("pad_left(\"7\", 3)", Str("  7".to_string()));
+        assert_eval!("pad_left(\"7\", 3, \"0\")", Str("007".to_string()));
+        assert_eval!("pad_left(\"1234\", 3, \"0\")", Str("1234".to_string()));
+        assert_eval!("repeat(\"ab\", 3)", Str("ababab".to_string()));
+        assert_eval!("substr(\"hostname\", 4, 4)", Str("name".to_string()));
+        assert_eval!(
+            "let i = 3; \"web\" + pad_left(i as String, 2, \"0\")",
+            Str("web03".to_string())
+        );
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        use JSON::*;
+        assert_eval!("len([1, 2, 3])", Nat(3));
+        assert_eval!("len((1, \"a\"))", Nat(2));
+        assert_eval!("sum([1, 2, 3])", Nat(6));
+        assert_eval!("sum([1, -2, 3])", Int(2));
+        assert_eval!("sum([1, 0.5])", Float(1.5));
+        assert_eval!("sum([])", Nat(0));
+        assert_eval!("product([2, 3, 4])", Nat(24));
+        assert_eval!("min([3, -1, 2])", Int(-1));
+        assert_eval!("max([\"b\", \"c\", \"a\"])", Str("c".to_string()));
+        assert_eval!("sort([3, 1, 2])", Array(vec![Nat(1), Nat(2), Nat(3)]));
+        assert_eval!(
+            "struct S { name: String, n: Nat }
+            sort_by_key([S(\"b\", 1), S(\"a\", 2)], \"name\")[0].n",
+            Nat(2)
+        );
+        assert_eval!(
+            "sort_by_key([(2, \"x\"), (1, \"y\")], 0)",
+            Array(vec![
+                Array(vec![Nat(1), Str("y".to_string())]),
+                Array(vec![Nat(2), Str("x".to_string())])
+            ])
+        );
+        assert_eval!("uniq([1, 2, 1, 3, 2])", Array(vec![Nat(1), Nat(2), Nat(3)]));
+        assert_eval!(
+            "zip([1, 2, 3], [\"a\", \"b\"])",
+            Array(vec![
+                Array(vec![Nat(1), Str("a".to_string())]),
+                Array(vec![Nat(2), Str("b".to_string())])
+            ])
+        );
+        assert_eval!(
+            "enumerate([\"a\"])",
+            Array(vec![Array(vec![Nat(0), Str("a".to_string())])])
+        );
+        assert_eval!(
+            "flatten([[1], [], [2, -3]])",
+            Array(vec![Int(1), Int(2), Int(-3)])
+        );
+        assert_eval!("contains([1, 2], 2)", Bool(true));
+        assert_eval!("contains([1, 2], -2)", Bool(false));
+        assert_eval!("index_of([1, 2], 2)", Nat(1));
+        assert_eval!("index_of([1, 2], 3)", Null);
+        assert_eval!(
+            "chunks([1, 2, 3], 2)",
+            Array(vec![Array(vec![Nat(1), Nat(2)]), Array(vec![Nat(3)])])
+        );
+        assert_eval!("range(3)", Array(vec![Nat(0), Nat(1), Nat(2)]));
+        assert_eval!("range(2, 4)", Array(vec![Nat(2), Nat(3)]));
+        assert_eval!(
+            "let xs: Array<Int> = range(2); xs",
+            Array(vec![Int(0), Int(1)])
+        );
+    }
+
+    #[test]
+    fn test_math_builtins() {
+        use JSON::*;
+        assert_eval!("abs(-3)", Int(3));
+        assert_eval!("abs(3)", Nat(3));
+        assert_eval!("abs(-0.5)", Float(0.5));
+        assert_eval!("floor(2.7)", Float(2.0));
+        assert_eval!("ceil(2.1)", Float(3.0));
+        assert_eval!("round(2.5)", Float(3.0));
+        assert_eval!("trunc(-2.7)", Float(-2.0));
+        assert_eval!("floor(-3)", Int(-3));
+        assert_eval!("sqrt(16)", Float(4.0));
+        assert_eval!("exp(0)", Float(1.0));
+        assert_eval!("ln(E)", Float(1.0));
+        assert_eval!("log2(1024)", Float(10.0));
+        assert_eval!("log10(0.001)", Float(-3.0));
+        assert_eval!("min(3, 1, 2)", Nat(1));
+        assert_eval!("max(3, -1)", Int(3));
+        assert_eval!("max(1, 2.5)", Float(2.5));
+        assert_eval!("clamp(120, 0, 100)", Nat(100));
+        assert_eval!("clamp(-5, 0, 100)", Int(0));
+        assert_eval!("clamp(0.5, 0, 1)", Float(0.5));
+        assert_eval!("gcd(12, 18)", Nat(6));
+        assert_eval!("gcd(-12, 18)", Int(6));
+        assert_eval!("lcm(4, 6)", Nat(12));
+        assert_eval!("lcm(0, 6)", Nat(0));
+        assert_eval!("(PI > 3.14) and (PI < 3.15)", Bool(true));
+        assert_eval!("let PI = 3; PI", Nat(3));
+        assert_eval!("floor(64 * 0.75) as Nat", Nat(48));
+    }
+
+    #[test]
+    fn test_interpolated() {
+        use JSON::*;
+        assert_eval!(
+            "let host = \"example.com\"; let port = 8080; f\"https://{host}:{port}/api\"",
+            Str("https://example.com:8080/api".to_string())
+        );
+        assert_eval!(
+            "enum R { East } f\"region={R::East}, ok={1 < 2}\"",
+            Str("region=East, ok=true".to_string())
+        );
+        assert_eval!("f\"{{literal}}\"", Str("{literal}".to_string()));
+        assert_eval!("f\"[{3:03}] [{-3:04}]\"", Str("[003] [-003]".to_string()));
+        assert_eval!(
+            "f\"[{\"ab\":>4}] [{\"ab\":-<4}]\"",
+            Str("[  ab] [ab--]".to_string())
+        );
+        assert_eval!("f\"[{7:^5}] [{7:<3}]\"", Str("[  7  ] [7  ]".to_string()));
+        assert_eval!("f\"{PI:.2} {1:.1}\"", Str("3.14 1.0".to_string()));
+        assert_eval!("f\"{\"hostname\":.4}\"", Str("host".to_string()));
+        assert_eval!("f\"{10:04} {(10:04)}\"", Str("0010 10:04:00".to_string()));
+        assert_eval!(
+            "f\"${CUMIN_UNDEFINED_VAR:-fallback}\"",
+            Str("fallback".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eval!("let x = 2; x == 2", JSON::Bool(true));
+        assert_eval!("let x = 2; 2 < x + 1", JSON::Bool(true));
+        assert_eval!("[] == []", JSON::Bool(true));
+        assert_eval!("[1] == [1]", JSON::Bool(true));
+        assert_eval!("[1, 2] == concat([1], [2])", JSON::Bool(true));
+        assert_eval!("[1, 2] != [2, 1]", JSON::Bool(true));
+        assert_eval!("{{ x=1 }} == {{ x=1 }}", JSON::Bool(true));
+        assert_eval!("{{ x=1, y=1 }} == {{ y=1, x=1 }}", JSON::Bool(true));
+        assert_eval!("{{ x=1, y=2 }} != {{ y=1, x=2 }}", JSON::Bool(true));
+        assert_eval!("let x: Int = 1; x == 1", JSON::Bool(true));
+        assert_eval!(
+            "let x: Int = 1; let y: Nat = 1; [x] == [y]",
+            JSON::Bool(true)
+        );
     }
 
     #[test]
-    fn test_bools() {
+    fn test_decimal() {
+        use rust_decimal::Decimal;
+        let dec = |s: &str| JSON::Decimal(s.parse::<Decimal>().unwrap());
+        assert_eval!("0.1d + 0.2d", dec("0.3"));
+        assert_eval!("0.1d + 0.2d == 0.3d", JSON::Bool(true));
+        assert_eval!("Decimal(\"12.50\") * 2", dec("25.00"));
+        assert_eval!("12.50d - 13", dec("-0.50"));
+        assert_eval!("-(1.5d)", dec("-1.5"));
+        assert_eval!("7.5d % 2", dec("1.5"));
+        assert_eval!("1.00d / 4", dec("0.25"));
+        assert_eval!("[1, 2.5d]", JSON::Array(vec![dec("1"), dec("2.5")]));
+        assert_eval!("sum([0.1d, 0.2d, 0.3d])", dec("0.6"));
+        assert_eval!("round(2.5d)", dec("3"));
+        assert_eval!("0.1 as Decimal", dec("0.1"));
+        assert_eval!("2.99d as Int", JSON::Int(2));
+        assert_eval!("0.5d as Float", JSON::Float(0.5));
+        assert_eval!("12.50d as String", JSON::Str("12.50".to_string()));
+        assert_eval!("f\"{2.5d:.2}\"", JSON::Str("2.50".to_string()));
+        assert_eval!("1.5d < 2", JSON::Bool(true));
+        assert_eval!("1.0d == 1", JSON::Bool(true));
+    }
+
+    #[test]
+    fn test_decimal_rounding() {
+        use crate::eval::{eval_cumin, DecimalRounding, Environ};
+        use crate::parser::value::Value;
+        use rust_decimal::Decimal;
+        let run = |rounding, code| {
+            let mut env = Environ::new(None).with_decimal_rounding(rounding, 2);
+            eval_cumin(&mut env, &cumin(code).unwrap().1).unwrap()
+        };
+        let dec = |s: &str| Value::Decimal(s.parse::<Decimal>().unwrap());
+        assert_eq!(run(DecimalRounding::HalfEven, "10.0d / 3"), dec("3.33"));
+        assert_eq!(run(DecimalRounding::Up, "10.0d / 3"), dec("3.34"));
+        assert_eq!(run(DecimalRounding::HalfEven, "0.125d / 1"), dec("0.12"));
+        assert_eq!(run(DecimalRounding::HalfUp, "0.125d / 1"), dec("0.13"));
+        assert_eq!(run(DecimalRounding::Floor, "-1.0d / 3"), dec("-0.34"));
+    }
+
+    #[test]
+    fn test_duration_bytes() {
+        use crate::json::{Output, JSON::*};
+        let human = |code: &str| {
+            eval(cumin(code).unwrap().1, None)
+                .unwrap()
+                .with_output(&Output::default())
+        };
+        assert_eq!(human("1h + 30m"), Str("1h30m".to_string()));
+        assert_eq!(human("30s * 4"), Str("2m".to_string()));
+        assert_eq!(human("2 * 256MiB"), Str("512MiB".to_string()));
+        assert_eq!(human("1h / 4"), Str("15m".to_string()));
+        assert_eq!(human("1m30s % 1m"), Str("30s".to_string()));
+        assert_eq!(human("\"1h30m\" as Duration"), Str("1h30m".to_string()));
+        assert_eq!(
+            human("f\"timeout={90s}\""),
+            Str("timeout=1m30s".to_string())
+        );
+        assert_eval!("1h / 30m", Float(2.0));
+        assert_eval!("500ms < 1s", Bool(true));
+        assert_eval!("1000ms == 1s", Bool(true));
+        assert_eval!("let d = 1m30s; d.as_secs()", Nat(90));
+        assert_eval!("(1500ms).as_secs()", Nat(1));
+        assert_eval!("as_millis(2s)", Nat(2000));
+        assert_eval!("512KiB as Nat", Nat(524288));
+        assert_eval!("fn twice(x: Int) = x * 2; let n = 3; n.twice()", Int(6));
+        assert_eval!("let name = \"web\"; name.upper()", Str("WEB".to_string()));
+    }
+
+    #[test]
+    fn test_datetime() {
+        use crate::json::JSON::*;
         assert_eval!(
-            "[true or true, true or false, false or true, false or false]",
+            "2024-03-01T09:30:00+09:00 + 90m",
+            Str("2024-03-01T11:00:00+09:00".to_string())
+        );
+        assert_eval!(
+            "2024-03-01T00:00:00Z - 1s",
+            Str("2024-02-29T23:59:59Z".to_string())
+        );
+        assert_eval!("2024-02-28 + 2 * 24h", Str("2024-03-01".to_string()));
+        assert_eval!("23:30 + 1h", Str("00:30:00".to_string()));
+        assert_eval!("(2024-03-01 - 2024-02-01).as_hours()", Nat(696));
+        assert_eval!(
+            "(2024-03-01T00:00:00+09:00 - 2024-02-29T14:00:00Z).as_mins()",
+            Nat(60)
+        );
+        assert_eval!(
+            "2024-03-01T00:00:00+09:00 < 2024-02-29T16:00:00Z",
+            Bool(true)
+        );
+        assert_eval!("2024-03-01 > 2024-02-29", Bool(true));
+        assert_eval!("let t = 2024-03-01T09:30:00Z; t.weekday", Nat(5));
+        assert_eval!("let t = 2024-03-01T09:30:00Z; t.hour", Nat(9));
+        assert_eval!("let t = 2024-03-01T09:30:00Z; t.year", Int(2024));
+        assert_eval!(
+            "let t = 2024-03-01T09:30:00Z; t.date",
+            Str("2024-03-01".to_string())
+        );
+        assert_eval!(
+            "\"2024-03-01T09:30:00+00:00\" as DateTime",
+            Str("2024-03-01T09:30:00Z".to_string())
+        );
+        assert_eval!("2024-03-01 as String", Str("2024-03-01".to_string()));
+    }
+
+    #[test]
+    fn test_network() {
+        use crate::json::JSON::*;
+        assert_eval!(
+            "let net = \"10.0.0.0/8\" as Cidr; net.contains(\"10.1.2.3\" as IpAddr)",
+            Bool(true)
+        );
+        assert_eval!(
+            "contains(\"10.0.0.0/8\" as Cidr, \"192.168.0.1\" as Ipv4)",
+            Bool(false)
+        );
+        assert_eval!(
+            "let u = \"HTTPS://API.Example.com/v1?x=1\" as Url; u.host",
+            Str("api.example.com".to_string())
+        );
+        assert_eval!(
+            "let u = \"https://api.example.com/v1\" as Url; u.port",
+            Nat(443)
+        );
+        assert_eval!("let u = \"redis://cache\" as Url; u.port", Null);
+        assert_eval!(
+            "let s = \"[::1]:8080\" as SocketAddr; (s.ip, s.port)",
+            Array(vec![Str("::1".to_string()), Nat(8080)])
+        );
+        assert_eval!(
+            "[\"10.0.0.1\" as Ipv4, \"::1\" as Ipv6]",
+            Array(vec![Str("10.0.0.1".to_string()), Str("::1".to_string())])
+        );
+        assert_eval!(
+            "\"10.0.0.2\" as IpAddr > \"10.0.0.10\" as IpAddr",
+            Bool(false)
+        );
+    }
+
+    #[test]
+    fn test_semver() {
+        use crate::json::JSON::*;
+        assert_eval!("let v = \"1.4.2\" as SemVer; v >= \"1.4.0\"", Bool(true));
+        assert_eval!("\"1.10.0\" as SemVer > \"1.9.0\" as SemVer", Bool(true));
+        assert_eval!("\"1.0.0-rc.1\" as SemVer < \"1.0.0\"", Bool(true));
+        assert_eval!("\"1.0.0+linux\" as SemVer == \"1.0.0+mac\"", Bool(true));
+        assert_eval!("\"1.0.0\" as SemVer != \"1.0.1\"", Bool(true));
+        assert_eval!(
+            "let v = \"2.7.1\" as SemVer; (v.major, v.minor, v.patch)",
+            Array(vec![Nat(2), Nat(7), Nat(1)])
+        );
+        assert_eval!("satisfies(\"1.4.2\" as SemVer, \"^1.2\")", Bool(true));
+        assert_eval!(
+            "let v = \"2.0.0\" as SemVer; v.satisfies(\">=1.4, <2\")",
+            Bool(false)
+        );
+        assert_eval!("satisfies(\"0.9.0\", \"~0.9 || ^1\")", Bool(true));
+        assert_eval!(
+            "max([\"1.10.0\" as SemVer, \"1.9.0\" as SemVer])",
+            Str("1.10.0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_now() {
+        use crate::datetime::parse_datetime;
+        use crate::eval::{eval_cumin, Environ};
+        use crate::json::JSON::*;
+        let now = parse_datetime("2024-03-01T09:00:00+01:00").unwrap();
+        let mut env = Environ::new(None).with_now(now);
+        let val = eval_cumin(&mut env, &cumin("now() + 1h").unwrap().1).unwrap();
+        assert_eq!(
+            JSON::from_cumin(val).unwrap(),
+            Str("2024-03-01T10:00:00+01:00".to_string())
+        );
+    }
+
+    #[test]
+    fn test_total_order() {
+        assert_eval!("1 < 2.5", JSON::Bool(true));
+        assert_eval!("2.5 > 1", JSON::Bool(true));
+        assert_eval!("-1 < 0.5", JSON::Bool(true));
+        assert_eval!("-2 < -1.5", JSON::Bool(true));
+        assert_eval!("3 >= 3.0", JSON::Bool(true));
+        assert_eval!("1 == 1.0", JSON::Bool(true));
+        assert_eval!("-1 == 1", JSON::Bool(false));
+        assert_eval!(
+            "340282366920938463463374607431768211455 > 1e38",
+            JSON::Bool(true)
+        );
+        assert_eval!("\"apple\" < \"banana\"", JSON::Bool(true));
+        assert_eval!("\"B\" < \"a\"", JSON::Bool(true));
+        assert_eval!("(1, \"b\") < (1, \"c\")", JSON::Bool(true));
+        assert_eval!("[1, 2] < [1, 2, 0]", JSON::Bool(true));
+        assert_eval!("[2] > [1, 9]", JSON::Bool(true));
+        assert_eval!(
+            "enum Level { Low, Mid, High } Level::High > Level::Low",
+            JSON::Bool(true)
+        );
+        assert_eval!(
+            "enum Level { Low, Mid, High } max([Level::Mid, Level::High, Level::Low])",
+            JSON::Str("High".to_string())
+        );
+        assert_eval!(
+            "enum Level { Low, Mid, High } sort([Level::High, Level::Low, Level::Mid])",
             JSON::Array(vec![
-                JSON::Bool(true),
-                JSON::Bool(true),
-                JSON::Bool(true),
-                JSON::Bool(false)
+                JSON::Str("Low".to_string()),
+                JSON::Str("Mid".to_string()),
+                JSON::Str("High".to_string()),
             ])
         );
         assert_eval!(
-            "[true and true, true and false, false and true, false and false]",
+            "sort([(2, \"a\"), (1, \"b\"), (1, \"a\")])",
             JSON::Array(vec![
-                JSON::Bool(true),
-                JSON::Bool(false),
-                JSON::Bool(false),
-                JSON::Bool(false)
+                JSON::Array(vec![JSON::Nat(1), JSON::Str("a".to_string())]),
+                JSON::Array(vec![JSON::Nat(1), JSON::Str("b".to_string())]),
+                JSON::Array(vec![JSON::Nat(2), JSON::Str("a".to_string())]),
             ])
         );
+    }
+
+    #[test]
+    fn test_optional() {
         assert_eval!(
-            "[true xor true, true xor false, false xor true, false xor false]",
-            JSON::Array(vec![
-                JSON::Bool(false),
-                JSON::Bool(true),
-                JSON::Bool(true),
-                JSON::Bool(false)
+            "[None, Some(1)]",
+            JSON::Array(vec![JSON::Null, JSON::Nat(1)])
+        );
+    }
+
+    #[test]
+    fn test_dict() {
+        assert_eval!("{{}}", JSON::Dict(vec![]));
+        assert_eval!(
+            "{{ x = 1, y = 2, }}",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "{{ x: Float = 1, y = 2, }}",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Float(1.0)),
+                ("y".to_string(), JSON::Nat(2)),
             ])
         );
     }
 
     #[test]
-    fn test_builtins() {
-        assert_eval!("Some(1)", JSON::Nat(1));
-        assert_eval!("Some(1 + 2)", JSON::Nat(3));
-        assert_eval!("not(true)", JSON::Bool(false));
-        assert_eval!("concat()", JSON::Array(vec![]));
-        assert_eval!("concat([1])", JSON::Array(vec![JSON::Nat(1)]));
+    fn test_array() {
+        use JSON::*;
+        assert_eval!("[1, 2, 3]", Array(vec![Nat(1), Nat(2), Nat(3)]));
         assert_eval!(
-            "concat([1], [2])",
-            JSON::Array(vec![JSON::Nat(1), JSON::Nat(2)])
+            "[1, 2, 3, -1]",
+            Array(vec![Int(1), Int(2), Int(3), Int(-1)])
         );
+        assert_eval!("[None]", Array(vec![Null]));
+        assert_eval!("[Some(1), Some(-1)]", Array(vec![Int(1), Int(-1)]));
+        assert_eval!("[1, 2] ++ [] ++ [3]", Array(vec![Nat(1), Nat(2), Nat(3)]));
         assert_eval!(
-            "concat([1], [2], [3])",
-            JSON::Array(vec![JSON::Nat(1), JSON::Nat(2), JSON::Nat(3)])
+            "reverse([2, 1]) ++ [] ++ [3]",
+            Array(vec![Nat(1), Nat(2), Nat(3)])
         );
+    }
+
+    #[test]
+    fn test_struct() {
         assert_eval!(
-            "reverse([1, 2, 3])",
-            JSON::Array(vec![JSON::Nat(3), JSON::Nat(2), JSON::Nat(1)])
+            "struct P { x: Nat, y: Nat } P(1, 2)",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat, y: Nat = 100 } P(1, 2)",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat, y: Nat = 100 } P(1)",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(100)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat, y: Nat } P{ x = 1, y = 2 }",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat, y: Nat } P{ y = 2, x = 1 }",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat = 42, y: Nat } P{ x = 1, y = 2 }",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(1)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x: Nat = 42, y: Nat } P{ y = 2 }",
+            JSON::Dict(vec![
+                ("x".to_string(), JSON::Nat(42)),
+                ("y".to_string(), JSON::Nat(2)),
+            ])
+        );
+        assert_eval!(
+            "struct P { x = 42 } P(2)",
+            JSON::Dict(vec![("x".to_string(), JSON::Nat(2))])
         );
     }
 
     #[test]
-    fn test_compare() {
-        assert_eval!("let x = 2; x == 2", JSON::Bool(true));
-        assert_eval!("let x = 2; 2 < x + 1", JSON::Bool(true));
-        assert_eval!("[] == []", JSON::Bool(true));
-        assert_eval!("[1] == [1]", JSON::Bool(true));
-        assert_eval!("[1, 2] == concat([1], [2])", JSON::Bool(true));
-        assert_eval!("[1, 2] != [2, 1]", JSON::Bool(true));
-        assert_eval!("{{ x=1 }} == {{ x=1 }}", JSON::Bool(true));
-        assert_eval!("{{ x=1, y=1 }} == {{ y=1, x=1 }}", JSON::Bool(true));
-        assert_eval!("{{ x=1, y=2 }} != {{ y=1, x=2 }}", JSON::Bool(true));
-        assert_eval!("let x: Int = 1; x == 1", JSON::Bool(true));
+    fn test_enum() {
         assert_eval!(
-            "let x: Int = 1; let y: Nat = 1; [x] == [y]",
+            "enum X { Zoo, Park } X::Park",
+            JSON::Str("Park".to_string())
+        );
+        assert_eval!("enum X { Zoo, Park } X::Zoo", JSON::Str("Zoo".to_string()));
+    }
+
+    #[test]
+    fn test_type() {
+        assert_eval!(
+            "type T = Int | String; [T(1), T(\"hoge\")]",
+            JSON::Array(vec![JSON::Int(1), JSON::Str("hoge".to_string())])
+        );
+    }
+
+    macro_rules! assert_cannot_eval {
+        ($code:expr) => {
+            assert!(eval(cumin($code).unwrap().1, None).is_err());
+        };
+    }
+
+    macro_rules! assert_eval_error {
+        ($code:expr, $msg:expr) => {
+            assert_eq!(
+                eval(cumin($code).unwrap().1, None).unwrap_err().to_string(),
+                $msg
+            );
+        };
+    }
+
+    #[test]
+    fn test_refinement() {
+        let port = "struct S { port: Nat where 1 <= self and self <= 65535 = 8080 } ";
+        assert_eval!(&format!("{}S(443).port", port), JSON::Nat(443));
+        assert_eval!(&format!("{}S().port", port), JSON::Nat(8080));
+        assert_eval!(
+            "type Percent = Float where 0.0 <= self <= 100.0; Percent(99.5)",
+            JSON::Float(99.5)
+        );
+        assert_cannot_eval!(&format!("{}S(0)", port));
+        assert_cannot_eval!(&format!("{}S(port=70000)", port));
+        assert_cannot_eval!("type Percent = Float where 0.0 <= self <= 100.0; Percent(100.5)");
+        assert_cannot_eval!("struct S { x: Nat where self > 0 = 0 } S(1)");
+        assert_cannot_eval!("struct S { x: Nat where self + 1 } S(1)");
+
+        assert_eval_error!(
+            "struct S { name: String where len(self) > 0 } S(\"\")",
+            "RefinementError: S.name = Str(\"\") violates `where len(self) > 0`"
+        );
+        assert_eval_error!(
+            "type Port = Nat where self < 65536; Port(65536)",
+            "RefinementError: Port = Nat(65536) violates `where self < 65536`"
+        );
+        // a field of a refined alias names the field, as a refined field does
+        let alias = "type Port = Nat where self < 65536; struct Server { port: Port } ";
+        assert_eval!(&format!("{}Server(443).port", alias), JSON::Nat(443));
+        assert_eval_error!(
+            &format!("{}Server(65536)", alias),
+            "RefinementError: Server.port = Nat(65536) violates `where self < 65536`"
+        );
+        assert_eval_error!(
+            &format!(
+                "{}let s = Server(443); Server {{ ..s, port = 65536 }}",
+                alias
+            ),
+            "RefinementError: Server.port = Nat(65536) violates `where self < 65536`"
+        );
+        assert_eval_error!(
+            "struct Server { port: Nat where self < 65536 } Server(65536)",
+            "RefinementError: Server.port = Nat(65536) violates `where self < 65536`"
+        );
+    }
+
+    #[test]
+    fn test_struct_check() {
+        let deploy = "struct Deploy {
+            min_replicas: Nat,
+            max_replicas: Nat,
+            tls: Bool = false,
+            tls_cert: Option<String> = None,
+            check min_replicas <= max_replicas, \"min must not exceed max\";
+            check not tls or tls_cert != None, f\"tls_cert is required when tls = {tls}\";
+        } ";
+        assert_eval!(
+            &format!("{}Deploy(1, 3).max_replicas", deploy),
+            JSON::Nat(3)
+        );
+        assert_eval!(
+            &format!("{}Deploy(2, 2, true, Some(\"a.pem\")).tls", deploy),
             JSON::Bool(true)
         );
+        assert_cannot_eval!(&format!("{}Deploy(1, 3, tls=true)", deploy));
+
+        assert_eval_error!(
+            &format!("{}Deploy(3, 1, true)", deploy),
+            "CheckError: Deploy violates 2 check(s):
+  - min must not exceed max (`min_replicas <= max_replicas`)
+  - tls_cert is required when tls = true (`not tls or tls_cert != None`)"
+        );
+        assert_eval_error!(
+            "struct S { x: Nat, check x, \"x\"; } S(1)",
+            "CheckError: S violates 1 check(s):\n  - `x` is Nat(1), not Bool"
+        );
+        assert_cannot_eval!("struct S { x: Nat, check x > 0, 1; } S(0)");
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eval!(
+            "let xs = [1, 2, 3]; assert len(xs) == 3, \"three\"; assert xs[0] < xs[2]; xs[1]",
+            JSON::Nat(2)
+        );
+        // every let is in scope, whatever the order
+        assert_eval!("assert n >= 1; let n = 2; n", JSON::Nat(2));
+
+        assert_eval_error!(
+            "let servers = [\"a\", \"b\"]; assert len(servers) == 3; 0",
+            "assert failed: len(servers) == 3 (left = 2)"
+        );
+        assert_eval_error!(
+            "let a = 1; let b = \"x\"; assert a > len(b), f\"{b} is too long\"; 0",
+            "assert failed: a > len(b) (left = 1, right = 1): x is too long"
+        );
+        assert_eval_error!(
+            "let a = 2; assert 3 <= a; 0",
+            "assert failed: 3 <= a (right = 2)"
+        );
+        assert_eval_error!("let a = 2; assert (a > 3); 0", "assert failed: (a > 3)");
+        assert_eval_error!(
+            "let ok = false; assert ok, \"not ok\"; 0",
+            "assert failed: ok: not ok"
+        );
+        assert_eval_error!("assert 1; 0", "TypeError: assert `1` is Nat(1), not Bool");
+        assert_cannot_eval!("assert false, 1; 0");
+        assert_cannot_eval!("{ assert 1 == 2; 0 }");
+    }
+
+    #[test]
+    fn test_tests() {
+        use crate::eval::{eval_cumin, Environ, TestOutcome};
+        let code = "use \"sizing.cumin\";
+            let xs = [1, 2];
+            test \"pass\" { double(2) == 4 }
+            test \"assert\" { assert len(xs) == 3; }
+            test \"value\" { 1 }
+            test \"null\" { nothing() }
+            fn double(x: Nat) = x * 2;
+            fn nothing() = Null;
+            len(xs)";
+        // normal evaluation skips the tests
+        assert_eval!(code, JSON::Nat(2));
+
+        let mut env = Environ::new(Some("examples".to_string())).with_tests("main.cumin");
+        let val = eval_cumin(&mut env, &cumin(code).unwrap().1).unwrap();
+        assert_eq!(val, crate::parser::value::Value::Nat(2));
+        let outcome = |module: &str, name: &str, failure: Option<&str>| TestOutcome {
+            module: Some(module.to_string()),
+            name: name.to_string(),
+            failure: failure.map(String::from),
+        };
+        assert_eq!(
+            env.take_tests(),
+            vec![
+                outcome("examples/sizing.cumin", "replicas rounds up", None),
+                outcome("examples/sizing.cumin", "replicas of an exact fit", None),
+                outcome("main.cumin", "pass", None),
+                outcome(
+                    "main.cumin",
+                    "assert",
+                    Some("assert failed: len(xs) == 3 (left = 2)")
+                ),
+                outcome(
+                    "main.cumin",
+                    "value",
+                    Some("TypeError: test is Nat(1), not Bool")
+                ),
+                outcome(
+                    "main.cumin",
+                    "null",
+                    Some("TypeError: test is Null, not Bool")
+                ),
+            ]
+        );
     }
 
     #[test]
-    fn test_optional() {
+    fn test_spread() {
+        let server = "struct Server {
+            host: String,
+            port: Nat where 1 <= self <= 65535 = 8080,
+            tls: Bool = false,
+            check not tls or port != 80, \"tls on port 80\";
+        }
+        let base = Server(\"a\");
+        ";
         assert_eval!(
-            "[None, Some(1)]",
-            JSON::Array(vec![JSON::Null, JSON::Nat(1)])
+            &format!("{}Server {{ ..base, port = 8081 }}.port", server),
+            JSON::Nat(8081)
         );
-    }
+        assert_eval!(
+            &format!("{}Server {{ ..base }} == base", server),
+            JSON::Bool(true)
+        );
+        assert_eval!(
+            &format!(
+                "{}Server {{ ..Server {{ ..base, tls = true }}, host = \"b\" }}",
+                server
+            ),
+            JSON::Dict(vec![
+                ("host".to_string(), JSON::Str("b".to_string())),
+                ("port".to_string(), JSON::Nat(8080)),
+                ("tls".to_string(), JSON::Bool(true)),
+            ])
+        );
+        assert_cannot_eval!(&format!("{}Server {{ ..base, port = 0 }}", server));
+        assert_cannot_eval!(&format!("{}Server {{ ..base, port = \"80\" }}", server));
+        assert_cannot_eval!(&format!(
+            "{}Server {{ ..base, tls = true, port = 80 }}",
+            server
+        ));
+        assert_cannot_eval!(&format!("{}Server {{ ..base, name = 1 }}", server));
+        assert_eval_error!(
+            &format!("{}Server {{ ..base, port = 3, port = 4 }}", server),
+            "Duplicated Key `port` in an update of `Server`"
+        );
+        assert_cannot_eval!(&format!("{}Server {{ ..{{{{ host = \"a\" }}}} }}", server));
+        assert_cannot_eval!("struct A { x: Nat } struct B { x: Nat } B { ..A(1) }");
+        assert_cannot_eval!("C { ..{{ x = 1 }} }");
 
-    #[test]
-    fn test_dict() {
-        assert_eval!("{{}}", JSON::Dict(vec![]));
         assert_eval!(
-            "{{ x = 1, y = 2, }}",
+            "let a = {{ x = 1, y = 2 }}; let b = {{ y = 3, z = 4 }}; {{ ..a, ..b, x = 5 }}",
             JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
+                ("x".to_string(), JSON::Nat(5)),
+                ("y".to_string(), JSON::Nat(3)),
+                ("z".to_string(), JSON::Nat(4)),
             ])
         );
         assert_eval!(
-            "{{ x: Float = 1, y = 2, }}",
+            "struct P { x: Nat } {{ x = 0, ..P(1), y = 2 }}",
             JSON::Dict(vec![
-                ("x".to_string(), JSON::Float(1.0)),
+                ("x".to_string(), JSON::Nat(1)),
                 ("y".to_string(), JSON::Nat(2)),
             ])
         );
+        assert_cannot_eval!("{{ ..[1, 2] }}");
+        assert_cannot_eval!("{{ ..{{ y = 1 }}, x = 1, x = 2 }}");
     }
 
     #[test]
-    fn test_array() {
-        use JSON::*;
-        assert_eval!("[1, 2, 3]", Array(vec![Nat(1), Nat(2), Nat(3)]));
+    fn test_merge() {
         assert_eval!(
-            "[1, 2, 3, -1]",
-            Array(vec![Int(1), Int(2), Int(3), Int(-1)])
+            "let base = {{ db = {{ host = \"a\", port = 5432 }}, tags = [\"x\"] }};
+            let prod = {{ db = {{ host = \"b\" }}, tags = [\"y\"], debug = false }};
+            merge(base, prod)",
+            JSON::Dict(vec![
+                (
+                    "db".to_string(),
+                    JSON::Dict(vec![
+                        ("host".to_string(), JSON::Str("b".to_string())),
+                        ("port".to_string(), JSON::Nat(5432)),
+                    ])
+                ),
+                (
+                    "tags".to_string(),
+                    JSON::Array(vec![JSON::Str("y".to_string())])
+                ),
+                ("debug".to_string(), JSON::Bool(false)),
+            ])
         );
-        assert_eval!("[None]", Array(vec![Null]));
-        assert_eval!("[Some(1), Some(-1)]", Array(vec![Int(1), Int(-1)]));
-        assert_eval!("[1, 2] ++ [] ++ [3]", Array(vec![Nat(1), Nat(2), Nat(3)]));
         assert_eval!(
-            "reverse([2, 1]) ++ [] ++ [3]",
-            Array(vec![Nat(1), Nat(2), Nat(3)])
+            "merge({{ xs = [1] }}, {{ xs = [2] }}, \"append\").xs",
+            JSON::Array(vec![JSON::Nat(1), JSON::Nat(2)])
         );
-    }
-
-    #[test]
-    fn test_struct() {
         assert_eval!(
-            "struct P { x: Nat, y: Nat } P(1, 2)",
-            JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
+            "let a = [{{ name = \"web\", port = 80, tls = false }}, {{ name = \"db\", port = 5432 }}];
+            let b = [{{ name = \"web\", tls = true }}, {{ name = \"cache\", port = 6379 }}];
+            merge(a, b, \"merge\", \"name\")",
+            JSON::Array(vec![
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("web".to_string())),
+                    ("port".to_string(), JSON::Nat(80)),
+                    ("tls".to_string(), JSON::Bool(true)),
+                ]),
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("db".to_string())),
+                    ("port".to_string(), JSON::Nat(5432)),
+                ]),
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("cache".to_string())),
+                    ("port".to_string(), JSON::Nat(6379)),
+                ]),
             ])
         );
+        // structs stay structs with their field types
+        let server = "struct Server {
+            port: Float where self > 0,
+            tags: Array<String> = [],
+            check len(tags) < 3, \"too many tags\";
+        }
+        ";
         assert_eval!(
-            "struct P { x: Nat, y: Nat = 100 } P(1, 2)",
-            JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
-            ])
+            &format!(
+                "{}merge(Server(1.5), {{{{ port = 2 }}}}) == Server(2.0)",
+                server
+            ),
+            JSON::Bool(true)
         );
         assert_eval!(
-            "struct P { x: Nat, y: Nat = 100 } P(1)",
-            JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(100)),
-            ])
+            &format!("{}merge(Server(1.5), Server(3.0)).port", server),
+            JSON::Float(3.0)
+        );
+        assert_cannot_eval!(&format!("{}merge(Server(1.5), {{{{ port = 0 }}}})", server));
+        assert_cannot_eval!(&format!(
+            "{}merge(Server(1.5), {{{{ port = \"80\" }}}})",
+            server
+        ));
+        assert_cannot_eval!(&format!(
+            "{}merge(Server(1.5), {{{{ host = \"a\" }}}})",
+            server
+        ));
+        assert_cannot_eval!(&format!(
+            "{}merge(Server(1.5, [\"a\", \"b\"]), {{{{ tags = [\"c\"] }}}}, \"append\")",
+            server
+        ));
+
+        assert_eval_error!(
+            "merge({{ db = {{ port = 5432 }} }}, {{ db = {{ port = \"5432\" }} }})",
+            "TypeError: merge cannot change Nat to String at `db.port`"
+        );
+        assert_eval_error!(
+            "merge({{ db = {{ port = 1 }} }}, {{ db = 1 }})",
+            "TypeError: merge cannot change Dict to Nat at `db`"
         );
         assert_eval!(
-            "struct P { x: Nat, y: Nat } P{ x = 1, y = 2 }",
+            "merge({{ a = 1, b = [1], c = 1 }}, {{ a = -2, b = [-2], c = 0.5 }})",
             JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
+                ("a".to_string(), JSON::Int(-2)),
+                ("b".to_string(), JSON::Array(vec![JSON::Int(-2)])),
+                ("c".to_string(), JSON::Float(0.5)),
             ])
         );
+        assert_eval_error!(
+            "struct S { a: Nat } merge(S(1), {{ a = -2 }})",
+            "TypeError: merge cannot change Nat to Int at `a`"
+        );
+        assert_cannot_eval!("merge([1], [2], \"merge\")");
+        assert_cannot_eval!("merge([1], [2], \"merge\", \"name\")");
+        assert_cannot_eval!("merge([1], [2], \"zip\")");
+        assert_cannot_eval!("merge(1)");
+    }
+
+    #[test]
+    fn test_patch() {
+        let server = "struct Server {
+            host: String,
+            port: Nat where self >= 1 = 8080,
+            cert: Option<String> = None,
+            check port != 443 or cert != None, \"443 needs a cert\";
+        }
+        let base = Server(\"a\");
+        ";
         assert_eval!(
-            "struct P { x: Nat, y: Nat } P{ y = 2, x = 1 }",
+            &format!(
+                "{}let p: Patch<Server> = {{{{ port = 443, cert = Some(\"a.pem\") }}}};
+                apply_patch(base, p)",
+                server
+            ),
             JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
+                ("host".to_string(), JSON::Str("a".to_string())),
+                ("port".to_string(), JSON::Nat(443)),
+                ("cert".to_string(), JSON::Str("a.pem".to_string())),
             ])
         );
+        // a patch sets only what it has
         assert_eval!(
-            "struct P { x: Nat = 42, y: Nat } P{ x = 1, y = 2 }",
-            JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(1)),
-                ("y".to_string(), JSON::Nat(2)),
-            ])
+            &format!("{}{{{{ port = 1 }}}} as Patch<Server>", server),
+            JSON::Dict(vec![("port".to_string(), JSON::Nat(1))])
         );
         assert_eval!(
-            "struct P { x: Nat = 42, y: Nat } P{ y = 2 }",
-            JSON::Dict(vec![
-                ("x".to_string(), JSON::Nat(42)),
-                ("y".to_string(), JSON::Nat(2)),
-            ])
+            &format!("{}apply_patch(base, {{{{}}}}) == base", server),
+            JSON::Bool(true)
         );
         assert_eval!(
-            "struct P { x = 42 } P(2)",
-            JSON::Dict(vec![("x".to_string(), JSON::Nat(2))])
+            &format!(
+                "{}fn prod(p: Patch<Server>) = apply_patch(base, p); prod({{{{ host = \"b\" }}}}).host",
+                server
+            ),
+            JSON::Str("b".to_string())
+        );
+        assert_eval!(
+            &format!("{}apply_patch(base, Server(\"c\", 1)).port", server),
+            JSON::Nat(1)
+        );
+        assert_cannot_eval!(&format!(
+            "{}{{{{ name = \"a\" }}}} as Patch<Server>",
+            server
+        ));
+        assert_cannot_eval!(&format!(
+            "{}{{{{ port = \"80\" }}}} as Patch<Server>",
+            server
+        ));
+        assert_cannot_eval!(&format!("{}{{{{ port = 0 }}}} as Patch<Server>", server));
+        assert_cannot_eval!(&format!(
+            "{}apply_patch(base, {{{{ port = 443 }}}})",
+            server
+        ));
+        assert_cannot_eval!(&format!(
+            "{}apply_patch({{{{ port = 1 }}}}, {{{{}}}})",
+            server
+        ));
+        assert_cannot_eval!(&format!(
+            "{}struct Db {{ port: Nat }} apply_patch(base, {{{{ port = 1 }}}} as Patch<Db>)",
+            server
+        ));
+        assert_cannot_eval!("{{ x = 1 }} as Patch<Undefined>");
+        assert_eval!(
+            &format!(
+                "{}let p: Patch<Server> = {{{{ port = 443 }}}}; p.port",
+                server
+            ),
+            JSON::Nat(443)
+        );
+        assert_eval_error!(
+            &format!(
+                "{}let p: Patch<Server> = {{{{ port = 443 }}}}; p.host",
+                server
+            ),
+            "Patch<Server> does not set `host`"
+        );
+        // the failing field and the reason reach the user
+        assert_eval_error!(
+            &format!("{}apply_patch(base, {{{{ port = \"x\" }}}})", server),
+            "TypeError: Server.port: No ways to cast Str(\"x\") => Nat"
+        );
+        assert_eval_error!(
+            &format!("{}apply_patch(base, {{{{ prot = 1 }}}})", server),
+            "Struct `Server` has no Field `prot`"
+        );
+        assert_eval_error!(
+            &format!("{}apply_patch(base, {{{{ port = 0 }}}})", server),
+            "RefinementError: Server.port = Nat(0) violates `where self >= 1`"
         );
     }
 
     #[test]
-    fn test_enum() {
+    fn test_let_pattern() {
+        let records = "struct UserRecord { id: Nat, name: String, email: String = \"\" }
+        let first = UserRecord(id = 1, name = \"alice\");
+        ";
         assert_eval!(
-            "enum X { Zoo, Park } X::Park",
-            JSON::Str("Park".to_string())
+            "let (host, port) = (\"example.com\", 443); port",
+            JSON::Nat(443)
+        );
+        assert_eval!(
+            "let {{ user, region }} = {{ user = \"u\", region = \"eu\", tier = 1 }}; region",
+            JSON::Str("eu".to_string())
+        );
+        assert_eval!(
+            &format!("{}let UserRecord {{ id, name, .. }} = first; name", records),
+            JSON::Str("alice".to_string())
+        );
+        assert_eval!(
+            "let [a, b, ..rest] = [1, 2, 3, 4]; (a, b, rest)",
+            JSON::Array(vec![
+                JSON::Nat(1),
+                JSON::Nat(2),
+                JSON::Array(vec![JSON::Nat(3), JSON::Nat(4)])
+            ])
+        );
+        assert_eval!("let [x, ..rest] = [1]; rest", JSON::Array(vec![]));
+        assert_eval!(
+            "type Addr = (String, Nat); let (_, port) = Addr((\"h\", 80)); port",
+            JSON::Nat(80)
+        );
+
+        assert_eval_error!(
+            "let (host, port) = (\"h\", 80, 1); host",
+            "TypeError: `(host, port)` binds 2 elements, but the Tuple has 3"
+        );
+        assert_eval_error!(
+            "let [a, b] = [1, 2, 3]; a",
+            "TypeError: `[a, b]` binds 2 elements, but the Array has 3"
+        );
+        assert_eval_error!(
+            "let [a, b, ..rest] = [1]; a",
+            "TypeError: `[a, b, ..rest]` binds at least 2 elements, but the Array has 1"
+        );
+        assert_eval_error!(
+            "let (a, b) = [1, 2]; a",
+            "TypeError: `(a, b)` needs a Tuple, not Array(Nat)"
+        );
+        assert_eval_error!(
+            "let [a] = {{ a = 1 }}; a",
+            "TypeError: `[a]` needs an Array, not Dict"
+        );
+        assert_eval_error!(
+            "let {{ user, region }} = {{ user = 1 }}; user",
+            "TypeError: `{{ user, region }}` binds `region`, but the Dict has no such key"
+        );
+        assert_eval_error!(&format!("{}let UserRecord {{ id, name }} = first; id", records),
+            "TypeError: `UserRecord { id, name }` does not bind email of UserRecord; end it with `..` to ignore them"
+        );
+        assert_eval_error!(
+            &format!("{}let UserRecord {{ age, .. }} = first; age", records),
+            "Struct `UserRecord` has no Field `age`"
+        );
+        assert_eval_error!(
+            &format!(
+                "{}struct Group {{ id: Nat }} let Group {{ id }} = first; id",
+                records
+            ),
+            "TypeError: `Group { id }` needs a Group, not UserRecord"
+        );
+        assert_eval_error!(
+            "let (a, [a]) = (1, [2]); a",
+            "Duplicated binding `a` in a pattern"
         );
-        assert_eval!("enum X { Zoo, Park } X::Zoo", JSON::Str("Zoo".to_string()));
     }
 
     #[test]
-    fn test_type() {
+    fn test_merge_by() {
+        let services = "struct Service {
+            name: String,
+            replicas: Nat where self >= 1 = 1,
+            env = {{}},
+        }
+        let base = [
+            Service(\"web\", 2, {{ LOG = \"info\", PORT = \"80\" }}),
+            Service(\"worker\"),
+            Service(\"legacy\"),
+        ];
+        ";
         assert_eval!(
-            "type T = Int | String; [T(1), T(\"hoge\")]",
-            JSON::Array(vec![JSON::Int(1), JSON::Str("hoge".to_string())])
+            &format!(
+                "{}merge_by(base, [
+                    {{{{ name = \"web\", env = {{{{ LOG = \"warn\" }}}} }}}},
+                    {{{{ name = \"legacy\", _delete = true }}}},
+                    {{{{ name = \"cron\", replicas = 3 }}}},
+                ], \"name\")",
+                services
+            ),
+            JSON::Array(vec![
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("web".to_string())),
+                    ("replicas".to_string(), JSON::Nat(2)),
+                    (
+                        "env".to_string(),
+                        JSON::Dict(vec![
+                            ("LOG".to_string(), JSON::Str("warn".to_string())),
+                            ("PORT".to_string(), JSON::Str("80".to_string())),
+                        ])
+                    ),
+                ]),
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("worker".to_string())),
+                    ("replicas".to_string(), JSON::Nat(1)),
+                    ("env".to_string(), JSON::Dict(vec![])),
+                ]),
+                JSON::Dict(vec![
+                    ("name".to_string(), JSON::Str("cron".to_string())),
+                    ("replicas".to_string(), JSON::Nat(3)),
+                    ("env".to_string(), JSON::Dict(vec![])),
+                ]),
+            ])
+        );
+        assert_eval!(
+            &format!(
+                "{}let p: Patch<Service> = {{{{ name = \"worker\", replicas = 4 }}}};
+                merge_by(base, [p], \"name\")[1].replicas",
+                services
+            ),
+            JSON::Nat(4)
+        );
+        assert_eval!(
+            &format!("{}merge_by(base, [], \"name\") == base", services),
+            JSON::Bool(true)
+        );
+        // an empty base takes the struct of its overrides, or else their dicts as they are
+        assert_eval!(
+            &format!(
+                "{}merge_by([], [Service(\"a\"), Service(\"b\", 2)], \"name\")
+                == [Service(\"a\"), Service(\"b\", 2)]",
+                services
+            ),
+            JSON::Bool(true)
+        );
+        assert_eval!(
+            &format!(
+                "{}let p: Patch<Service> = {{{{ name = \"a\" }}}};
+                merge_by([], [p], \"name\")[0].replicas",
+                services
+            ),
+            JSON::Nat(1)
+        );
+        assert_eval!(
+            "merge_by([], [{{ name = \"a\" }}, {{ name = \"a\", x = 1 }}], \"name\")",
+            JSON::Array(vec![JSON::Dict(vec![
+                ("name".to_string(), JSON::Str("a".to_string())),
+                ("x".to_string(), JSON::Nat(1)),
+            ])])
         );
-    }
 
-    macro_rules! assert_cannot_eval {
-        ($code:expr) => {
-            assert!(eval(cumin($code).unwrap().1, None).is_err());
-        };
+        assert_eval_error!(
+            &format!("{}merge_by(base, [{{{{ name = 1 }}}}], \"name\")", services),
+            "TypeError: merge_by key `name` of overrides[0] is Nat, not String"
+        );
+        assert_eval_error!(
+            &format!(
+                "{}merge_by(base, [{{{{ replicas = 2 }}}}], \"name\")",
+                services
+            ),
+            "merge_by key `name` is missing in overrides[0]"
+        );
+        assert_eval_error!(
+            &format!("{}merge_by(base, [], \"id\")", services),
+            "Struct `Service` has no Field `id` to merge by"
+        );
+        assert_cannot_eval!(&format!(
+            "{}merge_by(base, [{{{{ name = \"gone\", _delete = true }}}}], \"name\")",
+            services
+        ));
+        assert_cannot_eval!(&format!(
+            "{}merge_by(base, [{{{{ name = \"web\", replicas = 0 }}}}], \"name\")",
+            services
+        ));
+        assert_cannot_eval!(&format!(
+            "{}merge_by(base, [{{{{ name = \"new\", port = 1 }}}}], \"name\")",
+            services
+        ));
+        assert_cannot_eval!(&format!(
+            "{}struct Db {{ name: String }} merge_by(base, [Db(\"web\")], \"name\")",
+            services
+        ));
+        assert_cannot_eval!("merge_by([{{ name = \"a\" }}], [], \"name\")");
+        assert_cannot_eval!("merge_by([1], [2], \"name\")");
     }
 
     #[test]
@@ -805,6 +3438,189 @@ mod test_eval_from_parse {
         assert_cannot_eval!("let n: Nat = -1; n");
         assert_cannot_eval!("let xs: Array<Nat> = [-1]; xs");
         assert_cannot_eval!("let xs: Option<Nat> = Some(-1); xs");
+        assert_cannot_eval!("\"abc\" as Nat");
+        assert_cannot_eval!("1 < \"2\"");
+        assert_cannot_eval!("(1, 2) < (1, \"a\")");
+        assert_cannot_eval!("enum A { X } enum B { Y } A::X < B::Y");
+        assert_cannot_eval!("${CUMIN_UNDEFINED_VARIABLE}");
+    }
+
+    #[test]
+    fn test_array_builtins_error() {
+        assert_cannot_eval!("sum([\"a\"])");
+        assert_cannot_eval!("sum([340282366920938463463374607431768211455, 1])");
+        assert_cannot_eval!("min([])");
+        assert_cannot_eval!("sort([{{ a = 1 }}, {{ a = 0 }}])");
+        assert_cannot_eval!("flatten([1, 2])");
+        assert_cannot_eval!("contains([1, 2], \"a\")");
+        assert_cannot_eval!("chunks([1], 0)");
+        assert_cannot_eval!("range(1, 2, 3)");
+    }
+
+    #[test]
+    fn test_math_builtins_error() {
+        assert_cannot_eval!("abs(\"1\")");
+        assert_cannot_eval!("sqrt(-1)");
+        assert_cannot_eval!("ln(true)");
+        assert_cannot_eval!("clamp(1, 2, 0)");
+        assert_cannot_eval!("gcd(1.5, 3)");
+        assert_cannot_eval!("min(1, \"a\")");
+        assert_eval_error!(
+            &format!("abs({})", i128::MIN),
+            "ArithmeticError: `abs` Int(-170141183460469231731687303715884105728) overflows."
+        );
+        assert_eval_error!(
+            "lcm(340282366920938463463374607431768211455, 2)",
+            "ArithmeticError: Nat(340282366920938463463374607431768211455) `lcm` Nat(2) overflows."
+        );
+    }
+
+    #[test]
+    fn test_interpolated_error() {
+        assert_cannot_eval!("f\"{undefined}\"");
+        assert_cannot_eval!("f\"{[1]}\"");
+        assert_cannot_eval!("f\"{true:.2}\"");
+        assert_cannot_eval!("f\"{1:999999999999}\"");
+    }
+
+    #[test]
+    fn test_decimal_error() {
+        assert_cannot_eval!("0.1d + 0.1");
+        assert_cannot_eval!("1.5d / 0");
+        assert_cannot_eval!("1.5d % 0.0d");
+        assert_cannot_eval!("Decimal(\"abc\")");
+        assert_cannot_eval!("-1.5d as Nat");
+        assert_cannot_eval!("1e300 as Decimal");
+        assert_cannot_eval!("79228162514264337593543950335.0d * 10");
+    }
+
+    #[test]
+    fn test_duration_bytes_error() {
+        assert_cannot_eval!("1s + 1");
+        assert_cannot_eval!("1s + 1KB");
+        assert_cannot_eval!("1s - 2s");
+        assert_cannot_eval!("1s * 1s");
+        assert_cannot_eval!("1s / 0");
+        assert_cannot_eval!("1s < 1");
+        assert_cannot_eval!("(1KB).as_secs()");
+        assert_cannot_eval!("fn twice(x: Int) = x * 2; twice.twice(3)");
+        assert_cannot_eval!("\"90\" as Duration");
+        assert_cannot_eval!("90 as Duration");
+        assert_eval_error!(
+            "30s as Nat",
+            "TypeError: Duration has no unit to cast to Nat; use as_secs(), as_millis() or another accessor"
+        );
+    }
+
+    #[test]
+    fn test_datetime_error() {
+        assert_cannot_eval!("now()");
+        assert_cannot_eval!("2024-03-01 + 1h");
+        assert_cannot_eval!("2024-02-01 - 2024-03-01");
+        assert_cannot_eval!("2024-03-01 + 1");
+        assert_cannot_eval!("2024-03-01 < 2024-03-01T00:00:00Z");
+        assert_cannot_eval!("2024-03-01 + 2024-03-01");
+        assert_cannot_eval!("let d = 2024-03-01; d.hour");
+        assert_cannot_eval!("\"2024-03-01T09:30:00\" as DateTime");
+        assert_cannot_eval!("\"2024-13-01\" as Date");
+        // a literal shaped like a date must be one, not a subtraction
+        for (code, msg) in [
+            ("2026-02-30", "invalid date 2026-02-30"),
+            ("let d = 2026-13-01; d", "invalid date 2026-13-01"),
+            (
+                "[2026-02-28T25:00:00Z]",
+                "invalid datetime 2026-02-28T25:00:00Z",
+            ),
+        ] {
+            let err = cumin(code).unwrap_err();
+            assert_eq!(parse_error(&err), Some(msg.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_semver_error() {
+        assert_cannot_eval!("\"1.2\" as SemVer");
+        assert_cannot_eval!("\"v1.2.3\" as SemVer");
+        assert_cannot_eval!("\"1.02.3\" as SemVer");
+        assert_cannot_eval!("\"1.2.3\" as SemVer < \"latest\"");
+        assert_cannot_eval!("\"1.2.3\" as SemVer < 1");
+        assert_cannot_eval!("satisfies(\"1.2.3\", \"~>1.2\")");
+        assert_cannot_eval!("satisfies(1, \"^1\")");
+        assert_cannot_eval!("let v = \"1.2.3\" as SemVer; v.build");
+    }
+
+    #[test]
+    fn test_network_error() {
+        assert_cannot_eval!("\"10.0.0.300\" as IpAddr");
+        assert_cannot_eval!("\"::1\" as Ipv4");
+        assert_cannot_eval!("\"10.0.0.1/8\" as Cidr");
+        assert_cannot_eval!("\"localhost\" as SocketAddr");
+        assert_cannot_eval!("\"http://bad host\" as Url");
+        assert_cannot_eval!("\"http://example.com:70000\" as Url");
+        assert_cannot_eval!("(\"10.0.0.0/8\" as Cidr).contains(\"10.0.0.1\")");
+        assert_cannot_eval!("let u = \"http://example.com\" as Url; u.user");
+        assert_cannot_eval!("struct S { ip: Ipv4 } S(\"::1\" as IpAddr)");
+    }
+
+    #[test]
+    fn test_arithmetic_error() {
+        assert_cannot_eval!("340282366920938463463374607431768211455 + 1");
+        assert_cannot_eval!("-170141183460469231731687303715884105727 - 2");
+        assert_cannot_eval!("2 ** 128");
+        assert_cannot_eval!("2 ** 4294967296");
+        assert_cannot_eval!("-(-170141183460469231731687303715884105727 - 1)");
+        assert_cannot_eval!("1 / 0");
+        assert_cannot_eval!("-1 % 0");
+        assert_cannot_eval!("1e308 * 10");
+        assert_cannot_eval!("0.0 / 0.0");
+        assert_cannot_eval!("-1 as Nat");
+        assert_cannot_eval!("1e40 as Int");
+        assert_cannot_eval!("340282366920938463463374607431768211455 as Int");
+        assert_cannot_eval!("exp(1000)");
+        assert_cannot_eval!("ln(0)");
+        assert_cannot_eval!("log2(0.0)");
+        assert_cannot_eval!("log10(0)");
+        assert_cannot_eval!("sqrt(1e400)");
+        assert_cannot_eval!("1e400");
+        assert_cannot_eval!("-1e400");
+        assert_cannot_eval!("[1.0, 1e400]");
+    }
+
+    #[test]
+    fn test_float_overflow_saturate() {
+        use crate::eval::{eval_cumin, Environ, FloatOverflow};
+        use crate::parser::value::Value;
+        let mut env = Environ::new(None).with_float_overflow(FloatOverflow::Saturate);
+        let val = eval_cumin(&mut env, &cumin("-1e308 * 10").unwrap().1).unwrap();
+        assert_eq!(val, Value::Float(f64::MIN));
+        let mut env = Environ::new(None).with_float_overflow(FloatOverflow::Saturate);
+        assert!(eval_cumin(&mut env, &cumin("0.0 / 0.0").unwrap().1).is_err());
+        let mut env = Environ::new(None).with_float_overflow(FloatOverflow::Saturate);
+        let val = eval_cumin(&mut env, &cumin("ln(0)").unwrap().1).unwrap();
+        assert_eq!(val, Value::Float(f64::MIN));
+        let mut env = Environ::new(None).with_float_overflow(FloatOverflow::Saturate);
+        let val = eval_cumin(&mut env, &cumin("1e400").unwrap().1).unwrap();
+        assert_eq!(val, Value::Float(f64::MAX));
+    }
+
+    #[test]
+    fn test_bitwise_error() {
+        assert_cannot_eval!("1 << 128");
+        assert_cannot_eval!("0xFF << 121");
+        assert_cannot_eval!("1 >> -1");
+        assert_cannot_eval!("1.0 & 1");
+        assert_cannot_eval!("true | false");
+    }
+
+    #[test]
+    fn test_string_builtins_error() {
+        assert_cannot_eval!("len(1)");
+        assert_cannot_eval!("upper(\"a\", \"b\")");
+        assert_cannot_eval!("join([1, 2], \",\")");
+        assert_cannot_eval!("pad_left(\"7\")");
+        assert_cannot_eval!("pad_left(\"7\", 3, \"00\")");
+        assert_cannot_eval!("repeat(\"a\", -1)");
+        assert_cannot_eval!("substr(\"abc\", 2, 2)");
     }
 
     #[test]
@@ -868,6 +3684,51 @@ mod test_eval_from_parse {
         );
     }
 
+    #[test]
+    fn test_index() {
+        use JSON::*;
+        assert_eval!("let xs = [1, 2, 3]; xs[0]", Nat(1));
+        assert_eval!("let xs = [1, 2, 3]; xs[-1]", Nat(3));
+        assert_eval!("let xs = [[1], [2, 3]]; xs[1][0]", Nat(2));
+        assert_eval!("let s = \"hello\"; s[1]", Str("e".to_string()));
+        assert_eval!("let t = (1, \"x\"); t[1]", Str("x".to_string()));
+        assert_eval!("let t = (1, (2, 3)); t.1.0", Nat(2));
+        assert_eval!(
+            "struct X { value: Nat } let xs = [X(1), X(2)]; xs[-1].value",
+            Nat(2)
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        use JSON::*;
+        assert_eval!(
+            "let xs = [1, 2, 3, 4]; xs[1..3]",
+            Array(vec![Nat(2), Nat(3)])
+        );
+        assert_eval!(
+            "let xs = [1, 2, 3, 4]; xs[..-2]",
+            Array(vec![Nat(1), Nat(2)])
+        );
+        assert_eval!("let xs = [1, 2, 3, 4]; xs[3..]", Array(vec![Nat(4)]));
+        assert_eval!("let xs = [1, 2, 3, 4]; xs[2..2]", Array(vec![]));
+        assert_eval!("\"hello\"[1..4]", Str("ell".to_string()));
+        assert_eval!("(1, 2, 3)[1..]", Array(vec![Nat(2), Nat(3)]));
+    }
+
+    #[test]
+    fn test_index_error() {
+        assert_cannot_eval!("let xs = [1, 2, 3]; xs[3]");
+        assert_cannot_eval!("let xs = [1, 2, 3]; xs[-4]");
+        assert_cannot_eval!("let xs = [1, 2, 3]; xs[2..1]");
+        assert_cannot_eval!("let xs = [1, 2, 3]; xs[0..4]");
+        assert_cannot_eval!("let xs = [1, 2, 3]; xs[1.0]");
+        assert_cannot_eval!("let t = (1, 2); t.2");
+        assert_cannot_eval!("let x = 1; x[0]");
+        let err = eval(cumin("[1, 2, 3][5]").unwrap().1, None).unwrap_err();
+        assert!(format!("{}", err).contains("length 3"));
+    }
+
     #[test]
     fn test_hoist() {
         assert_eval!(
@@ -909,3 +3770,347 @@ mod test_eval_from_parse {
         );
     }
 }
+
+#[cfg(test)]
+mod test_panic_free {
+    use crate::eval::eval;
+    use crate::parser::cumin::{cumin, Cumin};
+    use crate::parser::expr::{Expr, FormatSpec, Segment, MAX_NESTING};
+    use crate::parser::statement::{Operands, Pattern, Statement};
+    use crate::parser::typing::Typing;
+    use crate::parser::value::Value;
+    use proptest::prelude::*;
+
+    const BUILTINS: &[&str] = &[
+        "Some",
+        "not",
+        "concat",
+        "reverse",
+        "len",
+        "sum",
+        "product",
+        "min",
+        "max",
+        "abs",
+        "floor",
+        "round",
+        "sqrt",
+        "exp",
+        "ln",
+        "clamp",
+        "gcd",
+        "lcm",
+        "sort",
+        "sort_by_key",
+        "uniq",
+        "zip",
+        "enumerate",
+        "flatten",
+        "index_of",
+        "chunks",
+        "range",
+        "upper",
+        "split",
+        "join",
+        "replace",
+        "contains",
+        "pad_left",
+        "repeat",
+        "substr",
+        "satisfies",
+        "merge",
+        "merge_by",
+        "apply_patch",
+        "now",
+        "undefined",
+    ];
+
+    /// Shared by variables, structs, types, functions and fields, so that the generated
+    /// statements and expressions refer to each other.
+    const NAMES: &[&str] = &["x", "y", "S", "T"];
+
+    fn name() -> impl Strategy<Value = String> {
+        prop::sample::select(NAMES).prop_map(str::to_string)
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            Just(Value::Null),
+            prop::sample::select(vec![0, 1, 2, 3, u128::MAX]).prop_map(Value::Nat),
+            prop::sample::select(vec![-1, -2, i128::MIN, i128::MAX]).prop_map(Value::Int),
+            prop::sample::select(vec![0.0, 0.5, -1.5, f64::MAX, f64::NAN]).prop_map(Value::Float),
+            prop::sample::select(vec!["0.1", "-2.50", "79228162514264337593543950335"])
+                .prop_map(|d| Value::Decimal(d.parse().unwrap())),
+            prop::sample::select(vec![0, 1, u128::MAX]).prop_map(Value::Duration),
+            prop::sample::select(vec![0, 1024, u128::MAX]).prop_map(Value::Bytes),
+            prop::sample::select(vec!["0001-01-01", "2024-02-29", "9999-12-31"])
+                .prop_map(|d| Value::Date(crate::datetime::parse_date(d).unwrap())),
+            prop::sample::select(vec!["00:00", "23:59:59.999999999"])
+                .prop_map(|t| Value::Time(crate::datetime::parse_time(t).unwrap())),
+            prop::sample::select(vec!["2024-03-01T09:30:00+09:00", "9999-12-31T23:59:59Z"])
+                .prop_map(|t| Value::DateTime(crate::datetime::parse_datetime(t).unwrap())),
+            prop::sample::select(vec!["0.0.0.0", "255.255.255.255", "::1"])
+                .prop_map(|ip| Value::Ip(ip.parse().unwrap())),
+            prop::sample::select(vec!["0.0.0.0/0", "10.0.0.0/8", "::/128"])
+                .prop_map(|c| Value::Cidr(crate::net::Cidr::parse(c).unwrap())),
+            Just(Value::SocketAddr("[::1]:65535".parse().unwrap())),
+            Just(Value::Url(
+                crate::net::Url::parse("http://[::1]:8080/a?b#c").unwrap()
+            )),
+            any::<bool>().prop_map(Value::Bool),
+            "[a-c0-9 ,]{0,4}".prop_map(Value::Str),
+            Just(Value::Env("CUMIN_UNDEFINED".to_string(), None)),
+        ]
+    }
+
+    fn typing() -> impl Strategy<Value = Typing> {
+        let leaf = prop::sample::select(vec![
+            Typing::Null,
+            Typing::Any,
+            Typing::Nat,
+            Typing::Int,
+            Typing::Float,
+            Typing::Decimal,
+            Typing::Duration,
+            Typing::Bytes,
+            Typing::Date,
+            Typing::Time,
+            Typing::DateTime,
+            Typing::IpAddr,
+            Typing::Ipv4,
+            Typing::Ipv6,
+            Typing::Cidr,
+            Typing::SocketAddr,
+            Typing::Url,
+            Typing::SemVer,
+            Typing::Bool,
+            Typing::String,
+            Typing::UserTyping("T".to_string()),
+            Typing::Patch("T".to_string()),
+        ]);
+        leaf.prop_recursive(2, 4, 2, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(|t| Typing::Array(Box::new(t))),
+                inner.clone().prop_map(|t| Typing::Option(Box::new(t))),
+                prop::collection::vec(inner, 0..3).prop_map(Typing::Tuple),
+            ]
+        })
+    }
+
+    fn expr() -> impl Strategy<Value = Expr> {
+        let leaf = prop_oneof![
+            value().prop_map(Expr::Val),
+            prop::sample::select(vec!["x", "y", "S", "PI", "E"])
+                .prop_map(|v| Expr::Var(v.to_string())),
+        ];
+        leaf.prop_recursive(4, 32, 3, |inner| {
+            let b = || inner.clone().prop_map(Box::new);
+            prop_oneof![
+                (
+                    prop::sample::select(BUILTINS),
+                    prop::collection::vec(inner.clone(), 0..4)
+                )
+                    .prop_map(|(f, args)| Expr::Apply(f.to_string(), args, vec![])),
+                (
+                    name(),
+                    prop::collection::vec(inner.clone(), 0..2),
+                    prop::collection::vec((name(), inner.clone()), 0..3)
+                )
+                    .prop_map(|(f, args, kwargs)| Expr::Apply(f, args, kwargs)),
+                (
+                    b(),
+                    prop::sample::select(BUILTINS),
+                    prop::collection::vec(inner.clone(), 0..3)
+                )
+                    .prop_map(|(x, f, args)| Expr::MethodCall(
+                        x,
+                        f.to_string(),
+                        args,
+                        vec![]
+                    )),
+                prop::collection::vec((name(), typing(), inner.clone()), 0..3)
+                    .prop_map(Expr::AnonymousStruct),
+                (
+                    name(),
+                    b(),
+                    prop::collection::vec((name(), inner.clone()), 0..3)
+                )
+                    .prop_map(|(s, base, kwargs)| Expr::StructUpdate(s, base, kwargs)),
+                prop::collection::vec(inner.clone(), 0..3).prop_map(Expr::Spread),
+                (b(), b()).prop_map(|(x, y)| Expr::Concat(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Add(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Sub(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Mul(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Div(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Mod(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Pow(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::BitAnd(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::BitXor(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Shl(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Shr(x, y)),
+                b().prop_map(Expr::Minus),
+                (b(), b()).prop_map(|(x, y)| Expr::And(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Xor(x, y)),
+                b().prop_map(Expr::Not),
+                (b(), b()).prop_map(|(x, y)| Expr::Equal(x, y)),
+                (b(), b()).prop_map(|(x, y)| Expr::Less(x, y)),
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Expr::Arrayed),
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Expr::Tuple),
+                (b(), typing()).prop_map(|(x, t)| Expr::AsCast(x, t)),
+                (b(), "[a-z0-9]{1,2}").prop_map(|(x, p)| Expr::Prop(x, p)),
+                (b(), b()).prop_map(|(x, i)| Expr::Index(x, i)),
+                (b(), prop::option::of(b()), prop::option::of(b()))
+                    .prop_map(|(x, s, e)| Expr::Slice(x, s, e)),
+                (inner.clone(), 0..3usize, 0..3usize).prop_map(|(x, w, p)| {
+                    let spec = FormatSpec {
+                        width: Some(w),
+                        precision: Some(p),
+                        ..FormatSpec::default()
+                    };
+                    Expr::Interpolated(vec![Segment::Expr(x, spec)])
+                }),
+            ]
+        })
+    }
+
+    fn pattern() -> impl Strategy<Value = Pattern> {
+        let leaf = prop_oneof![name().prop_map(Pattern::Bind), Just(Pattern::Ignore)];
+        leaf.prop_recursive(2, 8, 3, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..3).prop_map(Pattern::Tuple),
+                prop::collection::vec(name(), 0..3).prop_map(Pattern::Dict),
+                (name(), prop::collection::vec(name(), 0..3), any::<bool>())
+                    .prop_map(|(s, fields, rest)| Pattern::Struct(s, fields, rest)),
+                (prop::collection::vec(inner, 0..3), prop::option::of(name()))
+                    .prop_map(|(items, rest)| Pattern::Array(items, rest)),
+            ]
+        })
+    }
+
+    fn statement() -> impl Strategy<Value = Statement> {
+        let predicate = || expr().prop_map(|e| (e, "<predicate>".to_string()));
+        prop_oneof![
+            (name(), typing(), expr()).prop_map(|(v, t, e)| Statement::Let(v, t, e)),
+            (pattern(), expr()).prop_map(|(p, e)| Statement::LetPattern(p, e)),
+            (
+                name(),
+                prop::collection::vec(
+                    (
+                        name(),
+                        typing(),
+                        prop::option::of(predicate()),
+                        prop::option::of(expr())
+                    ),
+                    0..3
+                ),
+                prop::collection::vec((predicate(), expr()), 0..2)
+            )
+                .prop_map(|(s, fields, checks)| Statement::Struct(s, fields, checks)),
+            (name(), prop::collection::vec(name(), 0..3))
+                .prop_map(|(e, variants)| Statement::Enum(e, variants)),
+            (
+                name(),
+                prop::collection::vec(typing(), 1..3),
+                prop::option::of(predicate())
+            )
+                .prop_map(|(t, typs, p)| Statement::Type(t, typs, p)),
+            prop::sample::select(vec!["missing.cumin", "examples/fun.cumin"])
+                .prop_map(|path| Statement::Import(path.to_string())),
+            (
+                name(),
+                prop::collection::vec((name(), typing(), prop::option::of(expr())), 0..3),
+                expr()
+            )
+                .prop_map(|(f, args, body)| Statement::Fun(f, args, body)),
+            (
+                predicate(),
+                prop::option::of(prop::sample::select(vec![
+                    Operands::InOrder,
+                    Operands::Swapped
+                ])),
+                prop::option::of(expr())
+            )
+                .prop_map(|(p, operands, message)| Statement::Assert(p, operands, message)),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(512))]
+
+        #[test]
+        fn parse_and_eval_any_string(input in "\\PC{0,64}") {
+            if let Ok((_, ast)) = cumin(&input) {
+                let _ = eval(ast, None);
+            }
+        }
+
+        #[test]
+        fn parse_and_eval_cumin_like_string(
+            input in "[-+*/%&|^<>!=.,:;(){}\\[\\]\"'$ a-z0-9_#\n]{0,48}"
+        ) {
+            if let Ok((_, ast)) = cumin(&input) {
+                let _ = eval(ast, None);
+            }
+        }
+
+        #[test]
+        fn eval_any_ast(e in expr()) {
+            let _ = eval(Cumin(vec![], e), None);
+        }
+
+        #[test]
+        fn eval_any_program(
+            statements in prop::collection::vec(statement(), 0..4),
+            e in expr()
+        ) {
+            let _ = eval(Cumin(statements, e), None);
+        }
+    }
+
+    #[test]
+    fn parse_and_eval_deeply_nested_input() {
+        // The limits must hold on a thread of the default size, as in a service or a test
+        // that embeds the library, and not only on the larger main thread.
+        let run = std::thread::spawn(|| {
+            let nested = [
+                ("(", ")"),
+                ("[", "]"),
+                ("{", "}"),
+                ("{{ a = ", " }}"),
+                ("abs(", ")"),
+                ("f\"{", "}\""),
+                ("-", ""),
+                ("not ", ""),
+                ("x.f(", ")"),
+                ("x[", "]"),
+                ("{ let a = ", "; a }"),
+                ("let a: Array<", "Nat> = []; a"),
+                ("let (_, ", "a) = (1, 2); a"),
+            ];
+            let chained = [" + 1", " ++ [1]", " < 2", " and true", "[0]", ".a", ".f()"];
+            for depth in (1..40).chain([100, MAX_NESTING, 1_000, 100_000]) {
+                let inputs = nested
+                    .iter()
+                    .map(|(open, close)| format!("{}1{}", open.repeat(depth), close.repeat(depth)))
+                    .chain(chained.iter().map(|op| format!("1{}", op.repeat(depth))));
+                for input in inputs {
+                    let result = cumin(&input).map(|(_, ast)| eval(ast, None));
+                    if depth == 100_000 {
+                        assert!(!matches!(result, Ok(Ok(_))));
+                    }
+                }
+            }
+            let input = format!("{}1{}", "(".repeat(20), ")".repeat(20));
+            assert_eq!(
+                eval(cumin(&input).unwrap().1, None).unwrap(),
+                crate::json::JSON::Nat(1)
+            );
+            let input = format!("1{}", " + 1".repeat(100));
+            assert_eq!(
+                eval(cumin(&input).unwrap().1, None).unwrap(),
+                crate::json::JSON::Nat(101)
+            );
+        });
+        run.join().unwrap();
+    }
+}
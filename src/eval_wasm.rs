@@ -6,5 +6,5 @@ use anyhow::Result;
 pub fn eval_wasm(cumin: Cumin) -> Result<JSON> {
     let mut env = Environ::wasm();
     let val = eval_cumin(&mut env, &cumin)?;
-    Ok(JSON::from_cumin(val))
+    JSON::from_cumin(val)
 }
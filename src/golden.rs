@@ -0,0 +1,184 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+/// What a golden case expects of its `x.cumin`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    /// `x.json`, the output it compiles to
+    Json(PathBuf),
+    /// `x.fail`, that it cannot be compiled
+    Fail(PathBuf),
+}
+
+/// A golden case: `x.cumin` with `x.json` or `x.fail`, and `x.env` as its environ variables if exists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub cumin: PathBuf,
+    pub expect: Expect,
+    pub env: Option<PathBuf>,
+}
+
+/// The cases in `dir`: the `.json` ones, then the `.fail` ones, each in path order.
+pub fn cases(dir: &Path) -> Result<Vec<Case>> {
+    let mut paths = read_dir(dir)
+        .map_err(|err| anyhow!("Cannot read `{}` ({})", dir.display(), err))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    let mut cases = vec![];
+    for ext in ["json", "fail"].iter() {
+        for path in paths
+            .iter()
+            .filter(|path| path.extension().is_some_and(|e| e == *ext))
+        {
+            let env = path.with_extension("env");
+            cases.push(Case {
+                cumin: path.with_extension("cumin"),
+                expect: match *ext {
+                    "json" => Expect::Json(path.clone()),
+                    _ => Expect::Fail(path.clone()),
+                },
+                env: if env.is_file() { Some(env) } else { None },
+            });
+        }
+    }
+    Ok(cases)
+}
+
+/// Environ variables of an `x.env`, one `KEY=VALUE` a line.
+pub fn env_vars(content: &str) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        match line.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                vars.insert(key.to_string(), value.to_string());
+            }
+            _ => bail!("Expected `KEY=VALUE`, not `{}`", line),
+        }
+    }
+    Ok(vars)
+}
+
+pub fn read_env_vars(path: &Path) -> Result<HashMap<String, String>> {
+    let content = read_to_string(path)
+        .map_err(|err| anyhow!("Cannot open `{}` ({})", path.display(), err))?;
+    env_vars(&content).map_err(|err| anyhow!("{} in `{}`", err, path.display()))
+}
+
+/// Where `actual` differs from `expected`, a line each, with the JSON path of the difference.
+/// Object keys are compared regardless of their order, and numbers by their values (`1` is `1.0`).
+pub fn diff(expected: &Value, actual: &Value) -> Vec<String> {
+    let mut diffs = vec![];
+    diff_at("$", expected, actual, &mut diffs);
+    diffs
+}
+
+fn diff_at(path: &str, expected: &Value, actual: &Value, diffs: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (key, x) in e.iter() {
+                let path = format!("{}{}", path, key_path(key));
+                match a.get(key) {
+                    Some(y) => diff_at(&path, x, y, diffs),
+                    None => diffs.push(format!("{}: missing (expected {})", path, x)),
+                }
+            }
+            for (key, y) in a.iter().filter(|(key, _)| !e.contains_key(*key)) {
+                diffs.push(format!("{}{}: unexpected {}", path, key_path(key), y));
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            for (i, (x, y)) in e.iter().zip(a.iter()).enumerate() {
+                diff_at(&format!("{}[{}]", path, i), x, y, diffs);
+            }
+            for (i, x) in e.iter().enumerate().skip(a.len()) {
+                diffs.push(format!("{}[{}]: missing (expected {})", path, i, x));
+            }
+            for (i, y) in a.iter().enumerate().skip(e.len()) {
+                diffs.push(format!("{}[{}]: unexpected {}", path, i, y));
+            }
+        }
+        (Value::Number(x), Value::Number(y))
+            if x == y || ((x.is_f64() || y.is_f64()) && x.as_f64() == y.as_f64()) => {}
+        (x, y) if x == y => {}
+        (x, y) => diffs.push(format!("{}: expected {}, got {}", path, x, y)),
+    }
+}
+
+fn key_path(key: &str) -> String {
+    let mut chars = key.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if identifier {
+        format!(".{}", key)
+    } else {
+        format!("[{}]", Value::String(key.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test_golden {
+    use crate::golden::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        assert!(diff(
+            &json!({"a": 1, "b": [1.0, "x"]}),
+            &json!({"b": [1, "x"], "a": 1.0})
+        )
+        .is_empty());
+        assert_eq!(
+            diff(
+                &json!({"replicas": 3, "servers": ["a", "b"], "tls": true}),
+                &json!({"replicas": 2, "servers": ["a"], "extra key": null})
+            ),
+            vec![
+                "$.replicas: expected 3, got 2",
+                "$.servers[1]: missing (expected \"b\")",
+                "$.tls: missing (expected true)",
+                "$[\"extra key\"]: unexpected null",
+            ]
+        );
+        assert_eq!(
+            diff(&json!([{"x": 1}]), &json!([{"x": "1"}, 2])),
+            vec!["$[0].x: expected 1, got \"1\"", "$[1]: unexpected 2"]
+        );
+        assert_eq!(diff(&json!(1), &json!(1.5)), vec!["$: expected 1, got 1.5"]);
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let vars = env_vars("X=11\n\nURL=http://a?b=c\n").unwrap();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars["URL"], "http://a?b=c");
+        assert!(env_vars("X").is_err());
+        assert!(env_vars("=1").is_err());
+    }
+
+    #[test]
+    fn test_cases() {
+        let cases = cases(Path::new("examples")).unwrap();
+        let env = cases
+            .iter()
+            .find(|case| case.cumin == Path::new("examples/env.cumin"))
+            .unwrap();
+        assert_eq!(env.expect, Expect::Json(PathBuf::from("examples/env.json")));
+        assert_eq!(env.env, Some(PathBuf::from("examples/env.env")));
+        assert!(
+            cases
+                .iter()
+                .any(|case| case.expect
+                    == Expect::Fail(PathBuf::from("examples/assert_invalid.fail")))
+        );
+    }
+}
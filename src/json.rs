@@ -1,10 +1,17 @@
+use crate::datetime::*;
 use crate::parser::value::*;
+use crate::units::*;
+use anyhow::Result;
+use rust_decimal::Decimal;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum JSON {
     Nat(u128),
     Int(i128),
     Float(f64),
+    Decimal(Decimal),
+    Duration(u128),
+    Bytes(u128),
     Bool(bool),
     Str(String),
     Array(Vec<JSON>),
@@ -19,8 +26,11 @@ impl JSON {
             Nat(x) => format!("{}", x),
             Int(x) => format!("{}", x),
             Float(x) => format!("{}", x),
+            Decimal(x) => x.to_string(),
+            Duration(x) => Str(humanize_duration(*x)).stringify(),
+            Bytes(x) => Str(humanize_bytes(*x)).stringify(),
             Bool(x) => format!("{:?}", x),
-            Str(x) => format!("{:?}", x),
+            Str(x) => serde_json::Value::String(x.to_string()).to_string(),
             Array(xs) => format!(
                 "[{}]",
                 xs.iter()
@@ -38,21 +48,32 @@ impl JSON {
             Null => "null".to_string(),
         }
     }
-    pub fn from_cumin(val: Value) -> Self {
+    pub fn from_cumin(val: Value) -> Result<Self> {
         use JSON::*;
-        match val {
+        let json = match val {
             Value::Null => Null,
             Value::Nat(x) => Nat(x),
             Value::Int(x) => Int(x),
             Value::Float(x) => Float(x),
+            Value::Decimal(x) => Decimal(x),
+            Value::Duration(x) => Duration(x),
+            Value::Bytes(x) => Bytes(x),
+            Value::Date(x) => Str(format_date(&x)),
+            Value::Time(x) => Str(format_time(&x)),
+            Value::DateTime(x) => Str(format_datetime(&x)),
+            Value::Ip(x) => Str(x.to_string()),
+            Value::Cidr(x) => Str(x.to_string()),
+            Value::SocketAddr(x) => Str(x.to_string()),
+            Value::Url(x) => Str(x.to_string()),
+            Value::SemVer(x) => Str(x.to_string()),
             Value::Bool(x) => Bool(x),
             Value::Str(x) => Str(x),
-            Value::Env(v, _) => panic!("Env {} is unresolved", v),
+            Value::Env(v, _) => bail!("Env {} is unresolved", v),
             Value::Dict(_name, items) => {
                 let items: Vec<(String, JSON)> = items
                     .iter()
-                    .map(|(key, val)| (key.to_string(), JSON::from_cumin((*val).clone())))
-                    .collect();
+                    .map(|(key, val)| Ok((key.to_string(), JSON::from_cumin((*val).clone())?)))
+                    .collect::<Result<_>>()?;
                 Dict(items)
             }
             Value::EnumVariant(_, t) => Str(t),
@@ -60,21 +81,86 @@ impl JSON {
                 let elements = elements
                     .iter()
                     .map(|e| JSON::from_cumin((*e).clone()))
-                    .collect();
+                    .collect::<Result<_>>()?;
                 Array(elements)
             }
             Value::Tuple(elements) => {
                 let elements = elements
                     .iter()
                     .map(|e| JSON::from_cumin((*e).clone()))
-                    .collect();
+                    .collect::<Result<_>>()?;
                 Array(elements)
             }
             Value::Optional(_typ, val) => match *val {
-                Some(x) => JSON::from_cumin(x),
+                Some(x) => JSON::from_cumin(x)?,
                 None => JSON::Null,
             },
-            Value::Wrapped(_typ, val) => JSON::from_cumin(*val),
+            Value::Wrapped(_typ, val) => JSON::from_cumin(*val)?,
+        };
+        Ok(json)
+    }
+
+    /// Writes typed values out as `output` asks.
+    pub fn with_output(self, output: &Output) -> Self {
+        use JSON::*;
+        match self {
+            Decimal(x) => match output.decimal {
+                DecimalOutput::Number => Decimal(x),
+                DecimalOutput::String => Str(x.to_string()),
+            },
+            Duration(x) => in_unit(x, output.duration, humanize_duration),
+            Bytes(x) => in_unit(x, output.bytes, humanize_bytes),
+            Array(xs) => Array(xs.into_iter().map(|x| x.with_output(output)).collect()),
+            Dict(d) => Dict(
+                d.into_iter()
+                    .map(|(key, val)| (key, val.with_output(output)))
+                    .collect(),
+            ),
+            json => json,
+        }
+    }
+}
+
+fn in_unit(x: u128, output: UnitOutput, humanize: fn(u128) -> String) -> JSON {
+    match output {
+        UnitOutput::Human => JSON::Str(humanize(x)),
+        UnitOutput::Number(factor) if x.is_multiple_of(factor) => JSON::Nat(x / factor),
+        UnitOutput::Number(factor) => JSON::Float(x as f64 / factor as f64),
+    }
+}
+
+/// How typed values are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Output {
+    pub decimal: DecimalOutput,
+    pub duration: UnitOutput,
+    pub bytes: UnitOutput,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            decimal: DecimalOutput::Number,
+            duration: UnitOutput::Human,
+            bytes: UnitOutput::Human,
+        }
+    }
+}
+
+/// How Decimal values are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecimalOutput {
+    Number,
+    String,
+}
+
+impl std::str::FromStr for DecimalOutput {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "number" => Ok(DecimalOutput::Number),
+            "string" => Ok(DecimalOutput::String),
+            _ => bail!("Unknown decimal output: {} (expected number or string)", s),
         }
     }
 }
@@ -105,5 +191,41 @@ mod test_json {
             "[[],1,2,\"3\"]"
         );
         assert_eq!(Array(vec![Null, Nat(1)]).stringify(), "[null,1]");
+        assert_eq!(
+            Str("\u{1}\u{7f}\u{1F680}".to_string()).stringify(),
+            "\"\\u0001\u{7f}\u{1F680}\""
+        );
+    }
+
+    #[test]
+    fn test_with_output() {
+        let d = Dict(vec![
+            (
+                "price".to_string(),
+                Array(vec![Decimal("12.50".parse().unwrap()), Nat(1)]),
+            ),
+            ("timeout".to_string(), Duration(1_500_000_000)),
+            ("limit".to_string(), Bytes(512 * 1024)),
+        ]);
+        assert_eq!(
+            d.clone().with_output(&Output::default()).stringify(),
+            "{\"price\":[12.50,1],\"timeout\":\"1s500ms\",\"limit\":\"512KiB\"}"
+        );
+        let output = Output {
+            decimal: DecimalOutput::String,
+            duration: UnitOutput::Number(1_000_000_000),
+            bytes: UnitOutput::Number(1024),
+        };
+        assert_eq!(
+            d.with_output(&output).stringify(),
+            "{\"price\":[\"12.50\",1],\"timeout\":1.5,\"limit\":512}"
+        );
+    }
+
+    #[test]
+    fn test_from_cumin() {
+        use crate::parser::value::Value;
+        assert_eq!(JSON::from_cumin(Value::Nat(1)).unwrap(), Nat(1));
+        assert!(JSON::from_cumin(Value::Env("HOME".to_string(), None)).is_err());
     }
 }
@@ -3,20 +3,28 @@ extern crate anyhow;
 extern crate nom;
 
 pub mod builtins;
+pub mod datetime;
 pub mod errors;
 pub mod eval;
 pub mod eval_wasm;
+pub mod golden;
 pub mod json;
+pub mod net;
 pub mod parser;
+pub mod semver;
+pub mod units;
 
-use crate::{eval_wasm::eval_wasm, parser::cumin::cumin};
+use crate::{
+    eval_wasm::eval_wasm,
+    parser::cumin::{cumin, parse_error},
+};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub fn compile(input: &str) -> String {
     match cumin(input) {
         Ok((rest, data)) => {
-            if rest == "" {
+            if rest.is_empty() {
                 match eval_wasm(data) {
                     Ok(data) => data.stringify(),
                     Err(err) => format!("Error: eval failed ({:?})", err),
@@ -25,6 +33,9 @@ pub fn compile(input: &str) -> String {
                 format!("Error: Parsing stopped at {}", &rest)
             }
         }
-        Err(err) => format!("Error: Parsing failed ({:?})", &err),
+        Err(err) => match parse_error(&err) {
+            Some(msg) => format!("Error: Parsing failed ({})", msg),
+            None => format!("Error: Parsing failed ({:?})", &err),
+        },
     }
 }
@@ -0,0 +1,278 @@
+use crate::parser::value::Value;
+use anyhow::Result;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A network `addr/prefix` whose host bits are all zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some(parts) => parts,
+            None => bail!("missing /prefix"),
+        };
+        let addr: IpAddr = addr.parse()?;
+        let prefix: u8 = match prefix.parse() {
+            Ok(prefix) if prefix <= max_prefix(&addr) => prefix,
+            _ => bail!("prefix must be 0 to {}", max_prefix(&addr)),
+        };
+        let cidr = Cidr { addr, prefix };
+        if cidr.network() != addr {
+            bail!("host bits are set; did you mean {}?", cidr.masked());
+        }
+        Ok(cidr)
+    }
+
+    fn network(&self) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(a) & mask))
+            }
+        }
+    }
+
+    fn masked(&self) -> Cidr {
+        Cidr {
+            addr: self.network(),
+            prefix: self.prefix,
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        ip.is_ipv4() == self.addr.is_ipv4()
+            && Cidr {
+                addr: *ip,
+                prefix: self.prefix,
+            }
+            .network()
+                == self.addr
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+/// A hierarchical URL, `scheme://[userinfo@]host[:port][/path][?query][#fragment]`,
+/// with its scheme and host in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Url {
+    pub scheme: String,
+    pub userinfo: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+impl Url {
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(c) = s.chars().find(|c| c.is_whitespace() || c.is_control()) {
+            bail!("{:?} is not allowed", c);
+        }
+        let (scheme, rest) = match s.split_once("://") {
+            Some(parts) => parts,
+            None => bail!("missing scheme://"),
+        };
+        let mut chars = scheme.chars();
+        let valid_scheme = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+        if !valid_scheme {
+            bail!("invalid scheme {:?}", scheme);
+        }
+        let scheme = scheme.to_ascii_lowercase();
+
+        let (rest, fragment) = split_off(rest, '#');
+        let (rest, query) = split_off(rest, '?');
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (userinfo, hostport) = match authority.rsplit_once('@') {
+            Some((userinfo, hostport)) => (Some(userinfo.to_string()), hostport),
+            None => (None, authority),
+        };
+        let (host, port) = if let Some(v6) = hostport.strip_prefix('[') {
+            let (addr, port) = match v6.split_once(']') {
+                Some(parts) => parts,
+                None => bail!("unclosed [ in host"),
+            };
+            let addr: Ipv6Addr = addr.parse()?;
+            let port = match port {
+                "" => None,
+                _ => match port.strip_prefix(':') {
+                    Some(port) => Some(port),
+                    None => bail!("unexpected {:?} after host", port),
+                },
+            };
+            (format!("[{}]", addr), port)
+        } else {
+            let (host, port) = match hostport.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (hostport, None),
+            };
+            (hostname(host)?, port)
+        };
+        let port = match port {
+            Some(port) if port.bytes().all(|b| b.is_ascii_digit()) => match port.parse::<u16>() {
+                Ok(port) => Some(port),
+                Err(_) => bail!("invalid port {:?}", port),
+            },
+            Some(port) => bail!("invalid port {:?}", port),
+            None => None,
+        };
+        let port = port.filter(|port| Some(*port) != default_port(&scheme));
+        Ok(Url {
+            scheme,
+            userinfo,
+            host,
+            port,
+            path: path.to_string(),
+            query,
+            fragment,
+        })
+    }
+
+    /// The explicit port, or the well-known one of the scheme.
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port.or_else(|| default_port(&self.scheme))
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://", self.scheme)?;
+        if let Some(userinfo) = &self.userinfo {
+            write!(f, "{}@", userinfo)?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+fn split_off(s: &str, sep: char) -> (&str, Option<String>) {
+    match s.split_once(sep) {
+        Some((s, rest)) => (s, Some(rest.to_string())),
+        None => (s, None),
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        _ => None,
+    }
+}
+
+/// An IPv4 address or a DNS name of letters, digits and hyphens.
+fn hostname(host: &str) -> Result<String> {
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(host.to_string());
+    }
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if host.len() > 253 || !host.split('.').all(valid_label) {
+        bail!("invalid host {:?}", host);
+    }
+    Ok(host.to_ascii_lowercase())
+}
+
+/// `network`, `prefix` of a Cidr; `ip`, `port` of a SocketAddr;
+/// `scheme`, `host`, `port`, `path` of a Url.
+pub fn component(val: &Value, name: &str) -> Option<Value> {
+    use crate::parser::typing::Typing;
+    let nat = |x: u16| Value::Nat(x as u128);
+    match (val, name) {
+        (Value::Cidr(cidr), "network") => Some(Value::Ip(cidr.addr)),
+        (Value::Cidr(cidr), "prefix") => Some(Value::Nat(cidr.prefix as u128)),
+        (Value::SocketAddr(addr), "ip") => Some(Value::Ip(addr.ip())),
+        (Value::SocketAddr(addr), "port") => Some(nat(addr.port())),
+        (Value::Url(url), "scheme") => Some(Value::Str(url.scheme.clone())),
+        (Value::Url(url), "host") => Some(Value::Str(url.host.clone())),
+        (Value::Url(url), "port") => Some(Value::Optional(
+            Typing::Nat,
+            Box::new(url.port_or_default().map(nat)),
+        )),
+        (Value::Url(url), "path") => Some(Value::Str(url.path.clone())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_net {
+    use crate::net::*;
+
+    #[test]
+    fn test_cidr() {
+        let cidr = Cidr::parse("10.0.0.0/8").unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert!(cidr.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"::1".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains(&"1.2.3.4".parse().unwrap()));
+        let cidr = Cidr::parse("2001:DB8::/32").unwrap();
+        assert_eq!(cidr.to_string(), "2001:db8::/32");
+        assert!(cidr.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.1/8").is_err());
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0.0").is_err());
+    }
+
+    #[test]
+    fn test_url() {
+        let url = Url::parse("HTTPS://User@Example.COM:443/api?q=1#top").unwrap();
+        assert_eq!(url.to_string(), "https://User@example.com/api?q=1#top");
+        assert_eq!(url.port_or_default(), Some(443));
+        let url = Url::parse("http://[::1]:8080").unwrap();
+        assert_eq!(url.to_string(), "http://[::1]:8080/");
+        assert_eq!(url.host, "[::1]");
+        assert_eq!(url.port, Some(8080));
+        assert_eq!(Url::parse("redis://cache:6379").unwrap().port, Some(6379));
+        assert!(Url::parse("example.com").is_err());
+        assert!(Url::parse("http://exa mple.com").is_err());
+        assert!(Url::parse("http://example..com").is_err());
+        assert!(Url::parse("http://example.com:99999").is_err());
+        assert!(Url::parse("http://example.com:").is_err());
+        assert!(Url::parse("http://-bad.com").is_err());
+        assert!(Url::parse("1http://example.com").is_err());
+    }
+}
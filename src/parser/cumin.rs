@@ -3,7 +3,7 @@ use crate::parser::statement::*;
 use crate::parser::util::*;
 use crate::parser::value::*;
 
-use nom::{branch::alt, combinator::map, multi::many0, sequence::tuple, IResult};
+use nom::{branch::alt, combinator::map, error::Error, multi::many0, sequence::tuple, IResult};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Cumin(pub Vec<Statement>, pub Expr);
@@ -20,6 +20,14 @@ pub fn cumin(input: &str) -> IResult<&str, Cumin> {
     )(input)
 }
 
+/// Why `cumin` failed, for the literals that it rejects rather than reads otherwise.
+pub fn parse_error(err: &nom::Err<Error<&str>>) -> Option<String> {
+    match err {
+        nom::Err::Failure(e) => invalid_literal(e.input),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test_cumin {
     use crate::parser::cumin::*;
@@ -71,7 +79,8 @@ mod test_cumin {
             Cumin(
                 vec![Struct(
                     "X".to_string(),
-                    vec![("x".to_string(), Typing::Int, None)]
+                    vec![("x".to_string(), Typing::Int, None, None)],
+                    vec![]
                 )],
                 Add(
                     Box::new(Expr::Var("x".to_string())),
@@ -83,7 +92,11 @@ mod test_cumin {
             "struct X { x: Int } let x=1; X(x)",
             Cumin(
                 vec![
-                    Struct("X".to_string(), vec![("x".to_string(), Typing::Int, None)]),
+                    Struct(
+                        "X".to_string(),
+                        vec![("x".to_string(), Typing::Int, None, None)],
+                        vec![]
+                    ),
                     Let("x".to_string(), Typing::Any, Val(Nat(1)))
                 ],
                 Apply("X".to_string(), vec![Expr::Var("x".to_string())], vec![])
@@ -94,7 +107,8 @@ mod test_cumin {
             Cumin(
                 vec![Struct(
                     "X".to_string(),
-                    vec![("x".to_string(), Typing::Int, None)]
+                    vec![("x".to_string(), Typing::Int, None, None)],
+                    vec![]
                 ),],
                 Apply(
                     "X".to_string(),
@@ -114,10 +128,11 @@ mod test_cumin {
                 vec![Struct(
                     "X".to_string(),
                     vec![
-                        ("x".to_string(), Typing::Int, Some(Val(Nat(2)))),
-                        ("y".to_string(), Typing::Int, None),
-                        ("z".to_string(), Typing::Int, Some(Val(Nat(42)))),
-                    ]
+                        ("x".to_string(), Typing::Int, None, Some(Val(Nat(2)))),
+                        ("y".to_string(), Typing::Int, None, None),
+                        ("z".to_string(), Typing::Int, None, Some(Val(Nat(42)))),
+                    ],
+                    vec![]
                 ),],
                 Apply(
                     "X".to_string(),
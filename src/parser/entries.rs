@@ -9,7 +9,7 @@ impl Entries {
     pub fn new(data: Vec<(String, Value)>) -> Self {
         Self { data }
     }
-    pub fn iter(&self) -> std::slice::Iter<(String, Value)> {
+    pub fn iter(&self) -> std::slice::Iter<'_, (String, Value)> {
         self.data.iter()
     }
 }
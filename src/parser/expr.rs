@@ -5,20 +5,25 @@ use crate::parser::value::*;
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::space1,
-    combinator::{map, opt, peek},
-    multi::{fold_many0, many1, separated_list0, separated_list1},
-    sequence::{delimited, preceded, terminated, tuple},
+    bytes::complete::{is_not, tag},
+    character::complete::{char, digit1, none_of, one_of, space1},
+    combinator::{all_consuming, map, map_opt, map_res, opt, peek, verify},
+    error::{Error, ErrorKind},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
+use std::cell::Cell;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Val(Value),
     Var(String),
     Apply(String, Vec<Expr>, Vec<(String, Expr)>),
+    MethodCall(Box<Expr>, String, Vec<Expr>, Vec<(String, Expr)>),
     AnonymousStruct(Vec<(String, Typing, Expr)>),
+    StructUpdate(String, Box<Expr>, Vec<(String, Expr)>),
+    Spread(Vec<Expr>),
     Concat(Box<Expr>, Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
@@ -26,6 +31,11 @@ pub enum Expr {
     Div(Box<Expr>, Box<Expr>),
     Mod(Box<Expr>, Box<Expr>),
     Pow(Box<Expr>, Box<Expr>),
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    BitXor(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    Shr(Box<Expr>, Box<Expr>),
     Minus(Box<Expr>),
     And(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
@@ -38,75 +48,173 @@ pub enum Expr {
     Blocked(Box<Cumin>),
     AsCast(Box<Expr>, Typing),
     Prop(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    Interpolated(Vec<Segment>),
 }
 
-// <EXPR> ::= <AS>
-// <LOGIC> ::= <AB> {==, !=, <, >, <=, >=} <AB> | <AB>
-// <AB> ::= <TERM> {and,or,xor,+,-} <TERM> | <TERM>
+/// A piece of an interpolated string `f"..."`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Lit(String),
+    Expr(Expr, FormatSpec),
+}
+
+/// `{expr:[[fill]align][0][width][.precision]}`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FormatSpec {
+    pub fill: Option<char>,
+    pub align: Option<char>,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+}
+
+// <EXPR> ::= <LOGIC>
+// <LOGIC> ::= <COMPARE> {and,or,xor} <COMPARE> | <COMPARE>
+// <COMPARE> ::= <BITOR> { {==, !=, <, >, <=, >=} <BITOR> }
+// <BITOR> ::= <BITXOR> {|} <BITXOR> | <BITXOR>
+// <BITXOR> ::= <BITAND> {^} <BITAND> | <BITAND>
+// <BITAND> ::= <SHIFT> {&} <SHIFT> | <SHIFT>
+// <SHIFT> ::= <AB> {<<,>>} <AB> | <AB>
+// <AB> ::= <TERM> {++,+,-} <TERM> | <TERM>
 // <TERM> ::= <AS> {*,/,**} <AS> | <AS>
-// <AS> ::= <FACTOR> as <TYPE> | <FACTOR>
+// <AS> ::= <POSTFIX> as <TYPE> | <POSTFIX>
+// <POSTFIX> ::= <FACTOR> { [<EXPR>] | [<EXPR>..<EXPR>] | .<IDENTIFIER>(...) | .<IDENTIFIER> | .<NAT> }
 // <FACTOR> ::= ( <EXPR> ) | -<TERM> | not <TERM>
-//            | f(x) | S{x=x} | { ... } | Z::X | {{ ... }}
+//            | f(x) | S{x=x} | S{..<EXPR>, x=x} | { ... } | Z::X | {{ ... }} | {{ ..<EXPR>, x=x }}
 //            | [ <EXPR> ,... ]
-//            | <IDENTIFIER>.x
+//            | <IDENTIFIER>
+//            | f"...{ <EXPR> }..."
 
 pub fn expr(input: &str) -> IResult<&str, Expr> {
     terminated(logic_expr, commentable_spaces)(input)
 }
 
 pub fn logic_expr(input: &str) -> IResult<&str, Expr> {
-    let compare = map(
+    let (input, x) = compare_expr(input)?;
+    fold_chain(
         tuple((
-            terminated(ab_expr, commentable_spaces),
-            terminated(
-                alt((
-                    tag("=="),
-                    tag("!="),
-                    tag("<="),
-                    tag(">="),
-                    tag("<"),
-                    tag(">"),
-                )),
-                commentable_spaces,
-            ),
-            terminated(ab_expr, commentable_spaces),
+            terminated(alt((tag("and"), tag("or"), tag("xor"))), commentable_spaces),
+            compare_expr,
+        )),
+        x,
+        |acc, (op, val)| match op {
+            "and" => Expr::And(Box::new(acc), Box::new(val)),
+            "or" => Expr::Or(Box::new(acc), Box::new(val)),
+            "xor" => Expr::Xor(Box::new(acc), Box::new(val)),
+            _ => unreachable!("only and, or and xor are parsed here"),
+        },
+    )(input)
+}
+
+/// A chain `a < b <= c` holds when each adjacent pair does: `a < b and b <= c`.
+fn compare_expr(input: &str) -> IResult<&str, Expr> {
+    let (input, x) = terminated(bit_or_expr, commentable_spaces)(input)?;
+    let (input, (chain, last)) = fold_chain(
+        tuple((compare_op, terminated(bit_or_expr, commentable_spaces))),
+        (None, x),
+        |(chain, left): (Option<Expr>, Expr), (op, right)| {
+            let cmp = compare(&left, op, &right);
+            let chain = match chain {
+                Some(acc) => Expr::And(Box::new(acc), Box::new(cmp)),
+                None => cmp,
+            };
+            (Some(chain), right)
+        },
+    )(input)?;
+    Ok((input, chain.unwrap_or(last)))
+}
+
+/// A single comparison `x <op> y`, keeping its operands in source order.
+pub fn comparison(input: &str) -> IResult<&str, (Expr, &str, Expr)> {
+    tuple((
+        terminated(bit_or_expr, commentable_spaces),
+        compare_op,
+        terminated(bit_or_expr, commentable_spaces),
+    ))(input)
+}
+
+fn compare_op(input: &str) -> IResult<&str, &str> {
+    terminated(
+        alt((
+            tag("=="),
+            tag("!="),
+            tag("<="),
+            tag(">="),
+            tag("<"),
+            tag(">"),
+        )),
+        commentable_spaces,
+    )(input)
+}
+
+fn compare(x: &Expr, op: &str, y: &Expr) -> Expr {
+    let (x, y) = (Box::new(x.clone()), Box::new(y.clone()));
+    match op {
+        "==" => Expr::Equal(x, y),
+        "!=" => Expr::Not(Box::new(Expr::Equal(x, y))),
+        "<=" => Expr::Not(Box::new(Expr::Less(y, x))),
+        ">=" => Expr::Not(Box::new(Expr::Less(x, y))),
+        "<" => Expr::Less(x, y),
+        ">" => Expr::Less(y, x),
+        _ => unreachable!("compare_op only yields ==, !=, <=, >=, < and >"),
+    }
+}
+
+fn bit_or_expr(input: &str) -> IResult<&str, Expr> {
+    let (input, x) = bit_xor_expr(input)?;
+    fold_chain(
+        preceded(terminated(tag("|"), commentable_spaces), bit_xor_expr),
+        x,
+        |acc, val| Expr::BitOr(Box::new(acc), Box::new(val)),
+    )(input)
+}
+
+fn bit_xor_expr(input: &str) -> IResult<&str, Expr> {
+    let (input, x) = bit_and_expr(input)?;
+    fold_chain(
+        preceded(terminated(tag("^"), commentable_spaces), bit_and_expr),
+        x,
+        |acc, val| Expr::BitXor(Box::new(acc), Box::new(val)),
+    )(input)
+}
+
+fn bit_and_expr(input: &str) -> IResult<&str, Expr> {
+    let (input, x) = shift_expr(input)?;
+    fold_chain(
+        preceded(terminated(tag("&"), commentable_spaces), shift_expr),
+        x,
+        |acc, val| Expr::BitAnd(Box::new(acc), Box::new(val)),
+    )(input)
+}
+
+fn shift_expr(input: &str) -> IResult<&str, Expr> {
+    let (input, x) = ab_expr(input)?;
+    fold_chain(
+        tuple((
+            terminated(alt((tag("<<"), tag(">>"))), commentable_spaces),
+            ab_expr,
         )),
-        |(x, op, y)| match op {
-            "==" => Expr::Equal(Box::new(x), Box::new(y)),
-            "!=" => Expr::Not(Box::new(Expr::Equal(Box::new(x), Box::new(y)))),
-            "<=" => Expr::Not(Box::new(Expr::Less(Box::new(y), Box::new(x)))),
-            ">=" => Expr::Not(Box::new(Expr::Less(Box::new(x), Box::new(y)))),
-            "<" => Expr::Less(Box::new(x), Box::new(y)),
-            ">" => Expr::Less(Box::new(y), Box::new(x)),
+        x,
+        |acc, (op, val)| match op {
+            "<<" => Expr::Shl(Box::new(acc), Box::new(val)),
+            ">>" => Expr::Shr(Box::new(acc), Box::new(val)),
             _ => panic!(),
         },
-    );
-    alt((compare, ab_expr))(input)
+    )(input)
 }
 
 fn ab_expr(input: &str) -> IResult<&str, Expr> {
     let (input, x) = term(input)?;
     let (input, _) = commentable_spaces(input)?;
-    fold_many0(
+    fold_chain(
         tuple((
-            terminated(
-                alt((
-                    tag("and"),
-                    tag("or"),
-                    tag("xor"),
-                    tag("++"),
-                    tag("+"),
-                    tag("-"),
-                )),
-                commentable_spaces,
-            ),
+            terminated(alt((tag("++"), tag("+"), tag("-"))), commentable_spaces),
             term,
         )),
         x,
         |acc, (op, val)| match op {
-            "and" => Expr::And(Box::new(acc), Box::new(val)),
-            "or" => Expr::Or(Box::new(acc), Box::new(val)),
-            "xor" => Expr::Xor(Box::new(acc), Box::new(val)),
             "++" => Expr::Concat(Box::new(acc), Box::new(val)),
             "+" => Expr::Add(Box::new(acc), Box::new(val)),
             "-" => Expr::Sub(Box::new(acc), Box::new(val)),
@@ -118,7 +226,7 @@ fn ab_expr(input: &str) -> IResult<&str, Expr> {
 fn term(input: &str) -> IResult<&str, Expr> {
     let (input, x) = as_expr(input)?;
     let (input, _) = commentable_spaces(input)?;
-    fold_many0(
+    fold_chain(
         tuple((
             terminated(
                 alt((tag("**"), tag("*"), tag("/"), tag("%"))),
@@ -139,25 +247,217 @@ fn term(input: &str) -> IResult<&str, Expr> {
 
 fn as_expr(input: &str) -> IResult<&str, Expr> {
     // <expr> as <typing>
-    let as_expr = map(
+    map(
+        tuple((
+            terminated(postfix, commentable_spaces),
+            opt(preceded(terminated(tag("as"), commentable_spaces), typing)),
+        )),
+        |(e, typ)| match typ {
+            Some(typ) => Expr::AsCast(Box::new(e), typ),
+            None => e,
+        },
+    )(input)
+}
+
+/// Positional and keyword arguments.
+type Arguments = (Vec<Expr>, Vec<(String, Expr)>);
+
+/// `<expr>, ..., <identifier> = <expr>, ...`
+fn arguments(input: &str) -> IResult<&str, Arguments> {
+    let an_arg = alt((
+        map(
+            tuple((
+                terminated(identifier, commentable_spaces),
+                terminated(tag("="), commentable_spaces),
+                expr,
+            )),
+            |(name, _, e)| (Some(name), e),
+        ),
+        map(expr, |e| (None, e)),
+    ));
+    let comma = || tuple((tag(","), commentable_spaces));
+    map(
+        terminated(
+            terminated(separated_list0(comma(), an_arg), opt(comma())),
+            commentable_spaces,
+        ),
+        |mixed_args| {
+            let mut args: Vec<Expr> = vec![];
+            let mut kwargs: Vec<(String, Expr)> = vec![];
+            for (name, val) in mixed_args {
+                match name {
+                    None => {
+                        args.push(val);
+                    }
+                    Some(name) => {
+                        kwargs.push((name, val));
+                    }
+                }
+            }
+            (args, kwargs)
+        },
+    )(input)
+}
+
+fn postfix(input: &str) -> IResult<&str, Expr> {
+    enum Op {
+        Index(Expr),
+        Slice(Option<Expr>, Option<Expr>),
+        Prop(String),
+        Call(String, Vec<Expr>, Vec<(String, Expr)>),
+    }
+
+    // [ <expr> ] or [ <expr>? .. <expr>? ]
+    let index_or_slice = map_opt(
+        tuple((
+            terminated(tag("["), commentable_spaces),
+            opt(expr),
+            opt(preceded(
+                terminated(tag(".."), commentable_spaces),
+                opt(expr),
+            )),
+            terminated(tag("]"), commentable_spaces),
+        )),
+        |(_, start, end, _)| match (start, end) {
+            (start, Some(end)) => Some(Op::Slice(start, end)),
+            (Some(i), None) => Some(Op::Index(i)),
+            (None, None) => None,
+        },
+    );
+
+    // .<identifier> or .<nat>
+    let prop = map(
         tuple((
-            terminated(factor, commentable_spaces),
-            terminated(tag("as"), commentable_spaces),
-            typing,
+            tag("."),
+            commentable_spaces,
+            alt((identifier, map(digit1, |d: &str| d.to_string()))),
+            commentable_spaces,
+        )),
+        |(_, _, name, _)| Op::Prop(name),
+    );
+
+    // .<identifier>( <args> ), a method call with the receiver as the first argument
+    let call = map(
+        tuple((
+            tag("."),
+            commentable_spaces,
+            identifier,
+            commentable_spaces,
+            delimited(
+                terminated(tag("("), commentable_spaces),
+                arguments,
+                tag(")"),
+            ),
+            commentable_spaces,
         )),
-        |(e, _, typ)| Expr::AsCast(Box::new(e), typ),
+        |(_, _, name, _, (args, kwargs), _)| Op::Call(name, args, kwargs),
     );
-    alt((as_expr, factor))(input)
+
+    let (input, x) = factor(input)?;
+    fold_chain(
+        nested(alt((index_or_slice, call, prop))),
+        x,
+        |acc, op| match op {
+            Op::Index(i) => Expr::Index(Box::new(acc), Box::new(i)),
+            Op::Slice(start, end) => {
+                Expr::Slice(Box::new(acc), start.map(Box::new), end.map(Box::new))
+            }
+            Op::Prop(name) => Expr::Prop(Box::new(acc), name),
+            Op::Call(name, args, kwargs) => Expr::MethodCall(Box::new(acc), name, args, kwargs),
+        },
+    )(input)
+}
+
+/// How deep the parsed tree may grow before the input is rejected, rather than
+/// overflowing the stack when it is parsed, evaluated or dropped. Each operand folded
+/// into a chain such as `1 + 1 + ...` counts once, and each nested factor (parentheses,
+/// brackets, blocks, calls, prefix operators) counts `FACTOR_COST` times, as parsing
+/// one takes many more stack frames. Sized so that parsing a debug build stays well
+/// within a 2 MiB thread, the default for threads other than `main`.
+pub const MAX_NESTING: usize = 128;
+const FACTOR_COST: usize = 5;
+
+thread_local! {
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Holds `cost` levels of nesting for as long as it is alive.
+struct Nesting(usize);
+
+impl Nesting {
+    fn enter(input: &str, cost: usize) -> Result<Self, nom::Err<Error<&str>>> {
+        let depth = NESTING.with(Cell::get) + cost;
+        if depth > MAX_NESTING {
+            return Err(nom::Err::Failure(Error::new(input, ErrorKind::TooLarge)));
+        }
+        NESTING.with(|n| n.set(depth));
+        Ok(Nesting(cost))
+    }
+}
+
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        NESTING.with(|n| n.set(n.get() - self.0));
+    }
+}
+
+/// Like `fold_many0`, but each operand folded in holds a level of nesting until the
+/// chain ends, as it makes the tree built so far one level deeper.
+fn fold_chain<'a, A, O>(
+    mut item: impl FnMut(&'a str) -> IResult<&'a str, O>,
+    init: A,
+    fold: impl Fn(A, O) -> A,
+) -> impl FnOnce(&'a str) -> IResult<&'a str, A> {
+    move |mut input| {
+        let mut acc = init;
+        let mut levels = vec![];
+        loop {
+            match item(input) {
+                Ok((rest, o)) => {
+                    levels.push(Nesting::enter(rest, 1)?);
+                    acc = fold(acc, o);
+                    input = rest;
+                }
+                Err(nom::Err::Error(_)) => return Ok((input, acc)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Holds a factor's worth of nesting while `parser` runs, for the parsers of types and
+/// patterns as well as of expressions.
+pub fn nested<'a, O>(
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    move |input| {
+        let _nesting = Nesting::enter(input, FACTOR_COST)?;
+        parser(input)
+    }
 }
 
 fn factor(input: &str) -> IResult<&str, Expr> {
+    nested(nested_factor)(input)
+}
+
+fn nested_factor(input: &str) -> IResult<&str, Expr> {
+    // ( <expr> ) or ( <expr> , ... )
     let parened = map(
         tuple((
             terminated(tag("("), commentable_spaces),
-            terminated(expr, commentable_spaces),
+            separated_list1(
+                tuple((tag(","), commentable_spaces)),
+                terminated(expr, commentable_spaces),
+            ),
             tag(")"),
         )),
-        |(_, e, _)| e,
+        |(_, mut elems, _)| {
+            if elems.len() == 1 {
+                elems.remove(0)
+            } else {
+                Expr::Tuple(elems)
+            }
+        },
     );
     let minused = map(preceded(tag("-"), ab_expr), |e| Expr::Minus(Box::new(e)));
     let notted = map(
@@ -168,114 +468,128 @@ fn factor(input: &str) -> IResult<&str, Expr> {
         |e| Expr::Not(Box::new(e)),
     );
 
-    // <identifier>.<identifier> ( <expr>, ..., <identifier> = <expr>, ... )
-    let apply_expr = {
-        let an_arg = || {
+    // <identifier> ( <expr>, ..., <identifier> = <expr>, ... )
+    // or T.U.<identifier> ( ... ), which is T(U(<identifier>( ... ))); T and U are type or
+    // struct names, telling it apart from the method call x.f( ... )
+    let qualifier = terminated(
+        verify(identifier, |name: &str| {
+            name.starts_with(char::is_uppercase)
+        }),
+        tag("."),
+    );
+    let apply_expr = map(
+        tuple((
+            many0(qualifier),
+            identifier,
+            commentable_spaces,
             alt((
-                map(
-                    tuple((
-                        terminated(identifier, commentable_spaces),
-                        terminated(tag("="), commentable_spaces),
-                        expr,
-                    )),
-                    |(name, _, e)| (Some(name), e),
+                delimited(
+                    terminated(tag("("), commentable_spaces),
+                    arguments,
+                    tag(")"),
                 ),
-                map(expr, |e| (None, e)),
-            ))
-        };
-        let comma = || tuple((tag(","), commentable_spaces));
-        let args = || {
-            terminated(
-                terminated(separated_list0(comma(), an_arg()), opt(comma())),
-                commentable_spaces,
-            )
-        };
+                delimited(
+                    terminated(tag("{"), commentable_spaces),
+                    arguments,
+                    tag("}"),
+                ),
+            )),
+            commentable_spaces,
+        )),
+        |(qualifiers, f, _, (args, kwargs), _)| {
+            let e = Expr::Apply(f, args, kwargs);
+            qualifiers
+                .into_iter()
+                .rev()
+                .fold(e, |e, t| Expr::Apply(t, vec![e], vec![]))
+        },
+    );
 
-        map(
+    // <identifier> { ..<expr>, <identifier> = <expr>, ... }
+    let struct_update_expr = map_opt(
+        tuple((
+            identifier,
+            commentable_spaces,
+            terminated(tag("{"), commentable_spaces),
+            preceded(terminated(tag(".."), commentable_spaces), expr),
+            opt(preceded(tuple((tag(","), commentable_spaces)), arguments)),
+            tag("}"),
+        )),
+        |(name, _, _, base, args, _)| {
+            let (args, kwargs) = args.unwrap_or_default();
+            if !args.is_empty() {
+                return None;
+            }
+            Some(Expr::StructUpdate(name, Box::new(base), kwargs))
+        },
+    );
+
+    // {{ <identifier> = <exp> , ..<expr> , }}
+    let dict_expr = {
+        enum Item {
+            Field(String, Typing, Expr),
+            Spread(Expr),
+        }
+        let field = map(
             tuple((
-                separated_list1(tag("."), identifier),
+                identifier,
                 commentable_spaces,
-                alt((
-                    delimited(terminated(tag("("), commentable_spaces), args(), tag(")")),
-                    delimited(terminated(tag("{"), commentable_spaces), args(), tag("}")),
+                opt(map(
+                    tuple((tag(":"), commentable_spaces, typing, commentable_spaces)),
+                    |(_, _, typ, _)| typ,
                 )),
+                tag("="),
+                commentable_spaces,
+                expr,
                 commentable_spaces,
             )),
-            |(fs, _, mixed_args, _)| {
-                let n = fs.len();
-                assert!(n > 0);
-                let mut args: Vec<Expr> = vec![];
-                let mut kwargs: Vec<(String, Expr)> = vec![];
-                for (name, val) in mixed_args {
-                    match name {
-                        None => {
-                            args.push(val);
-                        }
-                        Some(name) => {
-                            kwargs.push((name, val));
+            |(name, _, typ, _, _, e, _)| Item::Field(name, typ.unwrap_or(Typing::Any), e),
+        );
+        let spread = map(
+            preceded(terminated(tag(".."), commentable_spaces), expr),
+            Item::Spread,
+        );
+        map(
+            tuple((
+                tag("{{"),
+                commentable_spaces,
+                separated_list0(tuple((tag(","), commentable_spaces)), alt((spread, field))),
+                opt(tuple((tag(","), commentable_spaces))),
+                tag("}}"),
+            )),
+            |(_, _, items, _, _)| {
+                // `{{ ..a, x = 1, ..b }}` merges `a`, `{{ x = 1 }}` and `b` in order
+                let mut parts = vec![];
+                let mut fields = vec![];
+                let mut has_spread = false;
+                for item in items {
+                    match item {
+                        Item::Field(name, typ, e) => fields.push((name, typ, e)),
+                        Item::Spread(e) => {
+                            if !fields.is_empty() {
+                                parts.push(Expr::AnonymousStruct(std::mem::take(&mut fields)));
+                            }
+                            parts.push(e);
+                            has_spread = true;
                         }
                     }
                 }
-                let mut e = Expr::Apply(fs[n - 1].to_string(), args, kwargs);
-                for i in (0..n - 1).rev() {
-                    e = Expr::Apply(fs[i].to_string(), vec![e], vec![]);
+                if !has_spread {
+                    return Expr::AnonymousStruct(fields);
+                }
+                if !fields.is_empty() {
+                    parts.push(Expr::AnonymousStruct(fields));
                 }
-                e
+                Expr::Spread(parts)
             },
         )
     };
 
-    // <identifier>.<identifier>
-    let property_expr = map(
-        tuple((
-            identifier,
-            many1(map(
-                tuple((tag("."), commentable_spaces, identifier)),
-                |(_, _, prop)| prop,
-            )),
-        )),
-        |(parent, children)| {
-            let mut e = Expr::Prop(Box::new(Expr::Var(parent)), children[0].to_string());
-            for i in 1..children.len() {
-                e = Expr::Prop(Box::new(e), children[i].to_string());
-            }
-            e
-        },
-    );
-
-    // {{ <identifier> = <exp> , }}
-    let dict_expr = map(
-        tuple((
-            tag("{{"),
-            commentable_spaces,
-            separated_list0(
-                tuple((tag(","), commentable_spaces)),
-                map(
-                    tuple((
-                        identifier,
-                        commentable_spaces,
-                        opt(map(
-                            tuple((tag(":"), commentable_spaces, typing, commentable_spaces)),
-                            |(_, _, typ, _)| typ,
-                        )),
-                        tag("="),
-                        commentable_spaces,
-                        expr,
-                        commentable_spaces,
-                    )),
-                    |(name, _, typ, _, _, e, _)| (name, typ.unwrap_or(Typing::Any), e),
-                ),
-            ),
-            opt(tuple((tag(","), commentable_spaces))),
-            tag("}}"),
-        )),
-        |(_, _, items, _, _)| Expr::AnonymousStruct(items),
-    );
-
-    // { <cumin> }
-    let blocked_expr = map(delimited(tag("{"), cumin, tag("}")), |cumin| {
+    // { <cumin> }, which holds a second factor's nesting, as its statements take about
+    // as much stack again to parse
+    let blocked_expr = nested(map(delimited(tag("{"), cumin, tag("}")), |cumin| {
         Expr::Blocked(Box::new(cumin))
-    });
+    }));
 
     // [ <expr> , ]
     let arrayed_expr = map(
@@ -292,14 +606,19 @@ fn factor(input: &str) -> IResult<&str, Expr> {
         |(_, _, elems, _, _)| Expr::Arrayed(elems),
     );
 
-    // ( <expr> , )
-    let tuple_expr = map(
-        tuple((
-            tag("("),
-            separated_list1(tuple((tag(","), commentable_spaces)), expr),
-            tag(")"),
-        )),
-        |item| Expr::Tuple(item.1),
+    // f" ... { <expr> [: <spec>] } ... "
+    let interpolated_expr = map(
+        delimited(tag("f\""), many0(segment), tag("\"")),
+        |segments| {
+            let mut merged: Vec<Segment> = vec![];
+            for seg in segments {
+                match (merged.last_mut(), seg) {
+                    (Some(Segment::Lit(s)), Segment::Lit(t)) => s.push_str(&t),
+                    (_, seg) => merged.push(seg),
+                }
+            }
+            Expr::Interpolated(merged)
+        },
     );
 
     // <value>
@@ -310,6 +629,7 @@ fn factor(input: &str) -> IResult<&str, Expr> {
 
     terminated(
         alt((
+            interpolated_expr,
             avalue,
             notted,
             minused,
@@ -317,15 +637,75 @@ fn factor(input: &str) -> IResult<&str, Expr> {
             dict_expr,
             blocked_expr,
             arrayed_expr,
+            struct_update_expr,
             apply_expr,
-            tuple_expr,
-            property_expr,
             vvalue,
         )),
         commentable_spaces,
     )(input)
 }
 
+fn segment(input: &str) -> IResult<&str, Segment> {
+    fn lit(s: &str) -> Segment {
+        Segment::Lit(s.to_string())
+    }
+    alt((
+        map(tag("{{"), |_| lit("{")),
+        map(tag("}}"), |_| lit("}")),
+        map(braced_env_value, |v| {
+            Segment::Expr(Expr::Val(v), FormatSpec::default())
+        }),
+        map(tag("$"), lit),
+        placeholder,
+        map(preceded(tag("\\"), escaped_char), |c| {
+            Segment::Lit(c.to_string())
+        }),
+        map(is_not("\"\\{}$"), lit),
+    ))(input)
+}
+
+/// `{ <expr> [: <format_spec>] }`. As an expression `10:04` is a time, so for `{10:04}`
+/// the splits at each `:` the expression took in are tried first, reading it as 10
+/// formatted with `04`.
+fn placeholder(input: &str) -> IResult<&str, Segment> {
+    let (input, _) = terminated(tag("{"), commentable_spaces)(input)?;
+    let (rest, e) = expr(input)?;
+    let taken = &input[..input.len() - rest.len()];
+    for (i, _) in taken.match_indices(':') {
+        if let (Ok((_, e)), Ok((rest, spec))) = (
+            all_consuming(expr)(&input[..i]),
+            terminated(format_spec, tag("}"))(&input[i + 1..]),
+        ) {
+            return Ok((rest, Segment::Expr(e, spec)));
+        }
+    }
+    let (rest, spec) = terminated(opt(preceded(tag(":"), format_spec)), tag("}"))(rest)?;
+    Ok((rest, Segment::Expr(e, spec.unwrap_or_default())))
+}
+
+fn format_spec(input: &str) -> IResult<&str, FormatSpec> {
+    let align = || one_of("<>^");
+    let number = || map_res(digit1, |d: &str| d.parse::<usize>());
+    map(
+        tuple((
+            alt((
+                map(pair(none_of("}"), align()), |(f, a)| (Some(f), Some(a))),
+                map(opt(align()), |a| (None, a)),
+            )),
+            opt(char('0')),
+            opt(number()),
+            opt(preceded(char('.'), number())),
+        )),
+        |((fill, align), zero, width, precision)| FormatSpec {
+            fill,
+            align,
+            zero: zero.is_some(),
+            width,
+            precision,
+        },
+    )(input)
+}
+
 #[cfg(test)]
 mod test_expr {
     use crate::parser::expr::*;
@@ -490,6 +870,48 @@ mod test_expr {
         );
     }
 
+    #[test]
+    fn test_bitwise() {
+        assert_expr!(
+            "0o755 & 0o777",
+            BitAnd(Box::new(Val(Nat(0o755))), Box::new(Val(Nat(0o777))))
+        );
+        assert_expr!(
+            "1 << 2 + 1",
+            Shl(
+                Box::new(Val(Nat(1))),
+                Box::new(Add(Box::new(Val(Nat(2))), Box::new(Val(Nat(1)))))
+            )
+        );
+        assert_expr!(
+            "a | b & c ^ d",
+            BitOr(
+                Box::new(Var("a".to_string())),
+                Box::new(BitXor(
+                    Box::new(BitAnd(
+                        Box::new(Var("b".to_string())),
+                        Box::new(Var("c".to_string()))
+                    )),
+                    Box::new(Var("d".to_string()))
+                ))
+            )
+        );
+        assert_expr!(
+            "x >> 1 < 4",
+            Less(
+                Box::new(Shr(Box::new(Var("x".to_string())), Box::new(Val(Nat(1))))),
+                Box::new(Val(Nat(4)))
+            )
+        );
+        assert_expr!(
+            "1 | 2 == 3",
+            Equal(
+                Box::new(BitOr(Box::new(Val(Nat(1))), Box::new(Val(Nat(2))))),
+                Box::new(Val(Nat(3)))
+            )
+        );
+    }
+
     #[test]
     fn test_bool_expression() {
         assert_expr!("true", Val(Bool(true)));
@@ -554,6 +976,43 @@ mod test_expr {
         );
     }
 
+    #[test]
+    fn test_spread() {
+        let x = || ("x".to_string(), Typing::Any, Val(Nat(1)));
+        assert_expr!(
+            "{{ ..a, ..b, x = 1 }}",
+            Spread(vec![
+                Var("a".to_string()),
+                Var("b".to_string()),
+                AnonymousStruct(vec![x()])
+            ])
+        );
+        assert_expr!(
+            "{{ x = 1, .. a.b, }}",
+            Spread(vec![
+                AnonymousStruct(vec![x()]),
+                Prop(Box::new(Var("a".to_string())), "b".to_string())
+            ])
+        );
+        assert_expr!(
+            "S { ..base, x = 1 }",
+            StructUpdate(
+                "S".to_string(),
+                Box::new(Var("base".to_string())),
+                vec![("x".to_string(), Val(Nat(1)))]
+            )
+        );
+        assert_expr!(
+            "S{..f(1)}",
+            StructUpdate(
+                "S".to_string(),
+                Box::new(Apply("f".to_string(), vec![Val(Nat(1))], vec![])),
+                vec![]
+            )
+        );
+        assert!(expr("S { ..base, 1 }").map_or(true, |(rest, _)| !rest.is_empty()));
+    }
+
     #[test]
     fn test_arrayed() {
         assert_expr!("[]", Arrayed(vec![]));
@@ -703,6 +1162,212 @@ mod test_expr {
         );
     }
 
+    #[test]
+    fn test_index() {
+        assert_expr!(
+            "xs[0]",
+            Index(Box::new(Var("xs".to_string())), Box::new(Val(Nat(0))))
+        );
+        assert_expr!(
+            "xs[-1]",
+            Index(Box::new(Var("xs".to_string())), Box::new(Val(Int(-1))))
+        );
+        assert_expr!(
+            "[1, 2][0]",
+            Index(
+                Box::new(Arrayed(vec![Val(Nat(1)), Val(Nat(2))])),
+                Box::new(Val(Nat(0)))
+            )
+        );
+        assert_expr!(
+            "xs[0][1]",
+            Index(
+                Box::new(Index(
+                    Box::new(Var("xs".to_string())),
+                    Box::new(Val(Nat(0)))
+                )),
+                Box::new(Val(Nat(1)))
+            )
+        );
+        assert_expr!(
+            "xs[0].name",
+            Prop(
+                Box::new(Index(
+                    Box::new(Var("xs".to_string())),
+                    Box::new(Val(Nat(0)))
+                )),
+                "name".to_string()
+            )
+        );
+        assert_expr!(
+            "xs[i + 1] as Int",
+            AsCast(
+                Box::new(Index(
+                    Box::new(Var("xs".to_string())),
+                    Box::new(Add(Box::new(Var("i".to_string())), Box::new(Val(Nat(1)))))
+                )),
+                Typing::Int
+            )
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        assert_expr!(
+            "xs[1..3]",
+            Slice(
+                Box::new(Var("xs".to_string())),
+                Some(Box::new(Val(Nat(1)))),
+                Some(Box::new(Val(Nat(3))))
+            )
+        );
+        assert_expr!(
+            "xs[..-1]",
+            Slice(
+                Box::new(Var("xs".to_string())),
+                None,
+                Some(Box::new(Val(Int(-1))))
+            )
+        );
+        assert_expr!(
+            "xs[ i .. ]",
+            Slice(
+                Box::new(Var("xs".to_string())),
+                Some(Box::new(Var("i".to_string()))),
+                None
+            )
+        );
+        assert_expr!("xs[..]", Slice(Box::new(Var("xs".to_string())), None, None));
+    }
+
+    #[test]
+    fn test_method_call() {
+        assert_expr!(
+            "(1h).as_secs()",
+            MethodCall(
+                Box::new(Val(Duration(3_600_000_000_000))),
+                "as_secs".to_string(),
+                vec![],
+                vec![]
+            )
+        );
+        assert_expr!(
+            "xs[0].pad_left(3, fill = \"0\")",
+            MethodCall(
+                Box::new(Index(
+                    Box::new(Var("xs".to_string())),
+                    Box::new(Val(Nat(0)))
+                )),
+                "pad_left".to_string(),
+                vec![Val(Nat(3))],
+                vec![("fill".to_string(), Val(Str("0".to_string())))]
+            )
+        );
+        assert_expr!(
+            "x.a.len()",
+            MethodCall(
+                Box::new(Prop(Box::new(Var("x".to_string())), "a".to_string())),
+                "len".to_string(),
+                vec![],
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_tuple_field() {
+        assert_expr!(
+            "pair.0",
+            Prop(Box::new(Var("pair".to_string())), "0".to_string())
+        );
+        assert_expr!(
+            "pair.1.0",
+            Prop(
+                Box::new(Prop(Box::new(Var("pair".to_string())), "1".to_string())),
+                "0".to_string()
+            )
+        );
+        assert_expr!(
+            "(1, 2).1",
+            Prop(
+                Box::new(Expr::Tuple(vec![Val(Nat(1)), Val(Nat(2))])),
+                "1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_interpolated() {
+        assert_expr!("f\"\"", Interpolated(vec![]));
+        assert_expr!(
+            "f\"a\\n{{b}}\"",
+            Interpolated(vec![Segment::Lit("a\n{b}".to_string())])
+        );
+        assert_expr!(
+            "f\"https://{host}:{port}/api\"",
+            Interpolated(vec![
+                Segment::Lit("https://".to_string()),
+                Segment::Expr(Var("host".to_string()), FormatSpec::default()),
+                Segment::Lit(":".to_string()),
+                Segment::Expr(Var("port".to_string()), FormatSpec::default()),
+                Segment::Lit("/api".to_string()),
+            ])
+        );
+        assert_expr!(
+            "f\"{ x + 1 }\"",
+            Interpolated(vec![Segment::Expr(
+                Add(Box::new(Var("x".to_string())), Box::new(Val(Nat(1)))),
+                FormatSpec::default()
+            )])
+        );
+        assert_expr!(
+            "f\"{x:*^8.2}\"",
+            Interpolated(vec![Segment::Expr(
+                Var("x".to_string()),
+                FormatSpec {
+                    fill: Some('*'),
+                    align: Some('^'),
+                    zero: false,
+                    width: Some(8),
+                    precision: Some(2),
+                }
+            )])
+        );
+        assert_expr!(
+            "f\"{n:03}\"",
+            Interpolated(vec![Segment::Expr(
+                Var("n".to_string()),
+                FormatSpec {
+                    zero: true,
+                    width: Some(3),
+                    ..FormatSpec::default()
+                }
+            )])
+        );
+        assert_expr!(
+            "f\"$HOME=${HOME:-/root}\"",
+            Interpolated(vec![
+                Segment::Lit("$HOME=".to_string()),
+                Segment::Expr(
+                    Val(Env("HOME".to_string(), Some("/root".to_string()))),
+                    FormatSpec::default()
+                ),
+            ])
+        );
+        assert_expr!(
+            "f\"{10:04}\"",
+            Interpolated(vec![Segment::Expr(
+                Val(Nat(10)),
+                FormatSpec {
+                    zero: true,
+                    width: Some(4),
+                    ..FormatSpec::default()
+                }
+            )])
+        );
+        assert!(!matches!(expr("f\"{x\""), Ok(("", _))));
+    }
+
     #[test]
     fn test_blocked() {
         assert_expr!(
@@ -841,6 +1506,31 @@ mod test_expr {
                 Box::new(Val(Bool(false)))
             )
         );
+        // a chain holds when each adjacent pair does
+        assert_expr!(
+            "0 < x <= 9",
+            And(
+                Box::new(Less(
+                    Box::new(Val(Nat(0))),
+                    Box::new(Expr::Var("x".to_string()))
+                )),
+                Box::new(Not(Box::new(Less(
+                    Box::new(Val(Nat(9))),
+                    Box::new(Expr::Var("x".to_string()))
+                ))))
+            )
+        );
+        // and/or bind looser than comparisons
+        assert_expr!(
+            "x == 1 or y",
+            Or(
+                Box::new(Equal(
+                    Box::new(Expr::Var("x".to_string())),
+                    Box::new(Val(Nat(1)))
+                )),
+                Box::new(Expr::Var("y".to_string()))
+            )
+        );
     }
 
     #[test]
@@ -1,3 +1,4 @@
+use crate::parser::cumin::*;
 use crate::parser::expr::*;
 use crate::parser::typing::*;
 use crate::parser::util::*;
@@ -5,20 +6,225 @@ use nom::combinator;
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
-    combinator::{map, opt},
-    multi::{separated_list0, separated_list1},
-    sequence::{delimited, terminated, tuple},
+    character::complete::satisfy,
+    combinator::{consumed, map, not, opt, peek},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Let(String, Typing, Expr),
-    Struct(String, Vec<(String, Typing, Option<Expr>)>), // StructName, [(name, type, default)]
+    LetPattern(Pattern, Expr),
+    Struct(String, Vec<Field>, Vec<Check>), // StructName, [(name, type, where, default)], [check]
     Enum(String, Vec<String>),
-    Type(String, Vec<Typing>),
+    Type(String, Vec<Typing>, Option<Predicate>),
     Import(String),
     Fun(String, Vec<(String, Typing, Option<Expr>)>, Expr),
+    Assert(Predicate, Option<Operands>, Option<Expr>), // predicate, its comparison, message
+    Test(String, Cumin),
+}
+
+/// How an asserted comparison `a <op> b` holds its operands: `a > b` and `a <= b` become
+/// `b < a` and `not (b < a)`. An assert names the left and right values when it fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    InOrder,
+    Swapped,
+}
+
+/// The left-hand side of a destructuring `let`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `x`
+    Bind(String),
+    /// `_`, matching anything without binding it
+    Ignore,
+    /// `(p, p)`
+    Tuple(Vec<Pattern>),
+    /// `{{ x, y }}`, the keys of a dict
+    Dict(Vec<String>),
+    /// `S { x, y [, ..] }`, all the fields of a struct `S` unless ending with `..`
+    Struct(String, Vec<String>, bool),
+    /// `[p, p [, ..rest]]`
+    Array(Vec<Pattern>, Option<String>),
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn join<T: ToString>(items: &[T]) -> String {
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        match self {
+            Pattern::Bind(name) => write!(f, "{}", name),
+            Pattern::Ignore => write!(f, "_"),
+            Pattern::Tuple(items) => write!(f, "({})", join(items)),
+            Pattern::Dict(keys) => write!(f, "{{{{ {} }}}}", join(keys)),
+            Pattern::Struct(name, fields, rest) => {
+                let mut items = fields.to_vec();
+                if *rest {
+                    items.push("..".to_string());
+                }
+                write!(f, "{} {{ {} }}", name, join(&items))
+            }
+            Pattern::Array(items, rest) => {
+                let mut items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                if let Some(rest) = rest {
+                    items.push(format!("..{}", rest));
+                }
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+/// `x`, `_`, `(p, ..)`, `{{ x, .. }}`, `S { x, .. [, ..] }` or `[p, .. [, ..rest]]`
+pub fn pattern(input: &str) -> IResult<&str, Pattern> {
+    let comma = || tuple((tag(","), commentable_spaces));
+    let names = || {
+        separated_list0(
+            tuple((tag(","), commentable_spaces)),
+            terminated(identifier, commentable_spaces),
+        )
+    };
+    let ignore = combinator::value(
+        Pattern::Ignore,
+        terminated(tag("_"), not(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+    );
+    let tuple_pattern = map(
+        tuple((
+            tag("("),
+            commentable_spaces,
+            separated_list1(comma(), terminated(pattern, commentable_spaces)),
+            opt(comma()),
+            tag(")"),
+        )),
+        |(_, _, items, _, _)| Pattern::Tuple(items),
+    );
+    let dict_pattern = map(
+        tuple((
+            tag("{{"),
+            commentable_spaces,
+            names(),
+            opt(comma()),
+            tag("}}"),
+        )),
+        |(_, _, keys, _, _)| Pattern::Dict(keys),
+    );
+    let struct_pattern = map(
+        tuple((
+            identifier,
+            commentable_spaces,
+            tag("{"),
+            commentable_spaces,
+            names(),
+            opt(comma()),
+            opt(terminated(tag(".."), commentable_spaces)),
+            tag("}"),
+        )),
+        |(name, _, _, _, fields, _, rest, _)| Pattern::Struct(name, fields, rest.is_some()),
+    );
+    let array_pattern = map(
+        tuple((
+            tag("["),
+            commentable_spaces,
+            separated_list0(comma(), terminated(pattern, commentable_spaces)),
+            opt(comma()),
+            opt(map(
+                tuple((tag(".."), identifier, commentable_spaces)),
+                |(_, rest, _)| rest,
+            )),
+            tag("]"),
+        )),
+        |(_, _, items, _, rest, _)| Pattern::Array(items, rest),
+    );
+    nested(alt((
+        ignore,
+        tuple_pattern,
+        dict_pattern,
+        struct_pattern,
+        array_pattern,
+        map(identifier, Pattern::Bind),
+    )))(input)
+}
+
+/// A refinement `where <expr>` and its source, which reads the checked value as `self`.
+pub type Predicate = (Expr, String);
+
+/// A struct field: name, type, refinement and default value.
+pub type Field = (String, Typing, Option<Predicate>, Option<Expr>);
+
+/// An invariant `check <expr>, <message>;` over the fields of a struct.
+pub type Check = (Predicate, Expr);
+
+/// `where <expr>`
+fn predicate(input: &str) -> IResult<&str, Predicate> {
+    map(
+        tuple((tag("where"), commentable_spaces, consumed(expr))),
+        |(_, _, (source, e))| (e, source.trim_end().to_string()),
+    )(input)
+}
+
+/// `check <expr>, <message>;`
+fn check(input: &str) -> IResult<&str, Check> {
+    map(
+        tuple((
+            tag("check"),
+            commentable_spaces,
+            consumed(expr),
+            tag(","),
+            commentable_spaces,
+            expr,
+            tag(";"),
+            commentable_spaces,
+        )),
+        |(_, _, (source, e), _, _, message, _, _)| ((e, source.trim_end().to_string()), message),
+    )(input)
+}
+
+/// `assert <expr> [, <message>];`
+fn assert(input: &str) -> IResult<&str, Statement> {
+    map(
+        tuple((
+            tag("assert"),
+            satisfy(char::is_whitespace),
+            commentable_spaces,
+            peek(opt(consumed(comparison))),
+            consumed(expr),
+            opt(preceded(tuple((tag(","), commentable_spaces)), expr)),
+            tag(";"),
+        )),
+        |(_, _, _, cmp, (source, e), message, _)| {
+            // only when the comparison is the whole predicate
+            let operands = match cmp {
+                Some((cmp_source, (_, op, _))) if cmp_source == source => match op {
+                    ">" | "<=" => Some(Operands::Swapped),
+                    _ => Some(Operands::InOrder),
+                },
+                _ => None,
+            };
+            Statement::Assert((e, source.trim_end().to_string()), operands, message)
+        },
+    )(input)
+}
+
+/// `test "<name>" { <cumin> }`
+fn test(input: &str) -> IResult<&str, Statement> {
+    map(
+        tuple((
+            tag("test"),
+            commentable_spaces,
+            delimited(tag("\""), is_not("\""), tag("\"")),
+            commentable_spaces,
+            delimited(tag("{"), cumin, tag("}")),
+        )),
+        |(_, _, name, _, body)| Statement::Test(name.to_string(), body),
+    )(input)
 }
 
 pub fn stmt(input: &str) -> IResult<&str, Statement> {
@@ -47,24 +253,43 @@ pub fn stmt(input: &str) -> IResult<&str, Statement> {
         )
     };
 
-    // struct id { id: typing [= expr] [,] }
+    // let <pattern> = expr;
+    let let_pattern_stmt = map(
+        tuple((
+            tag("let"),
+            commentable_spaces,
+            pattern,
+            commentable_spaces,
+            tag("="),
+            commentable_spaces,
+            expr,
+            tag(";"),
+        )),
+        |(_, _, pat, _, _, _, e, _)| Statement::LetPattern(pat, e),
+    );
+
+    // struct id { id: typing [where expr] [= expr] [,] [check expr, expr;] }
     let struct_stmt = {
+        let check_keyword = pair(tag("check"), satisfy(char::is_whitespace));
         let inner = separated_list0(
             tuple((tag(","), commentable_spaces)),
             map(
                 tuple((
-                    identifier,
+                    preceded(not(check_keyword), identifier),
                     commentable_spaces,
                     opt(map(
                         tuple((tag(":"), commentable_spaces, typing, commentable_spaces)),
                         |(_, _, typ, _)| typ,
                     )),
+                    opt(predicate),
                     opt(map(
                         tuple((tag("="), commentable_spaces, expr, commentable_spaces)),
                         |(_, _, e, _)| e,
                     )),
                 )),
-                |(name, _, typ, default_value)| (name, typ.unwrap_or(Typing::Any), default_value),
+                |(name, _, typ, pred, default_value)| {
+                    (name, typ.unwrap_or(Typing::Any), pred, default_value)
+                },
             ),
         );
         map(
@@ -77,9 +302,10 @@ pub fn stmt(input: &str) -> IResult<&str, Statement> {
                 commentable_spaces,
                 inner,
                 opt(tuple((tag(","), commentable_spaces))),
+                many0(check),
                 tag("}"),
             )),
-            |(_, _, name, _, _, _, items, _, _)| Statement::Struct(name, items),
+            |(_, _, name, _, _, _, items, _, checks, _)| Statement::Struct(name, items, checks),
         )
     };
 
@@ -105,7 +331,7 @@ pub fn stmt(input: &str) -> IResult<&str, Statement> {
         )
     };
 
-    // type <id> = <Typing> | ... | <Typing> ;
+    // type <id> = <Typing> | ... | <Typing> [where expr] ;
     let type_stmt = {
         let typelist = separated_list1(
             tuple((tag("|"), commentable_spaces)),
@@ -120,9 +346,10 @@ pub fn stmt(input: &str) -> IResult<&str, Statement> {
                 tag("="),
                 commentable_spaces,
                 typelist,
+                opt(predicate),
                 tag(";"),
             )),
-            |(_, _, name, _, _, _, typs, _)| Statement::Type(name, typs),
+            |(_, _, name, _, _, _, typs, pred, _)| Statement::Type(name, typs, pred),
         )
     };
 
@@ -216,10 +443,13 @@ pub fn stmt(input: &str) -> IResult<&str, Statement> {
             fn_stmt,
             let_fn_stmt,
             let_stmt,
+            let_pattern_stmt,
             struct_stmt,
             enum_stmst,
             type_stmt,
             use_stmt,
+            assert,
+            test,
         )),
         commentable_spaces,
     )(input)
@@ -265,16 +495,20 @@ mod test_statement {
 
     #[test]
     fn test_struct() {
-        assert_stmt!("struct X {} ", Struct("X".to_string(), vec![]));
-        assert_stmt!("struct X {} // comment", Struct("X".to_string(), vec![]));
+        assert_stmt!("struct X {} ", Struct("X".to_string(), vec![], vec![]));
+        assert_stmt!(
+            "struct X {} // comment",
+            Struct("X".to_string(), vec![], vec![])
+        );
         assert_stmt!(
             "struct Point { x: Int, y:Int} ",
             Struct(
                 "Point".to_string(),
                 vec![
-                    ("x".to_string(), Typing::Int, None),
-                    ("y".to_string(), Typing::Int, None),
-                ]
+                    ("x".to_string(), Typing::Int, None, None),
+                    ("y".to_string(), Typing::Int, None, None),
+                ],
+                vec![]
             )
         );
         // comma-trailing
@@ -283,9 +517,10 @@ mod test_statement {
             Struct(
                 "Point".to_string(),
                 vec![
-                    ("x".to_string(), Typing::Int, None),
-                    ("y".to_string(), Typing::Int, None),
-                ]
+                    ("x".to_string(), Typing::Int, None, None),
+                    ("y".to_string(), Typing::Int, None, None),
+                ],
+                vec![]
             )
         );
         // with default values
@@ -299,11 +534,13 @@ mod test_statement {
                     (
                         "name".to_string(),
                         Typing::String,
+                        None,
                         Some(Val(Str("hoge".to_string())))
                     ),
-                    ("x".to_string(), Typing::Int, None),
-                    ("y".to_string(), Typing::Int, Some(Val(Nat(0)))),
-                ]
+                    ("x".to_string(), Typing::Int, None, None),
+                    ("y".to_string(), Typing::Int, None, Some(Val(Nat(0)))),
+                ],
+                vec![]
             )
         );
         // without type-annotation & with default values
@@ -312,12 +549,104 @@ mod test_statement {
             Struct(
                 "P".to_string(),
                 vec![
-                    ("x".to_string(), Typing::Int, None),
-                    ("y".to_string(), Typing::Int, Some(Val(Nat(2)))),
-                    ("z".to_string(), Typing::Any, Some(Val(Nat(2)))),
+                    ("x".to_string(), Typing::Int, None, None),
+                    ("y".to_string(), Typing::Int, None, Some(Val(Nat(2)))),
+                    ("z".to_string(), Typing::Any, None, Some(Val(Nat(2)))),
+                ],
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_struct_check() {
+        assert_stmt!(
+            "struct R {
+                min: Nat,
+                max: Nat,
+                check min <= max, \"min must not exceed max\";
+                check max > 0 , f\"max is {max}\" ;
+            }",
+            Struct(
+                "R".to_string(),
+                vec![
+                    ("min".to_string(), Typing::Nat, None, None),
+                    ("max".to_string(), Typing::Nat, None, None),
+                ],
+                vec![
+                    (
+                        (
+                            Not(Box::new(Less(
+                                Box::new(Var("max".to_string())),
+                                Box::new(Var("min".to_string()))
+                            ))),
+                            "min <= max".to_string()
+                        ),
+                        Val(Str("min must not exceed max".to_string()))
+                    ),
+                    (
+                        (
+                            Less(Box::new(Val(Nat(0))), Box::new(Var("max".to_string()))),
+                            "max > 0".to_string()
+                        ),
+                        Interpolated(vec![
+                            Segment::Lit("max is ".to_string()),
+                            Segment::Expr(Var("max".to_string()), FormatSpec::default())
+                        ])
+                    ),
                 ]
             )
         );
+        // a field may still be named check
+        assert_stmt!(
+            "struct C { check: Bool }",
+            Struct(
+                "C".to_string(),
+                vec![("check".to_string(), Typing::Bool, None, None)],
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_struct_where() {
+        assert_stmt!(
+            "struct S { name: String where len(self) > 0, port: Nat where self != 0 = 80 }",
+            Struct(
+                "S".to_string(),
+                vec![
+                    (
+                        "name".to_string(),
+                        Typing::String,
+                        Some((
+                            Less(
+                                Box::new(Val(Nat(0))),
+                                Box::new(Apply(
+                                    "len".to_string(),
+                                    vec![Var("self".to_string())],
+                                    vec![]
+                                ))
+                            ),
+                            "len(self) > 0".to_string()
+                        )),
+                        None
+                    ),
+                    (
+                        "port".to_string(),
+                        Typing::Nat,
+                        Some((
+                            Not(Box::new(Equal(
+                                Box::new(Var("self".to_string())),
+                                Box::new(Val(Nat(0)))
+                            ))),
+                            "self != 0".to_string()
+                        )),
+                        Some(Val(Nat(80)))
+                    ),
+                ],
+                vec![]
+            )
+        );
     }
 
     #[test]
@@ -355,7 +684,11 @@ mod test_statement {
     fn test_type() {
         assert_stmt!(
             "type T = A;",
-            Type("T".to_string(), vec![Typing::UserTyping("A".to_string())])
+            Type(
+                "T".to_string(),
+                vec![Typing::UserTyping("A".to_string())],
+                None
+            )
         );
         assert_stmt!(
             "type T = A | B | Int;",
@@ -365,7 +698,28 @@ mod test_statement {
                     Typing::UserTyping("A".to_string()),
                     Typing::UserTyping("B".to_string()),
                     Typing::Int,
-                ]
+                ],
+                None
+            )
+        );
+        assert_stmt!(
+            "type Percent = Float where 0.0 <= self <= 100.0 ;",
+            Type(
+                "Percent".to_string(),
+                vec![Typing::Float],
+                Some((
+                    And(
+                        Box::new(Not(Box::new(Less(
+                            Box::new(Var("self".to_string())),
+                            Box::new(Val(Float(0.0)))
+                        )))),
+                        Box::new(Not(Box::new(Less(
+                            Box::new(Val(Float(100.0))),
+                            Box::new(Var("self".to_string()))
+                        ))))
+                    ),
+                    "0.0 <= self <= 100.0".to_string()
+                ))
             )
         );
     }
@@ -415,4 +769,126 @@ mod test_statement {
             )
         );
     }
+
+    #[test]
+    fn test_assert() {
+        let len = Apply("len".to_string(), vec![Var("xs".to_string())], vec![]);
+        assert_stmt!(
+            "assert len(xs) == 3, \"three\";",
+            Assert(
+                (
+                    Equal(Box::new(len.clone()), Box::new(Val(Nat(3)))),
+                    "len(xs) == 3".to_string()
+                ),
+                Some(Operands::InOrder),
+                Some(Val(Str("three".to_string())))
+            )
+        );
+        assert_stmt!(
+            "assert len(xs) > 3;",
+            Assert(
+                (
+                    Less(Box::new(Val(Nat(3))), Box::new(len.clone())),
+                    "len(xs) > 3".to_string()
+                ),
+                Some(Operands::Swapped),
+                None
+            )
+        );
+        assert_stmt!(
+            "assert len(xs) ; // no message",
+            Assert((len, "len(xs)".to_string()), None, None)
+        );
+        assert!(stmt("assert_ok;").is_err());
+        assert!(stmt("assert true, \"missing semicolon\"").is_err());
+    }
+
+    #[test]
+    fn test_test() {
+        assert_stmt!(
+            "test \"double\" { double(2) == 4 }",
+            Test(
+                "double".to_string(),
+                Cumin(
+                    vec![],
+                    Equal(
+                        Box::new(Apply("double".to_string(), vec![Val(Nat(2))], vec![])),
+                        Box::new(Val(Nat(4)))
+                    )
+                )
+            )
+        );
+        assert_stmt!(
+            "test \"asserts\" {\n    assert x;\n} // only asserts",
+            Test(
+                "asserts".to_string(),
+                Cumin(
+                    vec![Assert((Var("x".to_string()), "x".to_string()), None, None)],
+                    Val(Null)
+                )
+            )
+        );
+        assert!(stmt("test { true }").is_err());
+    }
+
+    #[test]
+    fn test_let_pattern() {
+        let bind = |name: &str| Pattern::Bind(name.to_string());
+        let var = |name: &str| Var(name.to_string());
+        assert_stmt!(
+            "let (host, port) = split_addr;",
+            LetPattern(
+                Pattern::Tuple(vec![bind("host"), bind("port")]),
+                var("split_addr")
+            )
+        );
+        assert_stmt!(
+            "let {{ user, region, }} = defaults;",
+            LetPattern(
+                Pattern::Dict(vec!["user".to_string(), "region".to_string()]),
+                var("defaults")
+            )
+        );
+        assert_stmt!(
+            "let UserRecord { id, name, .. } = first;",
+            LetPattern(
+                Pattern::Struct(
+                    "UserRecord".to_string(),
+                    vec!["id".to_string(), "name".to_string()],
+                    true
+                ),
+                var("first")
+            )
+        );
+        assert_stmt!(
+            "let [a, b, ..rest] = xs;",
+            LetPattern(
+                Pattern::Array(vec![bind("a"), bind("b")], Some("rest".to_string())),
+                var("xs")
+            )
+        );
+        assert_stmt!(
+            "let ((_, x), [y]) = p;",
+            LetPattern(
+                Pattern::Tuple(vec![
+                    Pattern::Tuple(vec![Pattern::Ignore, bind("x")]),
+                    Pattern::Array(vec![bind("y")], None)
+                ]),
+                var("p")
+            )
+        );
+        // `_x` is a name, not `_`
+        assert_stmt!(
+            "let (_x, _) = p;",
+            LetPattern(Pattern::Tuple(vec![bind("_x"), Pattern::Ignore]), var("p"))
+        );
+        assert!(stmt("let (a, b) = p").is_err());
+        assert_eq!(
+            pattern("[S { x, .. }, {{ y }}, ..zs]")
+                .unwrap()
+                .1
+                .to_string(),
+            "[S { x, .. }, {{ y }}, ..zs]"
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use crate::parser::expr::nested;
 use crate::parser::util::{commentable_spaces, identifier, spaces};
 use nom::combinator;
 use nom::{
@@ -12,22 +13,52 @@ pub enum Typing {
     Nat,
     Int,
     Float,
+    Decimal,
+    Duration,
+    Bytes,
+    Date,
+    Time,
+    DateTime,
+    IpAddr,
+    Ipv4,
+    Ipv6,
+    Cidr,
+    SocketAddr,
+    Url,
+    SemVer,
     Bool,
     String,
     Array(Box<Typing>),
     Tuple(Vec<Typing>),
     Option(Box<Typing>),
     UserTyping(String),
+    /// `Patch<S>`, the fields of struct `S`, each of them optional
+    Patch(String),
 }
 
 pub fn typing(input: &str) -> IResult<&str, Typing> {
-    alt((
+    nested(alt((
         combinator::value(Typing::Null, tag("Null")),
         combinator::value(Typing::Any, tag("Any")),
         combinator::value(Typing::Any, tag("_")),
         combinator::value(Typing::Nat, tag("Nat")),
         combinator::value(Typing::Int, tag("Int")),
         combinator::value(Typing::Float, tag("Float")),
+        combinator::value(Typing::Decimal, tag("Decimal")),
+        alt((
+            combinator::value(Typing::Duration, tag("Duration")),
+            combinator::value(Typing::Bytes, tag("Bytes")),
+            combinator::value(Typing::DateTime, tag("DateTime")),
+            combinator::value(Typing::Date, tag("Date")),
+            combinator::value(Typing::Time, tag("Time")),
+            combinator::value(Typing::IpAddr, tag("IpAddr")),
+            combinator::value(Typing::Ipv4, tag("Ipv4")),
+            combinator::value(Typing::Ipv6, tag("Ipv6")),
+            combinator::value(Typing::Cidr, tag("Cidr")),
+            combinator::value(Typing::SocketAddr, tag("SocketAddr")),
+            combinator::value(Typing::Url, tag("Url")),
+            combinator::value(Typing::SemVer, tag("SemVer")),
+        )),
         combinator::value(Typing::Bool, tag("Bool")),
         combinator::value(Typing::String, tag("String")),
         map(
@@ -64,8 +95,21 @@ pub fn typing(input: &str) -> IResult<&str, Typing> {
             )),
             |item| Typing::Option(Box::new(item.4)),
         ),
-        map(identifier, |s| Typing::UserTyping(s)),
-    ))(input)
+        map(
+            tuple((
+                tag("Patch"),
+                spaces,
+                tag("<"),
+                spaces,
+                identifier,
+                spaces,
+                tag(">"),
+                spaces,
+            )),
+            |item| Typing::Patch(item.4),
+        ),
+        map(identifier, Typing::UserTyping),
+    )))(input)
 }
 
 impl Typing {
@@ -83,6 +127,16 @@ impl Typing {
             (Typing::Int, Typing::Float) => Some(Typing::Float),
             (Typing::Float, Typing::Nat) => Some(Typing::Float),
             (Typing::Float, Typing::Int) => Some(Typing::Float),
+            // Integers are exact in Decimal; Float is not.
+            (Typing::Nat, Typing::Decimal) => Some(Typing::Decimal),
+            (Typing::Int, Typing::Decimal) => Some(Typing::Decimal),
+            (Typing::Decimal, Typing::Nat) => Some(Typing::Decimal),
+            (Typing::Decimal, Typing::Int) => Some(Typing::Decimal),
+            // Either family of addresses is an IpAddr.
+            (
+                Typing::Ipv4 | Typing::Ipv6 | Typing::IpAddr,
+                Typing::Ipv4 | Typing::Ipv6 | Typing::IpAddr,
+            ) => Some(Typing::IpAddr),
             // struct
             (Typing::Array(s), Typing::Array(t)) => {
                 Typing::unify(s, t).map(|typ| Typing::Array(Box::new(typ)))
@@ -123,6 +177,18 @@ mod test_typing {
         assert_typing!("Nat", Typing::Nat);
         assert_typing!("Int", Typing::Int);
         assert_typing!("Float", Typing::Float);
+        assert_typing!("Decimal", Typing::Decimal);
+        assert_typing!("Duration", Typing::Duration);
+        assert_typing!("Date", Typing::Date);
+        assert_typing!("Time", Typing::Time);
+        assert_typing!("DateTime", Typing::DateTime);
+        assert_typing!("IpAddr", Typing::IpAddr);
+        assert_typing!("Ipv6", Typing::Ipv6);
+        assert_typing!("Cidr", Typing::Cidr);
+        assert_typing!("SocketAddr", Typing::SocketAddr);
+        assert_typing!("Url", Typing::Url);
+        assert_typing!("SemVer", Typing::SemVer);
+        assert_typing!("Bytes", Typing::Bytes);
         assert_typing!("Bool", Typing::Bool);
         assert_typing!("String", Typing::String);
         assert_typing!("Array<_>", Typing::Array(Box::new(Typing::Any)));
@@ -154,6 +220,12 @@ mod test_typing {
             ))))))
         );
         assert_typing!("Hoge_type", Typing::UserTyping("Hoge_type".to_string()));
+        assert_typing!("Patch<S>", Typing::Patch("S".to_string()));
+        assert_typing!(
+            "Array<Patch < S >>",
+            Typing::Array(Box::new(Typing::Patch("S".to_string())))
+        );
+        assert_typing!("Patches", Typing::UserTyping("Patches".to_string()));
     }
 
     macro_rules! assert_unify {
@@ -168,6 +240,8 @@ mod test_typing {
         assert_unify!(Typing::Nat, Typing::Any, Some(Typing::Nat));
         assert_unify!(Typing::Nat, Typing::Int, Some(Typing::Int));
         assert_unify!(Typing::Float, Typing::Int, Some(Typing::Float));
+        assert_unify!(Typing::Nat, Typing::Decimal, Some(Typing::Decimal));
+        assert_unify!(Typing::Float, Typing::Decimal, None);
         assert_unify!(
             Typing::Tuple(vec![Typing::Any, Typing::Nat]),
             Typing::Tuple(vec![Typing::Nat, Typing::Int]),
@@ -1,17 +1,30 @@
+use crate::bail_type_error;
+use crate::datetime::*;
+use crate::net::{Cidr, Url};
 use crate::parser::entries::*;
 use crate::parser::typing::*;
 use crate::parser::util::*;
+use crate::semver::SemVer;
+use crate::units::*;
 use anyhow::Result;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
 use nom::combinator;
 use nom::{
     branch::alt,
-    bytes::complete::{escaped_transform, is_not, tag},
-    character::complete::{char, one_of},
-    combinator::{map, opt, recognize},
+    bytes::complete::{escaped_transform, is_not, tag, take_while, take_while_m_n},
+    character::complete::{char, one_of, satisfy},
+    combinator::{map, map_opt, map_res, not, opt, recognize},
+    error::{Error, ErrorKind},
     multi::{many0, many1},
-    sequence::{delimited, pair, terminated, tuple},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -19,6 +32,17 @@ pub enum Value {
     Nat(u128),
     Int(i128),
     Float(f64),
+    Decimal(Decimal),
+    Duration(u128),
+    Bytes(u128),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    DateTime(DateTime<FixedOffset>),
+    Ip(IpAddr),
+    Cidr(Cidr),
+    SocketAddr(SocketAddr),
+    Url(Url),
+    SemVer(SemVer),
     Bool(bool),
     Str(String),
     Env(String, Option<String>),
@@ -30,6 +54,9 @@ pub enum Value {
     Wrapped(Typing, Box<Value>),
 }
 
+/// Variants of each enum in declaration order.
+pub type Enums = HashMap<String, Vec<String>>;
+
 impl Value {
     pub fn type_of(&self) -> Typing {
         match self {
@@ -37,6 +64,18 @@ impl Value {
             Value::Nat(_) => Typing::Nat,
             Value::Int(_) => Typing::Int,
             Value::Float(_) => Typing::Float,
+            Value::Decimal(_) => Typing::Decimal,
+            Value::Duration(_) => Typing::Duration,
+            Value::Bytes(_) => Typing::Bytes,
+            Value::Date(_) => Typing::Date,
+            Value::Time(_) => Typing::Time,
+            Value::DateTime(_) => Typing::DateTime,
+            Value::Ip(IpAddr::V4(_)) => Typing::Ipv4,
+            Value::Ip(IpAddr::V6(_)) => Typing::Ipv6,
+            Value::Cidr(_) => Typing::Cidr,
+            Value::SocketAddr(_) => Typing::SocketAddr,
+            Value::Url(_) => Typing::Url,
+            Value::SemVer(_) => Typing::SemVer,
             Value::Bool(_) => Typing::Bool,
             Value::Str(_) | Value::Env(_, _) => Typing::String,
             Value::Dict(Some(name), _) | Value::EnumVariant(name, _) => {
@@ -55,19 +94,25 @@ impl Value {
         let ret = match (self, typ) {
             (_, Typing::Any) => self.clone(),
             (val, typ) if &val.type_of() == typ => self.clone(),
-            (Nat(x), Typing::Int) => Int((*x) as i128),
+            (Nat(x), Typing::Int) => match i128::try_from(*x) {
+                Ok(x) => Int(x),
+                Err(_) => bail!("ArithmeticError: {} is too large for Int", x),
+            },
             (Nat(x), Typing::Float) => Float((*x) as f64),
             (Int(x), Typing::Float) => Float((*x) as f64),
+            (Nat(x), Typing::Decimal) => match i128::try_from(*x) {
+                Ok(x) => Decimal(decimal_of_int(x)?),
+                Err(_) => bail!("ArithmeticError: {} is too large for Decimal", x),
+            },
+            (Int(x), Typing::Decimal) => Decimal(decimal_of_int(*x)?),
+            (Ip(_), Typing::IpAddr) => self.clone(),
             (Array(s, elems), Typing::Array(t)) => {
                 if let Some(typ) = Typing::unify(s, t) {
                     let elems: Vec<Value> = elems
                         .iter()
                         .map(|val| val.cast(&typ))
                         .collect::<Result<_>>()?;
-                    let elems = elems
-                        .iter()
-                        .map(|val| val.cast(&t))
-                        .collect::<Result<_>>()?;
+                    let elems = elems.iter().map(|val| val.cast(t)).collect::<Result<_>>()?;
                     Array(typ, elems)
                 } else {
                     bail!("Cannot unify Array<{:?}> and Array<{:?}>", &s, &t);
@@ -77,7 +122,7 @@ impl Value {
                 let elems = elems
                     .iter()
                     .zip(types.iter())
-                    .map(|(val, ty)| val.cast(&ty))
+                    .map(|(val, ty)| val.cast(ty))
                     .collect::<Result<Vec<Value>>>()?;
                 Tuple(elems)
             }
@@ -86,7 +131,7 @@ impl Value {
                     match &**val {
                         Some(x) => {
                             let val = x.cast(&typ)?;
-                            let val = val.cast(&t)?;
+                            let val = val.cast(t)?;
                             Optional(typ, Box::new(Some(val)))
                         }
                         None => Optional(typ, Box::new(None)),
@@ -113,20 +158,402 @@ impl Value {
         use Value::*;
         let ret = match (self, typ) {
             (Nat(x), Typing::String) => Str(format!("{}", x)),
-            (Int(x), Typing::Nat) => Nat((*x) as u128),
+            (Int(x), Typing::Nat) => match u128::try_from(*x) {
+                Ok(x) => Nat(x),
+                Err(_) => bail!("ArithmeticError: {} is negative, cannot be Nat", x),
+            },
             (Int(x), Typing::String) => Str(format!("{}", x)),
-            (Float(x), Typing::Nat) => Nat((*x) as u128),
-            (Float(x), Typing::Int) => Int((*x) as i128),
+            (Float(x), Typing::Nat) => {
+                let y = x.trunc();
+                if !(0.0..u128::MAX as f64).contains(&y) {
+                    bail!("ArithmeticError: {} is out of range for Nat", x);
+                }
+                Nat(y as u128)
+            }
+            (Float(x), Typing::Int) => {
+                let y = x.trunc();
+                if !(i128::MIN as f64..i128::MAX as f64).contains(&y) {
+                    bail!("ArithmeticError: {} is out of range for Int", x);
+                }
+                Int(y as i128)
+            }
             (Float(x), Typing::String) => Str(format!("{}", x)),
-            (Str(x), Typing::Nat) => Nat(x.parse::<u128>().unwrap()),
-            (Str(x), Typing::Int) => Int(x.parse::<i128>().unwrap()),
-            (Str(x), Typing::Float) => Float(x.parse::<f64>().unwrap()),
+            (Float(x), Typing::Decimal) => match format!("{}", x).parse() {
+                Ok(d) => Decimal(d),
+                Err(_) => bail!("ArithmeticError: {} is out of range for Decimal", x),
+            },
+            (Decimal(x), Typing::Nat) => match x.trunc().to_u128() {
+                Some(x) => Nat(x),
+                None => bail!("ArithmeticError: {} is negative, cannot be Nat", x),
+            },
+            (Decimal(x), Typing::Int) => match x.trunc().to_i128() {
+                Some(x) => Int(x),
+                None => bail!("ArithmeticError: {} is out of range for Int", x),
+            },
+            (Decimal(x), Typing::Float) => match x.to_f64() {
+                Some(x) => Float(x),
+                None => bail!("ArithmeticError: {} is out of range for Float", x),
+            },
+            (Decimal(x), Typing::String) => Str(x.to_string()),
+            (Duration(x), Typing::String) => Str(humanize_duration(*x)),
+            (Bytes(x), Typing::String) => Str(humanize_bytes(*x)),
+            (Bytes(x), Typing::Nat) => Nat(*x),
+            (Duration(_), Typing::Nat) => bail!(
+                "TypeError: Duration has no unit to cast to Nat; use as_secs(), as_millis() or another accessor"
+            ),
+            (Nat(x), Typing::Bytes) => Bytes(*x),
+            (Str(x), Typing::Duration) => match duration_literal(x) {
+                Ok(("", d)) => d,
+                _ => bail!("Cannot coerce {:?} to Duration", x),
+            },
+            (Str(x), Typing::Bytes) => match bytes_literal(x) {
+                Ok(("", b)) => b,
+                _ => bail!("Cannot coerce {:?} to Bytes", x),
+            },
+            (Date(x), Typing::String) => Str(format_date(x)),
+            (Time(x), Typing::String) => Str(format_time(x)),
+            (DateTime(x), Typing::String) => Str(format_datetime(x)),
+            (DateTime(x), Typing::Date) => Date(x.date_naive()),
+            (DateTime(x), Typing::Time) => Time(x.time()),
+            (Str(x), Typing::Date) => match parse_date(x) {
+                Some(d) => Date(d),
+                None => bail!("Cannot coerce {:?} to Date (expected YYYY-MM-DD)", x),
+            },
+            (Str(x), Typing::Time) => match parse_time(x) {
+                Some(t) => Time(t),
+                None => bail!("Cannot coerce {:?} to Time (expected HH:MM[:SS[.frac]])", x),
+            },
+            (Str(x), Typing::DateTime) => match parse_datetime(x) {
+                Some(dt) => DateTime(dt),
+                None => bail!("Cannot coerce {:?} to DateTime (expected RFC 3339)", x),
+            },
+            (Ip(x), Typing::String) => Str(x.to_string()),
+            (Cidr(x), Typing::String) => Str(x.to_string()),
+            (SocketAddr(x), Typing::String) => Str(x.to_string()),
+            (Url(x), Typing::String) => Str(x.to_string()),
+            (SemVer(x), Typing::String) => Str(x.to_string()),
+            (Str(x), Typing::SemVer) => match crate::semver::SemVer::parse(x) {
+                Ok(v) => SemVer(v),
+                Err(err) => bail!("Cannot coerce {:?} to SemVer ({})", x, err),
+            },
+            (Str(x), Typing::IpAddr | Typing::Ipv4 | Typing::Ipv6) => match x.parse() {
+                Ok(ip) => Ip(ip).cast(typ).map_err(|_| {
+                    anyhow!("Cannot coerce {:?} to {:?} (wrong address family)", x, typ)
+                })?,
+                Err(err) => bail!("Cannot coerce {:?} to {:?} ({})", x, typ, err),
+            },
+            (Str(x), Typing::Cidr) => match crate::net::Cidr::parse(x) {
+                Ok(cidr) => Cidr(cidr),
+                Err(err) => bail!("Cannot coerce {:?} to Cidr ({})", x, err),
+            },
+            (Str(x), Typing::SocketAddr) => match x.parse() {
+                Ok(addr) => SocketAddr(addr),
+                Err(err) => bail!("Cannot coerce {:?} to SocketAddr ({})", x, err),
+            },
+            (Str(x), Typing::Url) => match crate::net::Url::parse(x) {
+                Ok(url) => Url(url),
+                Err(err) => bail!("Cannot coerce {:?} to Url ({})", x, err),
+            },
+            (Str(x), Typing::Nat) => match x.parse::<u128>() {
+                Ok(x) => Nat(x),
+                Err(err) => bail!("Cannot coerce {:?} to Nat ({})", x, err),
+            },
+            (Str(x), Typing::Int) => match x.parse::<i128>() {
+                Ok(x) => Int(x),
+                Err(err) => bail!("Cannot coerce {:?} to Int ({})", x, err),
+            },
+            (Str(x), Typing::Float) => match x.parse::<f64>() {
+                Ok(x) => Float(x),
+                Err(err) => bail!("Cannot coerce {:?} to Float ({})", x, err),
+            },
+            (Str(x), Typing::Decimal) => match x.parse() {
+                Ok(d) => Decimal(d),
+                Err(err) => bail!("Cannot coerce {:?} to Decimal ({})", x, err),
+            },
             (Str(x), Typing::Bool) if x.as_str() == "true" => Bool(true),
             (Str(x), Typing::Bool) if x.as_str() == "false" => Bool(false),
+            (Bool(x), Typing::String) => Str(format!("{}", x)),
+            (EnumVariant(_, x), Typing::String) => Str(x.to_string()),
             _ => self.cast(typ)?,
         };
         Ok(ret)
     }
+
+    /// Total ordering used by `<`, `==` on numbers, `sort`, `min` and `max`.
+    ///
+    /// Numbers compare by exact value across Nat, Int, Float and Decimal (a Decimal
+    /// meets a Float as Float); durations, sizes, dates and times compare with their own kind
+    /// (datetimes as instants, whatever their offsets), and so do network addresses (IPv4
+    /// before IPv6) and URLs (as strings); versions compare by semver precedence, also
+    /// against a string that parses as one; strings compare
+    /// lexicographically; tuples and arrays compare element-wise, a prefix first;
+    /// enum variants compare in declaration order, looked up in `enums`.
+    /// NaN has no place in the order, so comparing it is an error.
+    pub fn compare(&self, other: &Value, enums: &Enums) -> Result<Ordering> {
+        use Value::*;
+        let nan = || {
+            anyhow!(
+                "Cannot compare {:?} and {:?}, because NaN is unordered",
+                self,
+                other
+            )
+        };
+        let ord = match (self, other) {
+            (Nat(x), Nat(y)) => x.cmp(y),
+            (Int(x), Int(y)) => x.cmp(y),
+            (Nat(_), Int(y)) if *y < 0 => Ordering::Greater,
+            (Nat(x), Int(y)) => x.cmp(&(*y as u128)),
+            (Nat(x), Float(y)) => cmp_nat_float(*x, *y).ok_or_else(nan)?,
+            (Int(x), Float(y)) => cmp_int_float(*x, *y).ok_or_else(nan)?,
+            (Float(x), Float(y)) => x.partial_cmp(y).ok_or_else(nan)?,
+            (Decimal(x), Decimal(y)) => x.cmp(y),
+            (Duration(x), Duration(y)) | (Bytes(x), Bytes(y)) => x.cmp(y),
+            (Date(x), Date(y)) => x.cmp(y),
+            (Time(x), Time(y)) => x.cmp(y),
+            (DateTime(x), DateTime(y)) => x.cmp(y),
+            (Ip(x), Ip(y)) => x.cmp(y),
+            (Cidr(x), Cidr(y)) => x.cmp(y),
+            (SocketAddr(x), SocketAddr(y)) => x.cmp(y),
+            (Url(x), Url(y)) => x.to_string().cmp(&y.to_string()),
+            (SemVer(x), SemVer(y)) => x.precedence(y),
+            (SemVer(_), Str(_)) => self.compare(&other.coerce(&Typing::SemVer)?, enums)?,
+            (Str(_), SemVer(_)) => other.compare(self, enums)?.reverse(),
+            (Nat(_), Decimal(y)) => match self.cast(&Typing::Decimal) {
+                Ok(Decimal(x)) => x.cmp(y),
+                _ => Ordering::Greater,
+            },
+            (Int(x), Decimal(y)) => match self.cast(&Typing::Decimal) {
+                Ok(Decimal(x)) => x.cmp(y),
+                _ if *x < 0 => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            (Float(x), Decimal(y)) => match y.to_f64() {
+                Some(y) => x.partial_cmp(&y).ok_or_else(nan)?,
+                None => bail!("Cannot compare {:?} and {:?}", self, other),
+            },
+            (Int(_), Nat(_))
+            | (Float(_), Nat(_))
+            | (Float(_), Int(_))
+            | (Decimal(_), Nat(_))
+            | (Decimal(_), Int(_))
+            | (Decimal(_), Float(_)) => other.compare(self, enums)?.reverse(),
+            (Bool(x), Bool(y)) => x.cmp(y),
+            (Str(x), Str(y)) => x.cmp(y),
+            (Tuple(xs), Tuple(ys)) | (Array(_, xs), Array(_, ys)) => {
+                for (x, y) in xs.iter().zip(ys.iter()) {
+                    match x.compare(y, enums)? {
+                        Ordering::Equal => continue,
+                        ord => return Ok(ord),
+                    }
+                }
+                xs.len().cmp(&ys.len())
+            }
+            (EnumVariant(s, x), EnumVariant(t, y)) if s == t => {
+                let variants = match enums.get(s) {
+                    Some(variants) => variants,
+                    None => bail!(
+                        "Cannot compare {:?} and {:?}, because enum {} is undefined",
+                        self,
+                        other,
+                        s
+                    ),
+                };
+                let rank = |v: &String| variants.iter().position(|w| w == v);
+                match (rank(x), rank(y)) {
+                    (Some(i), Some(j)) => i.cmp(&j),
+                    _ => bail!(
+                        "Cannot compare {:?} and {:?}, because of unknown variants",
+                        self,
+                        other
+                    ),
+                }
+            }
+            (x, y) => bail_type_error!(compute x "<=>" y),
+        };
+        Ok(ord)
+    }
+
+    /// Whether two numbers are the same value; NaN equals nothing, not even itself.
+    pub fn numeric_eq(&self, other: &Value) -> Option<bool> {
+        use Value::*;
+        match (self, other) {
+            (Nat(_) | Int(_) | Float(_) | Decimal(_), Nat(_) | Int(_) | Float(_) | Decimal(_)) => {
+                Some(matches!(
+                    self.compare(other, &Enums::new()),
+                    Ok(Ordering::Equal)
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn decimal(input: &str) -> IResult<&str, &str> {
+    recognize(many1(terminated(one_of("0123456789"), many0(char('_')))))(input)
+}
+
+/// `<number><unit>` counted in the smallest unit of `units`, which must come out whole.
+fn quantity<'a>(input: &'a str, units: &[(&str, u128)]) -> IResult<&'a str, u128> {
+    let (rest, num) = recognize(pair(decimal, opt(pair(char('.'), decimal))))(input)?;
+    let mut by_length: Vec<&(&str, u128)> = units.iter().collect();
+    by_length.sort_by_key(|(unit, _)| std::cmp::Reverse(unit.len()));
+    let (rest, factor) = match by_length.iter().find(|(unit, _)| rest.starts_with(unit)) {
+        Some((unit, factor)) => (&rest[unit.len()..], *factor),
+        None => return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+    };
+    let num: String = num.chars().filter(|&c| c != '_').collect();
+    let amount = num
+        .parse::<Decimal>()
+        .ok()
+        .and_then(|x| x.checked_mul(Decimal::from(factor)))
+        .filter(|x| x.fract().is_zero())
+        .and_then(|x| x.to_u128());
+    match amount {
+        Some(amount) => Ok((rest, amount)),
+        None => Err(nom::Err::Error(Error::new(input, ErrorKind::MapRes))),
+    }
+}
+
+fn end_of_literal(input: &str) -> IResult<&str, ()> {
+    not(satisfy(|c: char| c.is_alphanumeric() || c == '_'))(input)
+}
+
+/// `500ms`, `30s`, `1h30m`
+pub fn duration_literal(input: &str) -> IResult<&str, Value> {
+    let (rest, parts) = many1(|i| quantity(i, DURATION_UNITS))(input)?;
+    let (rest, _) = end_of_literal(rest)?;
+    match parts
+        .into_iter()
+        .try_fold(0u128, |acc, x| acc.checked_add(x))
+    {
+        Some(nanos) => Ok((rest, Value::Duration(nanos))),
+        None => Err(nom::Err::Error(Error::new(input, ErrorKind::MapRes))),
+    }
+}
+
+/// `512KiB`, `2GB`
+pub fn bytes_literal(input: &str) -> IResult<&str, Value> {
+    let (rest, bytes) = quantity(input, BYTES_UNITS)?;
+    let (rest, _) = end_of_literal(rest)?;
+    Ok((rest, Value::Bytes(bytes)))
+}
+
+fn digits(n: usize) -> impl Fn(&str) -> IResult<&str, &str> {
+    move |input| take_while_m_n(n, n, |c: char| c.is_ascii_digit())(input)
+}
+
+fn date_part(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        digits(4),
+        char('-'),
+        digits(2),
+        char('-'),
+        digits(2),
+    )))(input)
+}
+
+fn time_part(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        digits(2),
+        char(':'),
+        digits(2),
+        opt(tuple((
+            char(':'),
+            digits(2),
+            opt(pair(
+                char('.'),
+                take_while_m_n(1, 9, |c: char| c.is_ascii_digit()),
+            )),
+        ))),
+    )))(input)
+}
+
+fn datetime_part(input: &str) -> IResult<&str, &str> {
+    let offset = alt((
+        tag("Z"),
+        recognize(tuple((one_of("+-"), digits(2), char(':'), digits(2)))),
+    ));
+    recognize(tuple((date_part, char('T'), time_part, offset)))(input)
+}
+
+/// Once input has the shape of a date, it must be one: `2026-02-30` is an error rather
+/// than the subtraction `2026 - 02 - 30`.
+fn committed<'a, T>(
+    input: &'a str,
+    shape: impl Fn(&'a str) -> IResult<&'a str, &'a str>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> IResult<&'a str, T> {
+    let (rest, s) = terminated(shape, end_of_literal)(input)?;
+    match parse(s) {
+        Some(val) => Ok((rest, val)),
+        None => Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify))),
+    }
+}
+
+/// `2024-03-01`
+pub fn date_literal(input: &str) -> IResult<&str, Value> {
+    committed(input, date_part, |s| parse_date(s).map(Value::Date))
+}
+
+/// `09:30`, `09:30:15.250`
+pub fn time_literal(input: &str) -> IResult<&str, Value> {
+    terminated(
+        map_opt(time_part, |s| parse_time(s).map(Value::Time)),
+        end_of_literal,
+    )(input)
+}
+
+/// `2024-03-01T09:30:00Z`, `2024-03-01T09:30:00+09:00`; the offset is required.
+pub fn datetime_literal(input: &str) -> IResult<&str, Value> {
+    committed(input, datetime_part, |s| {
+        parse_datetime(s).map(Value::DateTime)
+    })
+}
+
+/// The message for a parse `Failure` at `input`, when it stopped at a date or datetime
+/// literal that is not a real one.
+pub fn invalid_literal(input: &str) -> Option<String> {
+    if let Ok((_, s)) = datetime_part(input) {
+        Some(format!("invalid datetime {}", s))
+    } else if let Ok((_, s)) = date_part(input) {
+        Some(format!("invalid date {}", s))
+    } else {
+        None
+    }
+}
+
+/// Integers within 96 bits are exact in Decimal.
+pub fn decimal_of_int(x: i128) -> Result<Decimal> {
+    match Decimal::try_from_i128_with_scale(x, 0) {
+        Ok(d) => Ok(d),
+        Err(_) => bail!("ArithmeticError: {} is too large for Decimal", x),
+    }
+}
+
+/// Exact comparison of a Nat with a Float; `None` for NaN.
+fn cmp_nat_float(x: u128, y: f64) -> Option<Ordering> {
+    if y.is_nan() {
+        None
+    } else if y < 0.0 {
+        Some(Ordering::Greater)
+    } else if y >= u128::MAX as f64 {
+        Some(Ordering::Less)
+    } else {
+        Some(x.cmp(&(y as u128)).then(0.0.partial_cmp(&y.fract())?))
+    }
+}
+
+/// Exact comparison of an Int with a Float; `None` for NaN.
+fn cmp_int_float(x: i128, y: f64) -> Option<Ordering> {
+    if y.is_nan() {
+        None
+    } else if y >= i128::MAX as f64 {
+        Some(Ordering::Less)
+    } else if y < i128::MIN as f64 {
+        Some(Ordering::Greater)
+    } else {
+        Some(x.cmp(&(y as i128)).then(0.0.partial_cmp(&y.fract())?))
+    }
 }
 
 pub fn value(input: &str) -> IResult<&str, Value> {
@@ -136,14 +563,33 @@ pub fn value(input: &str) -> IResult<&str, Value> {
         combinator::value(Value::Bool(false), tag("false")),
     ));
 
-    fn decimal(input: &str) -> IResult<&str, &str> {
-        recognize(many1(terminated(one_of("0123456789"), many0(char('_')))))(input)
+    fn exponent(input: &str) -> IResult<&str, &str> {
+        recognize(tuple((one_of("eE"), opt(one_of("+-")), decimal)))(input)
     }
 
+    // 12.50d
+    let decimal_value = map_res(
+        terminated(
+            recognize(tuple((opt(char('-')), decimal, char('.'), decimal))),
+            pair(char('d'), end_of_literal),
+        ),
+        |num_str: &str| {
+            let num: String = num_str.chars().filter(|&c| c != '_').collect();
+            num.parse::<Decimal>().map(Value::Decimal)
+        },
+    );
+
     let float_value = map(
         alt((
-            recognize(tuple((opt(char('-')), char('.'), decimal))),
-            recognize(tuple((opt(char('-')), decimal, char('.'), decimal))),
+            recognize(tuple((opt(char('-')), char('.'), decimal, opt(exponent)))),
+            recognize(tuple((
+                opt(char('-')),
+                decimal,
+                char('.'),
+                decimal,
+                opt(exponent),
+            ))),
+            recognize(tuple((opt(char('-')), decimal, exponent))),
         )),
         |num_str: &str| {
             let num: String = num_str.chars().filter(|&c| c != '_').collect();
@@ -152,31 +598,43 @@ pub fn value(input: &str) -> IResult<&str, Value> {
         },
     );
 
-    let num_value = map(pair(opt(tag("-")), decimal), |(sign, num): (_, &str)| {
+    // 0x, 0o, 0b prefixed integers
+    let radix_value = {
+        let digits = |cs: &'static str| recognize(many1(terminated(one_of(cs), many0(char('_')))));
+        map_res(
+            pair(
+                opt(char('-')),
+                alt((
+                    preceded(
+                        tag("0x"),
+                        map(digits("0123456789abcdefABCDEF"), |d| (d, 16)),
+                    ),
+                    preceded(tag("0o"), map(digits("01234567"), |d| (d, 8))),
+                    preceded(tag("0b"), map(digits("01"), |d| (d, 2))),
+                )),
+            ),
+            |(sign, (num, radix)): (_, (&str, u32))| {
+                let num: String = num.chars().filter(|&c| c != '_').collect();
+                let n = u128::from_str_radix(&num, radix).map_err(|e| e.to_string())?;
+                signed(sign.is_some(), n)
+            },
+        )
+    };
+
+    let num_value = map_res(pair(opt(tag("-")), decimal), |(sign, num): (_, &str)| {
         let num: String = num.chars().filter(|&c| c != '_').collect();
-        match sign {
-            None => Value::Nat(num.parse::<u128>().unwrap()),
-            _ => Value::Int(-num.parse::<i128>().unwrap()),
-        }
+        let n = num.parse::<u128>().map_err(|e| e.to_string())?;
+        signed(sign.is_some(), n)
     });
 
     let str_value = alt((
+        map(multiline_str, Value::Str),
+        map(raw_str, Value::Str),
         combinator::value(Value::Str(String::new()), tag("\"\"")),
         map(
             delimited(
                 tag("\""),
-                escaped_transform(
-                    is_not("\"\\"),
-                    '\\',
-                    alt((
-                        combinator::value("\\", tag("\\")),
-                        combinator::value("\"", tag("\"")),
-                        combinator::value("\'", tag("\'")),
-                        combinator::value("\n", tag("n")),
-                        combinator::value("\r", tag("r")),
-                        combinator::value("\t", tag("t")),
-                    )),
-                ),
+                escaped_transform(is_not("\"\\"), '\\', escaped_char),
                 tag("\""),
             ),
             Value::Str,
@@ -187,26 +645,25 @@ pub fn value(input: &str) -> IResult<&str, Value> {
         Value::EnumVariant(x, y)
     });
 
-    let env_value = {
-        let default_value = map(tuple((tag(":-"), is_not("}"))), |(_, val): (_, &str)| {
-            val.to_string()
-        });
-        alt((
-            map(
-                tuple((tag("${"), identifier, opt(default_value), tag("}"))),
-                |(_, name, default, _)| Value::Env(name, default),
-            ),
-            map(tuple((tag("$"), identifier)), |(_, name)| {
-                Value::Env(name, None)
-            }),
-        ))
-    };
+    let env_value = alt((
+        braced_env_value,
+        map(tuple((tag("$"), identifier)), |(_, name)| {
+            Value::Env(name, None)
+        }),
+    ));
 
     let null_value = map(tag("Null"), |_| Value::Null);
 
     alt((
         const_values,
+        datetime_literal,
+        date_literal,
+        time_literal,
+        duration_literal,
+        bytes_literal,
+        decimal_value,
         float_value,
+        radix_value,
         num_value,
         str_value,
         variant_value,
@@ -215,6 +672,123 @@ pub fn value(input: &str) -> IResult<&str, Value> {
     ))(input)
 }
 
+/// The character after `\\` in string literals.
+pub fn escaped_char(input: &str) -> IResult<&str, char> {
+    let unicode = map_opt(
+        delimited(
+            tag("u{"),
+            take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+            tag("}"),
+        ),
+        |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+    );
+    let ascii = map_opt(
+        preceded(
+            char('x'),
+            take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
+        ),
+        |hex: &str| {
+            u8::from_str_radix(hex, 16)
+                .ok()
+                .filter(|b| b.is_ascii())
+                .map(char::from)
+        },
+    );
+    alt((
+        combinator::value('\\', tag("\\")),
+        combinator::value('"', tag("\"")),
+        combinator::value('\'', tag("\'")),
+        combinator::value('\n', tag("n")),
+        combinator::value('\r', tag("r")),
+        combinator::value('\t', tag("t")),
+        unicode,
+        ascii,
+    ))(input)
+}
+
+/// `r"..."` or `r#"..."#`, with no escapes.
+fn raw_str(input: &str) -> IResult<&str, String> {
+    let (input, _) = char('r')(input)?;
+    let (input, hashes) = take_while(|c| c == '#')(input)?;
+    let (input, _) = char('"')(input)?;
+    let closing = format!("\"{}", hashes);
+    match input.find(&closing) {
+        Some(i) => Ok((&input[i + closing.len()..], input[..i].to_string())),
+        None => Err(nom::Err::Error(Error::new(input, ErrorKind::TakeUntil))),
+    }
+}
+
+/// `"""..."""`, with the common indentation stripped.
+fn multiline_str(input: &str) -> IResult<&str, String> {
+    let (input, _) = tag("\"\"\"")(input)?;
+    let mut chars = input.char_indices();
+    let mut end = None;
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if input[i..].starts_with("\"\"\"") {
+            end = Some(i);
+            break;
+        }
+    }
+    let end = end.ok_or_else(|| nom::Err::Error(Error::new(input, ErrorKind::TakeUntil)))?;
+    let body = dedent(&input[..end]);
+    let unescaped = match escaped_transform(is_not("\\"), '\\', escaped_char)(body.as_str()) {
+        Ok(("", s)) => Some(s),
+        _ => None,
+    };
+    match unescaped {
+        Some(s) => Ok((&input[end + 3..], s)),
+        None => Err(nom::Err::Error(Error::new(input, ErrorKind::Escaped))),
+    }
+}
+
+fn dedent(body: &str) -> String {
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))
+        .unwrap_or(body);
+    let blank = |line: &str| line.chars().all(char::is_whitespace);
+    let indent = body
+        .split('\n')
+        .filter(|line| !blank(line))
+        .map(|line| line.chars().take_while(|c| *c == ' ' || *c == '\t').count())
+        .min()
+        .unwrap_or(0);
+    body.split('\n')
+        .map(|line| {
+            if blank(line) {
+                String::new()
+            } else {
+                line.chars().skip(indent).collect()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Integer literal from its sign and magnitude; out of range is an error, not a wraparound.
+fn signed(negative: bool, n: u128) -> Result<Value, String> {
+    if !negative {
+        return Ok(Value::Nat(n));
+    }
+    0i128
+        .checked_sub_unsigned(n)
+        .map(Value::Int)
+        .ok_or_else(|| format!("-{} is too small for Int", n))
+}
+
+/// `${NAME}` or `${NAME:-DEFAULT}`
+pub fn braced_env_value(input: &str) -> IResult<&str, Value> {
+    let default_value = map(tuple((tag(":-"), is_not("}"))), |(_, val): (_, &str)| {
+        val.to_string()
+    });
+    map(
+        tuple((tag("${"), identifier, opt(default_value), tag("}"))),
+        |(_, name, default, _)| Value::Env(name, default),
+    )(input)
+}
+
 #[cfg(test)]
 mod test_value {
     use crate::parser::value::*;
@@ -238,6 +812,148 @@ mod test_value {
         assert_value!("100_000.0", Value::Float(100000.0));
         assert_value!("0.000_000_001", Value::Float(0.000000001));
         assert_value!("123_456.000_000_001", Value::Float(123456.000000001));
+        assert_value!("1e-6", Value::Float(1e-6));
+        assert_value!("-2.5E3", Value::Float(-2500.0));
+        assert_value!("1_000e+2", Value::Float(100000.0));
+        assert_value!(".5e1", Value::Float(5.0));
+        assert_value!(
+            "-170141183460469231731687303715884105728",
+            Value::Int(i128::MIN)
+        );
+        assert!(value("99999999999999999999999999999999999999999999999999").is_err());
+        assert!(value("-170141183460469231731687303715884105729").is_err());
+    }
+    #[test]
+    fn test_compare() {
+        let enums = Enums::new();
+        assert_eq!(Nat(1).compare(&Float(1.5), &enums).unwrap(), Ordering::Less);
+        assert_eq!(
+            Int(-1).compare(&Float(-1.5), &enums).unwrap(),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Float(2.0).compare(&Int(2), &enums).unwrap(),
+            Ordering::Equal
+        );
+        assert_eq!(
+            Nat(u128::MAX)
+                .compare(&Float(f64::INFINITY), &enums)
+                .unwrap(),
+            Ordering::Less
+        );
+        assert!(Float(f64::NAN).compare(&Float(1.0), &enums).is_err());
+        assert!(Nat(1).compare(&Float(f64::NAN), &enums).is_err());
+        assert_eq!(Float(f64::NAN).numeric_eq(&Float(f64::NAN)), Some(false));
+        assert_eq!(Nat(3).numeric_eq(&Float(3.0)), Some(true));
+        assert_eq!(Nat(3).numeric_eq(&Str("3".to_string())), None);
+    }
+    #[test]
+    fn test_coerce_error() {
+        assert!(Value::Str("abc".to_string()).coerce(&Typing::Nat).is_err());
+        assert!(Value::Str("1.5".to_string()).coerce(&Typing::Int).is_err());
+        assert!(Value::Str("x".to_string()).coerce(&Typing::Float).is_err());
+    }
+    #[test]
+    fn test_coerce_network() {
+        let coerce = |s: &str, typ: Typing| {
+            Value::Str(s.to_string())
+                .coerce(&typ)
+                .and_then(|val| val.coerce(&Typing::String))
+        };
+        let canonical = |s: &str| Some(Value::Str(s.to_string()));
+        assert_eq!(coerce("10.0.0.1", Typing::Ipv4).ok(), canonical("10.0.0.1"));
+        assert_eq!(
+            coerce("2001:DB8:0:0::1", Typing::IpAddr).ok(),
+            canonical("2001:db8::1")
+        );
+        assert_eq!(coerce("fd00::/8", Typing::Cidr).ok(), canonical("fd00::/8"));
+        assert_eq!(
+            coerce("[::1]:8080", Typing::SocketAddr).ok(),
+            canonical("[::1]:8080")
+        );
+        assert_eq!(
+            coerce("HTTP://Example.com:80", Typing::Url).ok(),
+            canonical("http://example.com/")
+        );
+        assert!(coerce("::1", Typing::Ipv4).is_err());
+        assert!(coerce("10.0.0.1", Typing::Ipv6).is_err());
+        assert!(coerce("10.0.0.256", Typing::IpAddr).is_err());
+        assert!(coerce("10.0.0.1/24", Typing::Cidr).is_err());
+        assert!(coerce("10.0.0.1", Typing::SocketAddr).is_err());
+        assert!(coerce("example.com/path", Typing::Url).is_err());
+    }
+    #[test]
+    fn test_decimal() {
+        let dec = |s: &str| Value::Decimal(s.parse().unwrap());
+        assert_value!("12.50d", dec("12.50"));
+        assert_value!("-0.1d", dec("-0.1"));
+        assert_value!("1_000.000_1d", dec("1000.0001"));
+        assert!(!matches!(value("12d"), Ok(("", _))));
+        assert!(!matches!(value("1.5days"), Ok(("", Value::Decimal(_)))));
+    }
+    #[test]
+    fn test_duration() {
+        assert_value!("500ms", Value::Duration(500_000_000));
+        assert_value!("30s", Value::Duration(30_000_000_000));
+        assert_value!("1h30m", Value::Duration(5_400_000_000_000));
+        assert_value!("1.5s", Value::Duration(1_500_000_000));
+        assert_value!("10us", Value::Duration(10_000));
+        assert!(!matches!(value("5min"), Ok(("", _))));
+        assert!(!matches!(value("0.5ns"), Ok(("", _))));
+    }
+    #[test]
+    fn test_bytes() {
+        assert_value!("512KiB", Value::Bytes(512 * 1024));
+        assert_value!("2GB", Value::Bytes(2_000_000_000));
+        assert_value!("1.5KB", Value::Bytes(1500));
+        assert_value!("64B", Value::Bytes(64));
+        assert!(!matches!(value("1.5B"), Ok(("", _))));
+        assert!(!matches!(value("2Gb"), Ok(("", _))));
+    }
+    #[test]
+    fn test_datetime() {
+        use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+        assert_value!(
+            "2024-02-29",
+            Value::Date(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap())
+        );
+        assert_value!(
+            "09:30",
+            Value::Time(NaiveTime::from_hms_opt(9, 30, 0).unwrap())
+        );
+        assert_value!(
+            "09:30:15.250",
+            Value::Time(NaiveTime::from_hms_milli_opt(9, 30, 15, 250).unwrap())
+        );
+        assert_value!(
+            "2024-03-01T09:30:00Z",
+            Value::DateTime(Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap().into())
+        );
+        assert!(matches!(
+            value("2024-03-01T09:30:00+09:00"),
+            Ok(("", Value::DateTime(_)))
+        ));
+        assert!(matches!(value("2024-02-30"), Err(nom::Err::Failure(_))));
+        assert!(matches!(value("2024-13-01"), Err(nom::Err::Failure(_))));
+        assert!(matches!(
+            value("2024-02-28T25:00:00Z"),
+            Err(nom::Err::Failure(_))
+        ));
+        assert!(!matches!(value("25:00"), Ok(("", _))));
+        assert!(!matches!(value("2024-03-01T09:30:00"), Ok(("", _))));
+    }
+    #[test]
+    fn test_radix() {
+        assert_value!("0xFF", Value::Nat(255));
+        assert_value!("0xdead_beef", Value::Nat(0xdead_beef));
+        assert_value!("0o755", Value::Nat(0o755));
+        assert_value!("0b1010_0101", Value::Nat(0b1010_0101));
+        assert_value!("-0x10", Value::Int(-16));
+        assert!(!matches!(value("0x"), Ok(("", _))));
+        assert!(!matches!(
+            value("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"),
+            Ok(("", _))
+        ));
     }
     #[test]
     fn test_const() {
@@ -259,6 +975,48 @@ mod test_value {
         );
     }
     #[test]
+    fn test_str_escape() {
+        assert_value!(
+            "\"\\u{48}\\u{3b1}\\u{1F600}\"",
+            Value::Str("H\u{3b1}\u{1F600}".to_string())
+        );
+        assert_value!("\"\\x41\\x7e\"", Value::Str("A~".to_string()));
+        assert!(value("\"\\x80\"").is_err());
+        assert!(value("\"\\u{110000}\"").is_err());
+        assert!(value("\"\\q\"").is_err());
+    }
+    #[test]
+    fn test_raw_str() {
+        assert_value!("r\"\"", Value::Str("".to_string()));
+        assert_value!("r\"C:\\path\\n\"", Value::Str("C:\\path\\n".to_string()));
+        assert_value!("r#\"say \"hi\"\"#", Value::Str("say \"hi\"".to_string()));
+        assert_value!("r##\"a\"#b\"##", Value::Str("a\"#b".to_string()));
+        assert!(value("r#\"unterminated\"").is_err());
+    }
+    #[test]
+    fn test_multiline_str() {
+        assert_value!("\"\"\"\"\"\"", Value::Str("".to_string()));
+        assert_value!("\"\"\"one line\"\"\"", Value::Str("one line".to_string()));
+        assert_value!(
+            "\"\"\"
+                #!/bin/sh
+                if true; then
+                    echo \"ok\"\\t!
+                fi
+                \"\"\"",
+            Value::Str("#!/bin/sh\nif true; then\n    echo \"ok\"\t!\nfi\n".to_string())
+        );
+        assert_value!(
+            "\"\"\"
+              a
+
+                b
+              \\\"\"\"
+            \"\"\"",
+            Value::Str("a\n\n  b\n\"\"\"\n".to_string())
+        );
+    }
+    #[test]
     fn test_enum() {
         assert_value!(
             "X::Zoo",
@@ -327,6 +1085,12 @@ mod test_value {
         assert_coerce!(Str("0".to_string()), Typing::Int, Int(0));
         assert_coerce!(Str("true".to_string()), Typing::Bool, Bool(true));
         assert_coerce!(Str("false".to_string()), Typing::Bool, Bool(false));
+        assert_coerce!(Bool(true), Typing::String, Str("true".to_string()));
+        assert_coerce!(
+            EnumVariant("R".to_string(), "East".to_string()),
+            Typing::String,
+            Str("East".to_string())
+        );
     }
 
     macro_rules! assert_type_of {
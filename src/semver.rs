@@ -0,0 +1,381 @@
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A Semantic Versioning 2.0.0 version, `MAJOR.MINOR.PATCH[-PRE][+BUILD]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemVer {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<Identifier>,
+    pub build: Vec<String>,
+}
+
+/// A dot-separated part of a pre-release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl SemVer {
+    pub fn parse(s: &str) -> Result<Self> {
+        let partial = Partial::parse(s)?;
+        match (partial.minor, partial.patch) {
+            (Some(minor), Some(patch)) => Ok(SemVer {
+                major: partial.major.unwrap_or(0),
+                minor,
+                patch,
+                pre: partial.pre,
+                build: partial.build,
+            }),
+            _ if partial.major.is_none() => bail!("wildcard in a version"),
+            _ => bail!("expected MAJOR.MINOR.PATCH"),
+        }
+    }
+
+    /// Precedence: build metadata is ignored, and a pre-release comes before its release.
+    pub fn precedence(&self, other: &SemVer) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => cmp_pre(&self.pre, &other.pre),
+            })
+    }
+
+    fn release(major: u64, minor: u64, patch: u64) -> Self {
+        SemVer {
+            major,
+            minor,
+            patch,
+            pre: vec![],
+            build: vec![],
+        }
+    }
+}
+
+fn cmp_pre(xs: &[Identifier], ys: &[Identifier]) -> Ordering {
+    for (x, y) in xs.iter().zip(ys.iter()) {
+        let ord = match (x, y) {
+            (Identifier::Numeric(x), Identifier::Numeric(y)) => x.cmp(y),
+            (Identifier::Numeric(_), Identifier::Alpha(_)) => Ordering::Less,
+            (Identifier::Alpha(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::Alpha(x), Identifier::Alpha(y)) => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    xs.len().cmp(&ys.len())
+}
+
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            let pre: Vec<String> = self.pre.iter().map(|id| id.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identifier::Numeric(n) => write!(f, "{}", n),
+            Identifier::Alpha(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A version in a range, where trailing parts may be missing or `*`, `x`, `X`.
+struct Partial {
+    major: Option<u64>,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<Identifier>,
+    build: Vec<String>,
+}
+
+impl Partial {
+    fn parse(s: &str) -> Result<Self> {
+        let (s, build) = match s.split_once('+') {
+            Some((s, build)) => (s, identifiers(build)?),
+            None => (s, vec![]),
+        };
+        let (s, pre) = match s.split_once('-') {
+            Some((s, pre)) => (s, pre_release(pre)?),
+            None => (s, vec![]),
+        };
+        let mut parts = s.split('.').map(number);
+        let major = parts.next().unwrap_or(Ok(None))?;
+        let minor = parts.next().unwrap_or(Ok(None))?;
+        let patch = parts.next().unwrap_or(Ok(None))?;
+        if parts.next().is_some() {
+            bail!("more than three numbers in {:?}", s);
+        }
+        if (major.is_none() && minor.is_some()) || (minor.is_none() && patch.is_some()) {
+            bail!("a number after a wildcard in {:?}", s);
+        }
+        if patch.is_none() && !pre.is_empty() {
+            bail!("a pre-release needs MAJOR.MINOR.PATCH");
+        }
+        Ok(Partial {
+            major,
+            minor,
+            patch,
+            pre,
+            build,
+        })
+    }
+
+    /// The least version matching the partial one.
+    fn floor(&self) -> SemVer {
+        SemVer {
+            major: self.major.unwrap_or(0),
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+            build: vec![],
+        }
+    }
+
+    /// The least release above the versions matching the partial one up to its `n`th number;
+    /// `None` when there is none.
+    fn bump(&self, n: usize) -> Option<SemVer> {
+        let major = self.major?;
+        let minor = self.minor.unwrap_or(0);
+        match n {
+            0 => Some(SemVer::release(major.checked_add(1)?, 0, 0)),
+            1 => Some(SemVer::release(major, minor.checked_add(1)?, 0)),
+            _ => Some(SemVer::release(
+                major,
+                minor,
+                self.patch.unwrap_or(0).checked_add(1)?,
+            )),
+        }
+    }
+
+    /// How many of major, minor and patch are given.
+    fn given(&self) -> usize {
+        [self.major, self.minor, self.patch]
+            .iter()
+            .take_while(|x| x.is_some())
+            .count()
+    }
+}
+
+fn number(s: &str) -> Result<Option<u64>> {
+    match s {
+        "*" | "x" | "X" => Ok(None),
+        _ if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) => {
+            bail!("{:?} is not a number", s)
+        }
+        _ if s.len() > 1 && s.starts_with('0') => bail!("{:?} has a leading zero", s),
+        _ => Ok(Some(s.parse()?)),
+    }
+}
+
+fn identifiers(s: &str) -> Result<Vec<String>> {
+    s.split('.')
+        .map(|id| {
+            if id.is_empty() || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+                bail!("{:?} is not an identifier", id);
+            }
+            Ok(id.to_string())
+        })
+        .collect()
+}
+
+fn pre_release(s: &str) -> Result<Vec<Identifier>> {
+    identifiers(s)?
+        .into_iter()
+        .map(|id| {
+            if !id.bytes().all(|b| b.is_ascii_digit()) {
+                Ok(Identifier::Alpha(id))
+            } else if id.len() > 1 && id.starts_with('0') {
+                bail!("{:?} has a leading zero", id)
+            } else {
+                Ok(Identifier::Numeric(id.parse()?))
+            }
+        })
+        .collect()
+}
+
+/// A bound of a comparator: the version, and whether it matches itself.
+type Bound = Option<(SemVer, bool)>;
+
+/// `lower <= v <= upper`, each bound optional and possibly exclusive.
+struct Comparator {
+    lower: Bound,
+    upper: Bound,
+}
+
+impl Comparator {
+    fn parse(s: &str) -> Result<Self> {
+        let (op, version) = match s.find(|c: char| c.is_ascii_alphanumeric() || c == '*') {
+            Some(i) => s.split_at(i),
+            None => bail!("missing version in {:?}", s),
+        };
+        let v = Partial::parse(version)?;
+        let given = v.given();
+        let exact = given == 3;
+        let at = |ver: SemVer, inclusive: bool| Some((ver, inclusive));
+        let (lower, upper) = match op {
+            _ if given == 0 => (None, None),
+            "" | "=" if exact => (at(v.floor(), true), at(v.floor(), true)),
+            "" | "=" => (at(v.floor(), true), v.bump(given - 1).map(|u| (u, false))),
+            ">=" => (at(v.floor(), true), None),
+            ">" if exact => (at(v.floor(), false), None),
+            ">" => (v.bump(given - 1).map(|l| (l, true)), None),
+            "<" => (None, at(v.floor(), false)),
+            "<=" if exact => (None, at(v.floor(), true)),
+            "<=" => (None, v.bump(given - 1).map(|u| (u, false))),
+            "~" => (
+                at(v.floor(), true),
+                v.bump(given.min(2) - 1).map(|u| (u, false)),
+            ),
+            "^" => {
+                let first_nonzero = [v.major, v.minor, v.patch]
+                    .iter()
+                    .take(given)
+                    .position(|x| *x != Some(0))
+                    .unwrap_or(given - 1);
+                (
+                    at(v.floor(), true),
+                    v.bump(first_nonzero).map(|u| (u, false)),
+                )
+            }
+            _ => bail!("unknown operator {:?}", op),
+        };
+        Ok(Comparator { lower, upper })
+    }
+
+    fn matches(&self, v: &SemVer) -> bool {
+        let above = match &self.lower {
+            Some((l, inclusive)) => match v.precedence(l) {
+                Ordering::Greater => true,
+                Ordering::Equal => *inclusive,
+                Ordering::Less => false,
+            },
+            None => true,
+        };
+        let below = match &self.upper {
+            Some((u, inclusive)) => match v.precedence(u) {
+                Ordering::Less => true,
+                Ordering::Equal => *inclusive,
+                Ordering::Greater => false,
+            },
+            None => true,
+        };
+        above && below
+    }
+
+    /// Whether a bound is a pre-release of the same `MAJOR.MINOR.PATCH` as `v`.
+    fn admits_pre(&self, v: &SemVer) -> bool {
+        [&self.lower, &self.upper].iter().any(|bound| match bound {
+            Some((b, _)) => {
+                !b.pre.is_empty() && (b.major, b.minor, b.patch) == (v.major, v.minor, v.patch)
+            }
+            None => false,
+        })
+    }
+}
+
+/// Whether `v` is in `range`: alternatives separated by `||`, each a list of comparators
+/// (`^1.2`, `~1.2.3`, `>=1.0.0, <2`, `1.x`, ...) that must all hold.
+/// A pre-release matches only when some comparator names a pre-release of the same version.
+pub fn satisfies(v: &SemVer, range: &str) -> Result<bool> {
+    for alternative in range.split("||") {
+        let comparators = alternative
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(Comparator::parse)
+            .collect::<Result<Vec<_>>>()?;
+        let pre_ok = v.pre.is_empty() || comparators.iter().any(|c| c.admits_pre(v));
+        if pre_ok && comparators.iter().all(|c| c.matches(v)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test_semver {
+    use crate::semver::*;
+
+    fn v(s: &str) -> SemVer {
+        SemVer::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(v("1.2.3").to_string(), "1.2.3");
+        assert_eq!(v("1.0.0-rc.1+build.5").to_string(), "1.0.0-rc.1+build.5");
+        assert!(SemVer::parse("1.2").is_err());
+        assert!(SemVer::parse("01.2.3").is_err());
+        assert!(SemVer::parse("1.2.3-01").is_err());
+        assert!(SemVer::parse("1.2.3-").is_err());
+        assert!(SemVer::parse("1.2.x").is_err());
+        assert!(SemVer::parse("v1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let order = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.2.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in order.windows(2) {
+            assert_eq!(v(pair[0]).precedence(&v(pair[1])), Ordering::Less);
+        }
+        assert_eq!(v("1.0.0+a").precedence(&v("1.0.0+b")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_satisfies() {
+        let ok = |ver: &str, range: &str| satisfies(&v(ver), range).unwrap();
+        assert!(ok("1.4.2", "^1.2"));
+        assert!(!ok("2.0.0", "^1.2"));
+        assert!(!ok("1.1.9", "^1.2"));
+        assert!(ok("0.2.5", "^0.2"));
+        assert!(!ok("0.3.0", "^0.2"));
+        assert!(!ok("0.0.4", "^0.0.3"));
+        assert!(ok("1.2.9", "~1.2.3"));
+        assert!(!ok("1.3.0", "~1.2.3"));
+        assert!(ok("1.9.0", "~1"));
+        assert!(ok("1.5.0", ">=1.4.0, <2"));
+        assert!(!ok("2.0.0", ">=1.4.0 <2"));
+        assert!(ok("1.3.0", ">1.2"));
+        assert!(!ok("1.2.9", ">1.2"));
+        assert!(ok("1.2.9", "<=1.2"));
+        assert!(ok("1.2.7", "1.2.x"));
+        assert!(ok("3.0.0", "^1 || ^3"));
+        assert!(ok("9.9.9", "*"));
+        assert!(ok("1.2.3", "=1.2.3"));
+        assert!(!ok("2.0.0-alpha", "^1.2"));
+        assert!(!ok("1.3.0-rc.1", "^1.2"));
+        assert!(ok("1.3.0-rc.2", ">=1.3.0-rc.1"));
+        assert!(ok("18446744073709551615.0.0", "^18446744073709551615"));
+        assert!(satisfies(&v("1.0.0"), "^").is_err());
+        assert!(satisfies(&v("1.0.0"), "!1.0").is_err());
+        assert!(satisfies(&v("1.0.0"), "^1.a").is_err());
+    }
+}
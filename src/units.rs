@@ -0,0 +1,127 @@
+use anyhow::Result;
+
+/// Duration units, largest first, counted in nanoseconds.
+pub const DURATION_UNITS: &[(&str, u128)] = &[
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+/// Byte-size units, largest first, counted in bytes.
+pub const BYTES_UNITS: &[(&str, u128)] = &[
+    ("PiB", 1 << 50),
+    ("PB", 1_000_000_000_000_000),
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+    ("B", 1),
+];
+
+pub fn unit_factor(units: &[(&str, u128)], name: &str) -> Option<u128> {
+    units
+        .iter()
+        .find(|(unit, _)| *unit == name)
+        .map(|(_, factor)| *factor)
+}
+
+/// `1h30m`, `500ms`; zero is `0s`.
+pub fn humanize_duration(nanos: u128) -> String {
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+    let mut rest = nanos;
+    let mut s = String::new();
+    for (unit, factor) in DURATION_UNITS {
+        if rest >= *factor {
+            s.push_str(&format!("{}{}", rest / factor, unit));
+            rest %= factor;
+        }
+    }
+    s
+}
+
+/// The largest unit that measures the size exactly: `2GB`, `512KiB`.
+pub fn humanize_bytes(bytes: u128) -> String {
+    if bytes == 0 {
+        return "0B".to_string();
+    }
+    let (unit, factor) = BYTES_UNITS
+        .iter()
+        .filter(|(_, factor)| bytes.is_multiple_of(*factor))
+        .max_by_key(|(_, factor)| *factor)
+        .unwrap_or(&("B", 1));
+    format!("{}{}", bytes / factor, unit)
+}
+
+/// How Duration or Bytes values are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitOutput {
+    /// A string such as `"1h30m"` or `"512KiB"`.
+    Human,
+    /// A number counted in a unit of the given size.
+    Number(u128),
+}
+
+impl UnitOutput {
+    /// `human`, or the name of a unit in `units`.
+    pub fn parse(s: &str, units: &[(&str, u128)]) -> Result<Self> {
+        if s == "human" {
+            return Ok(UnitOutput::Human);
+        }
+        match unit_factor(units, s) {
+            Some(factor) => Ok(UnitOutput::Number(factor)),
+            None => bail!(
+                "Unknown unit: {} (expected human or one of {})",
+                s,
+                units
+                    .iter()
+                    .map(|(unit, _)| *unit)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_units {
+    use crate::units::*;
+
+    #[test]
+    fn test_humanize() {
+        assert_eq!(humanize_duration(0), "0s");
+        assert_eq!(humanize_duration(500_000_000), "500ms");
+        assert_eq!(humanize_duration(5_400_000_000_000), "1h30m");
+        assert_eq!(humanize_duration(90_500_000_000), "1m30s500ms");
+        assert_eq!(humanize_bytes(0), "0B");
+        assert_eq!(humanize_bytes(512 * 1024), "512KiB");
+        assert_eq!(humanize_bytes(2_000_000_000), "2GB");
+        assert_eq!(humanize_bytes(1_024_000), "1000KiB");
+        assert_eq!(humanize_bytes(1023), "1023B");
+    }
+
+    #[test]
+    fn test_unit_output() {
+        assert_eq!(
+            UnitOutput::parse("human", DURATION_UNITS).unwrap(),
+            UnitOutput::Human
+        );
+        assert_eq!(
+            UnitOutput::parse("ms", DURATION_UNITS).unwrap(),
+            UnitOutput::Number(1_000_000)
+        );
+        assert_eq!(
+            UnitOutput::parse("MiB", BYTES_UNITS).unwrap(),
+            UnitOutput::Number(1 << 20)
+        );
+        assert!(UnitOutput::parse("MiB", DURATION_UNITS).is_err());
+    }
+}